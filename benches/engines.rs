@@ -0,0 +1,43 @@
+//! Runs `test.urcl` on every engine and prints how many URCL instructions each gets through per
+//! second. Run with `cargo bench`.
+
+use urcl_io::compiler::{
+    frontend::{lexer::*, ast::*, parser::*},
+    backend::opt::*,
+    engine::*,
+};
+use std::time::Instant;
+
+const SRC: &str = include_str!("../test.urcl");
+const RUNS: usize = 5;
+
+fn main() {
+    for (kind, level) in [
+        (EngineKind::Ast, OptLevel::O0),
+        (EngineKind::Ssa, OptLevel::O0),
+        (EngineKind::Ssa, OptLevel::O2),
+        (EngineKind::Vm, OptLevel::O0),
+        (EngineKind::Vm, OptLevel::O2),
+        (EngineKind::Threaded, OptLevel::O0),
+        (EngineKind::Threaded, OptLevel::O2),
+        (EngineKind::Jit, OptLevel::O0),
+        (EngineKind::Jit, OptLevel::O2),
+    ] {
+        let mut best = 0.0f64;
+        for _ in 0..RUNS {
+            let mut lex = Token::lexer(SRC);
+            let mut parser = Parser::new(&mut lex).ok().unwrap();
+            parse(&mut parser).ok().unwrap();
+
+            let mut engine = create_engine(kind, &mut PassManager::new(level), parser.ast);
+            let start = Instant::now();
+            let step = engine.run(usize::MAX);
+            let duration = start.elapsed().as_secs_f64();
+
+            assert!(matches!(step, StepResult::Halted), "{kind:?} didn't halt: {step:?}");
+            best = best.max(engine.stats().inst_count as f64 / duration);
+        }
+
+        println!("{:<12} {:>8.2} MHz", format!("{kind:?} {level:?}"), best / 1e6);
+    }
+}
@@ -27,10 +27,64 @@ document.addEventListener("DOMContentLoaded", (_) => {
     }
 
 	document.getElementById("test").onclick = (_) => {
-		urcl_io.test(input.value);
+		document.getElementById("console").innerText = urcl_io.test(input.value, "vm", document.getElementById("opt").value);
+	};
+
+	document.getElementById("run").onclick = async (_) => {
+		document.getElementById("console").innerText = await run_compiled(input.value, document.getElementById("opt").value);
 	};
 });
 
+// compiles the program into its own wasm module and runs that, with %TEXT and %NUMBER going to
+// the console like in `test`
+async function run_compiled(src, opt) {
+    let module;
+    try {
+        module = urcl_io.compile(src, opt);
+    } catch (err) {
+        return err;
+    }
+
+    let out = "";
+    let ok = 1;
+    let error = "";
+    const env = {
+        port_read(port) {
+            ok = port == 1n ? 1 : 0;
+            return 0n;
+        },
+        port_write(port, data) {
+            ok = 1;
+            data = BigInt.asUintN(64, data);
+            if (port == 1n) {
+                const valid = data < 0x110000n && !(data >= 0xd800n && data < 0xe000n);
+                out += String.fromCodePoint(valid ? Number(data) : 0xfffd);
+            } else if (port == 2n) {
+                out += data.toString();
+            } else {
+                ok = 0;
+            }
+        },
+        last_ok() {
+            return ok;
+        },
+        report_error(kind, pc, detail) {
+            error = urcl_io.describe_error(src, BigInt.asUintN(64, kind), pc, BigInt.asUintN(64, detail));
+        },
+    };
+
+    const { instance } = await WebAssembly.instantiate(module, { env });
+    if (instance.exports.main() != 0) {
+        out += "\n" + error;
+    }
+
+    return out;
+}
+
+export function now() {
+    return performance.now() / 1000;
+}
+
 init().then(() => {
     urcl_io.init_panic_hook();
 });
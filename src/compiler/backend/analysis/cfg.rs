@@ -0,0 +1,58 @@
+use crate::compiler::backend::ssa::*;
+
+/// Edges of the control-flow graph, indexed by block.
+#[derive(Debug, Clone)]
+pub struct Cfg {
+    pub successors: Vec<Vec<BlockId>>,
+    /// Predecessors of every block, a block branching to the same block twice is listed once
+    pub predecessors: Vec<Vec<BlockId>>,
+}
+
+impl Cfg {
+    pub fn new(body: &Body) -> Self {
+        let successors: Vec<Vec<BlockId>> = body.blocks.iter()
+            .map(|blk| blk.terminator.successors())
+            .collect();
+
+        let mut predecessors = vec![Vec::new(); body.blocks.len()];
+        for (i, succs) in successors.iter().enumerate() {
+            for s in succs {
+                if !predecessors[**s].contains(&BlockId(i)) {
+                    predecessors[**s].push(BlockId(i));
+                }
+            }
+        }
+
+        Self { successors, predecessors }
+    }
+
+    /// Blocks reachable from the entry block in reverse postorder, so that every block comes
+    /// before its successors unless the edge is a back edge.
+    pub fn reverse_postorder(&self) -> Vec<BlockId> {
+        let mut visited = vec![false; self.successors.len()];
+        let mut order = Vec::with_capacity(self.successors.len());
+        let mut stack = Vec::new();
+
+        if self.successors.is_empty() {
+            return order;
+        }
+
+        visited[0] = true;
+        stack.push((BlockId(0), 0));
+        while let Some((blk, next)) = stack.last_mut() {
+            if let Some(succ) = self.successors[**blk].get(*next).copied() {
+                *next += 1;
+                if !visited[*succ] {
+                    visited[*succ] = true;
+                    stack.push((succ, 0));
+                }
+            } else {
+                order.push(*blk);
+                stack.pop();
+            }
+        }
+
+        order.reverse();
+        order
+    }
+}
@@ -0,0 +1,118 @@
+use crate::compiler::backend::{ssa::*, analysis::cfg::*};
+
+/// Dominator tree of the reachable blocks, built with the algorithm of Cooper, Harvey and
+/// Kennedy.
+#[derive(Debug, Clone)]
+pub struct Dominators {
+    /// Immediate dominator of every block, `None` for the entry block and unreachable blocks
+    pub idom: Vec<Option<BlockId>>,
+    /// Blocks immediately dominated by every block
+    pub children: Vec<Vec<BlockId>>,
+    /// Position of every block in reverse postorder, `usize::MAX` if unreachable
+    order: Vec<usize>,
+}
+
+impl Dominators {
+    pub fn new(cfg: &Cfg) -> Self {
+        let rpo = cfg.reverse_postorder();
+        let mut order = vec![usize::MAX; cfg.successors.len()];
+        for (i, b) in rpo.iter().enumerate() {
+            order[**b] = i;
+        }
+
+        let mut idom = vec![None; cfg.successors.len()];
+        if let Some(entry) = rpo.first() {
+            idom[**entry] = Some(*entry);
+        }
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for b in rpo.iter().skip(1) {
+                let mut preds = cfg.predecessors[**b].iter().filter(|p| idom[***p].is_some());
+                let Some(mut new) = preds.next().copied() else { continue };
+                for p in preds {
+                    let mut f = *p;
+                    while f != new {
+                        while order[*f] > order[*new] {
+                            f = idom[*f].unwrap();
+                        }
+
+                        while order[*new] > order[*f] {
+                            new = idom[*new].unwrap();
+                        }
+                    }
+                }
+
+                if idom[**b] != Some(new) {
+                    idom[**b] = Some(new);
+                    changed = true;
+                }
+            }
+        }
+
+        if let Some(entry) = rpo.first() {
+            idom[**entry] = None;
+        }
+
+        let mut children = vec![Vec::new(); idom.len()];
+        for b in rpo.iter() {
+            if let Some(d) = idom[**b] {
+                children[*d].push(*b);
+            }
+        }
+
+        Self { idom, children, order }
+    }
+
+    pub fn is_reachable(&self, b: BlockId) -> bool {
+        self.order[*b] != usize::MAX
+    }
+
+    /// Whether every path from the entry block to `b` goes through `a`, which includes `a == b`
+    pub fn dominates(&self, a: BlockId, mut b: BlockId) -> bool {
+        if !self.is_reachable(a) || !self.is_reachable(b) {
+            return false;
+        }
+
+        while self.order[*b] > self.order[*a] {
+            b = self.idom[*b].unwrap();
+        }
+
+        a == b
+    }
+}
+
+/// Dominance frontier of every block, the blocks where its dominance ends
+#[derive(Debug, Clone)]
+pub struct DominanceFrontiers {
+    pub frontiers: Vec<Vec<BlockId>>,
+}
+
+impl DominanceFrontiers {
+    pub fn new(cfg: &Cfg, doms: &Dominators) -> Self {
+        let mut frontiers = vec![Vec::new(); cfg.successors.len()];
+        for (b, preds) in cfg.predecessors.iter().enumerate() {
+            let b = BlockId(b);
+            if preds.len() < 2 || !doms.is_reachable(b) {
+                continue;
+            }
+
+            for p in preds.iter().filter(|p| doms.is_reachable(**p)) {
+                let mut runner = *p;
+                while Some(runner) != doms.idom[*b] {
+                    if !frontiers[*runner].contains(&b) {
+                        frontiers[*runner].push(b);
+                    }
+
+                    match doms.idom[*runner] {
+                        Some(d) => runner = d,
+                        None => break,
+                    }
+                }
+            }
+        }
+
+        Self { frontiers }
+    }
+}
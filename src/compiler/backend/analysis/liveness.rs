@@ -0,0 +1,85 @@
+use crate::compiler::backend::{ssa::*, analysis::cfg::*};
+use std::collections::HashSet;
+
+/// Values live at the entry and exit of every block.
+///
+/// Phi nodes are treated as reading their input at the end of the predecessor it comes from,
+/// and as defining their result at the start of their block.
+#[derive(Debug, Clone)]
+pub struct Liveness {
+    pub live_in: Vec<HashSet<ValueId>>,
+    pub live_out: Vec<HashSet<ValueId>>,
+}
+
+impl Liveness {
+    pub fn new(body: &Body, cfg: &Cfg) -> Self {
+        let n = body.blocks.len();
+        let mut uses = vec![HashSet::new(); n];
+        let mut defs = vec![HashSet::new(); n];
+        // values read by the Phi nodes of a successor, by predecessor
+        let mut phi_uses = vec![HashSet::new(); n];
+
+        for blk in body.blocks.iter() {
+            let b = *blk.id;
+            for instr in blk.instructions.iter() {
+                match &instr.operation {
+                    Operation::Phi(branches) => for (v, p) in branches.iter() {
+                        phi_uses[**p].insert(*v);
+                    },
+                    op => for v in op.operands() {
+                        if !defs[b].contains(&v) {
+                            uses[b].insert(v);
+                        }
+                    },
+                }
+
+                if let Some(dst) = instr.destination {
+                    defs[b].insert(dst);
+                }
+            }
+
+            if let Terminator::Branch(cond, _, _) = blk.terminator {
+                if !defs[b].contains(&cond) {
+                    uses[b].insert(cond);
+                }
+            }
+        }
+
+        let mut live_in = uses.clone();
+        let mut live_out = phi_uses;
+
+        let mut order = cfg.reverse_postorder();
+        order.reverse();
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for b in order.iter() {
+                let mut out = live_out[**b].clone();
+                for s in cfg.successors[**b].iter() {
+                    out.extend(live_in[**s].iter().copied());
+                }
+
+                let mut in_ = uses[**b].clone();
+                in_.extend(out.difference(&defs[**b]).copied());
+
+                if out.len() != live_out[**b].len() || in_.len() != live_in[**b].len() {
+                    changed = true;
+                }
+
+                live_out[**b] = out;
+                live_in[**b] = in_;
+            }
+        }
+
+        Self { live_in, live_out }
+    }
+
+    pub fn is_live_in(&self, b: BlockId, v: ValueId) -> bool {
+        self.live_in[*b].contains(&v)
+    }
+
+    pub fn is_live_out(&self, b: BlockId, v: ValueId) -> bool {
+        self.live_out[*b].contains(&v)
+    }
+}
@@ -0,0 +1,87 @@
+use crate::compiler::backend::{ssa::*, analysis::{cfg::*, dominators::*}};
+
+/// A natural loop, made out of every back edge going to the same header
+#[derive(Debug, Clone)]
+pub struct Loop {
+    pub header: BlockId,
+    /// Every block in the loop including the header, sorted
+    pub blocks: Vec<BlockId>,
+    /// Index of the innermost loop containing this one
+    pub parent: Option<usize>,
+    /// 1 for outermost loops
+    pub depth: usize,
+}
+
+impl Loop {
+    pub fn contains(&self, b: BlockId) -> bool {
+        self.blocks.binary_search_by_key(&*b, |b| **b).is_ok()
+    }
+}
+
+/// Every natural loop of a body and how they nest
+#[derive(Debug, Clone)]
+pub struct LoopNest {
+    pub loops: Vec<Loop>,
+    /// Index of the innermost loop every block is in
+    pub innermost: Vec<Option<usize>>,
+}
+
+impl LoopNest {
+    pub fn new(cfg: &Cfg, doms: &Dominators) -> Self {
+        let mut loops: Vec<Loop> = Vec::new();
+
+        for (t, succs) in cfg.successors.iter().enumerate() {
+            let tail = BlockId(t);
+            for header in succs.iter().copied().filter(|h| doms.dominates(*h, tail)) {
+                // everything that reaches the tail without going through the header
+                let mut seen = vec![false; cfg.successors.len()];
+                seen[*header] = true;
+                let mut blocks = vec![header];
+                let mut stack = vec![tail];
+                while let Some(b) = stack.pop() {
+                    if seen[*b] {
+                        continue;
+                    }
+
+                    seen[*b] = true;
+                    blocks.push(b);
+                    stack.extend(cfg.predecessors[*b].iter().filter(|p| doms.is_reachable(**p)));
+                }
+
+                match loops.iter_mut().find(|l| l.header == header) {
+                    Some(l) => {
+                        l.blocks.extend(blocks);
+                        l.blocks.sort_unstable_by_key(|b| **b);
+                        l.blocks.dedup();
+                    },
+                    None => {
+                        blocks.sort_unstable_by_key(|b| **b);
+                        loops.push(Loop { header, blocks, parent: None, depth: 1 });
+                    },
+                }
+            }
+        }
+
+        // outer loops first, so that parents come before their children
+        loops.sort_by_key(|l| std::cmp::Reverse(l.blocks.len()));
+        for i in 0..loops.len() {
+            let parent = (0..i).rev().find(|j| loops[*j].contains(loops[i].header) && loops[*j].header != loops[i].header);
+            loops[i].parent = parent;
+            loops[i].depth = parent.map_or(1, |p| loops[p].depth + 1);
+        }
+
+        let mut innermost = vec![None; cfg.successors.len()];
+        for (i, l) in loops.iter().enumerate() {
+            for b in l.blocks.iter() {
+                innermost[**b] = Some(i);
+            }
+        }
+
+        Self { loops, innermost }
+    }
+
+    /// How many loops `b` is in
+    pub fn depth(&self, b: BlockId) -> usize {
+        self.innermost[*b].map_or(0, |l| self.loops[l].depth)
+    }
+}
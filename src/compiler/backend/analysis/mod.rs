@@ -0,0 +1,65 @@
+pub mod cfg;
+pub mod dominators;
+pub mod loops;
+pub mod liveness;
+
+pub use cfg::*;
+pub use dominators::*;
+pub use loops::*;
+pub use liveness::*;
+
+use crate::compiler::backend::ssa::Body;
+use std::rc::Rc;
+
+/// Analyses of a body that are computed when first asked for, and thrown away as soon as the
+/// body changes. Kept by the [`Builder`](super::builder::Builder).
+#[derive(Default)]
+pub struct Analyses {
+    cfg: Option<Rc<Cfg>>,
+    dominators: Option<Rc<Dominators>>,
+    frontiers: Option<Rc<DominanceFrontiers>>,
+    loops: Option<Rc<LoopNest>>,
+    liveness: Option<Rc<Liveness>>,
+}
+
+impl Analyses {
+    pub fn invalidate(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn cfg(&mut self, body: &Body) -> Rc<Cfg> {
+        self.cfg.get_or_insert_with(|| Rc::new(Cfg::new(body))).clone()
+    }
+
+    pub fn dominators(&mut self, body: &Body) -> Rc<Dominators> {
+        if self.dominators.is_none() {
+            self.dominators = Some(Rc::new(Dominators::new(&self.cfg(body))));
+        }
+
+        self.dominators.clone().unwrap()
+    }
+
+    pub fn frontiers(&mut self, body: &Body) -> Rc<DominanceFrontiers> {
+        if self.frontiers.is_none() {
+            self.frontiers = Some(Rc::new(DominanceFrontiers::new(&self.cfg(body), &self.dominators(body))));
+        }
+
+        self.frontiers.clone().unwrap()
+    }
+
+    pub fn loops(&mut self, body: &Body) -> Rc<LoopNest> {
+        if self.loops.is_none() {
+            self.loops = Some(Rc::new(LoopNest::new(&self.cfg(body), &self.dominators(body))));
+        }
+
+        self.loops.clone().unwrap()
+    }
+
+    pub fn liveness(&mut self, body: &Body) -> Rc<Liveness> {
+        if self.liveness.is_none() {
+            self.liveness = Some(Rc::new(Liveness::new(body, &self.cfg(body))));
+        }
+
+        self.liveness.clone().unwrap()
+    }
+}
@@ -0,0 +1,326 @@
+use crate::compiler::{error::*, backend::{ssa::*, analysis::*, regalloc::{phi_copies, sequentialise}}};
+use std::{collections::{HashMap, HashSet}, fmt::Write};
+
+/// Runtime every program gets, where `BITS`, `MASK` and `SIGN` come before it
+const PRELUDE: &str = r#"static word last_ok = 1;
+
+static void fail(word kind, word detail) {
+    fflush(stdout);
+    if (kind < sizeof(messages) / sizeof(*messages)) {
+        fprintf(stderr, "Error: %s", messages[kind]);
+        if (has_detail[kind]) fprintf(stderr, "%llu", (unsigned long long) detail);
+    } else {
+        fprintf(stderr, "Error: %s%llu", unknown, (unsigned long long) kind);
+    }
+
+    fputc('\n', stderr);
+    exit(3);
+}
+
+static int is_negative(word v) { return (v & SIGN) != 0; }
+static word negate(word v) { return (~v + 1) & MASK; }
+static word abs_word(word v) { return is_negative(v) ? negate(v) : v; }
+
+static word sdiv(word l, word r) {
+    word q = abs_word(l) / abs_word(r);
+    return is_negative(l) != is_negative(r) ? negate(q) : q;
+}
+
+static word smod(word l, word r) {
+    word m = abs_word(l) % abs_word(r);
+    return is_negative(l) ? negate(m) : m;
+}
+
+static word sar(word l, word r) {
+    if (!is_negative(l)) return r >= 64 ? 0 : l >> r;
+    if (r >= BITS) return MASK;
+    return (l >> r) | (MASK & ~(MASK >> r));
+}
+
+/* %TEXT reads a byte, giving 0 at the end of the input */
+static word port_read(word port) {
+    int c;
+    last_ok = port == 1;
+    if (!last_ok) return 0;
+    c = getchar();
+    return c == EOF ? 0 : (word) c;
+}
+
+/* %TEXT writes a character as UTF-8, invalid ones turn into U+FFFD */
+static void put_char(word c) {
+    if (c >= 0x110000 || (c >= 0xd800 && c < 0xe000)) c = 0xfffd;
+    if (c < 0x80) {
+        putchar((int) c);
+    } else if (c < 0x800) {
+        putchar((int) (0xc0 | c >> 6));
+        putchar((int) (0x80 | (c & 0x3f)));
+    } else if (c < 0x10000) {
+        putchar((int) (0xe0 | c >> 12));
+        putchar((int) (0x80 | (c >> 6 & 0x3f)));
+        putchar((int) (0x80 | (c & 0x3f)));
+    } else {
+        putchar((int) (0xf0 | c >> 18));
+        putchar((int) (0x80 | (c >> 12 & 0x3f)));
+        putchar((int) (0x80 | (c >> 6 & 0x3f)));
+        putchar((int) (0x80 | (c & 0x3f)));
+    }
+}
+
+static void port_write(word port, word data) {
+    last_ok = port == 1 || port == 2;
+    if (port == 1) put_char(data);
+    else if (port == 2) printf("%llu", (unsigned long long) data);
+}
+"#;
+
+/// Quotes `s` as a C string literal
+fn quote(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' | '\\' => write!(quoted, "\\{c}").unwrap(),
+            ' '..='~' => quoted.push(c),
+            _ => write!(quoted, "\\{:03o}", c as u32 & 0xff).unwrap(),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+fn word(v: u128) -> String {
+    format!("UINT64_C({v})")
+}
+
+struct Translation<'a> {
+    body: &'a Body,
+    c: String,
+    /// Variables that are given a size other than a constant
+    dynamic: HashSet<VariableId>,
+    /// Values that are read somewhere, others don't get a C variable at all
+    used: HashSet<ValueId>,
+}
+
+impl Translation<'_> {
+    fn operation(&mut self, dst: Option<ValueId>, op: &Operation) {
+        let dst = dst.filter(|d| self.used.contains(d));
+        let assign = dst.map_or(String::new(), |d| format!("v{} = ", *d));
+        let v = |v: &ValueId| format!("v{}", **v);
+        let masked = |e: String| format!("({e}) & MASK");
+
+        let expr = match op {
+            Operation::Phi(_) => return,
+            Operation::Integer(_) if dst.is_none() => return,
+            Operation::Integer(int) => word(*int),
+            Operation::BinOp(op, l, r) => {
+                let (l, r) = (v(l), v(r));
+                if matches!(op, BinOp::Div | BinOp::Mod | BinOp::SDiv | BinOp::SMod) {
+                    writeln!(self.c, "    if (!{r}) fail({}, 0);", InterpreterError::DivisionByZero.code()).unwrap();
+                }
+
+                if dst.is_none() {
+                    return;
+                }
+
+                match op {
+                    BinOp::Add  => masked(format!("{l} + {r}")),
+                    BinOp::Sub  => masked(format!("{l} - {r}")),
+                    BinOp::Mul  => masked(format!("{l} * {r}")),
+                    BinOp::Div  => format!("{l} / {r}"),
+                    BinOp::Mod  => format!("{l} % {r}"),
+                    BinOp::SDiv => format!("sdiv({l}, {r})"),
+                    BinOp::SMod => format!("smod({l}, {r})"),
+                    BinOp::And  => format!("{l} & {r}"),
+                    BinOp::Or   => format!("{l} | {r}"),
+                    BinOp::Xor  => format!("{l} ^ {r}"),
+                    BinOp::Shl  => format!("{r} >= 64 ? 0 : ({l} << {r}) & MASK"),
+                    BinOp::Shr  => format!("{r} >= 64 ? 0 : {l} >> {r}"),
+                    BinOp::Sar  => format!("sar({l}, {r})"),
+                    BinOp::Eq   => format!("{l} == {r}"),
+                    BinOp::Ne   => format!("{l} != {r}"),
+                    BinOp::Lt   => format!("{l} < {r}"),
+                    BinOp::Le   => format!("{l} <= {r}"),
+                    BinOp::Gt   => format!("{l} > {r}"),
+                    BinOp::Ge   => format!("{l} >= {r}"),
+                    BinOp::SLt  => format!("({l} ^ SIGN) < ({r} ^ SIGN)"),
+                    BinOp::SLe  => format!("({l} ^ SIGN) <= ({r} ^ SIGN)"),
+                    BinOp::SGt  => format!("({l} ^ SIGN) > ({r} ^ SIGN)"),
+                    BinOp::SGe  => format!("({l} ^ SIGN) >= ({r} ^ SIGN)"),
+                }
+            },
+            Operation::Allocate(var, size) => {
+                let (var, size) = (**var, v(size));
+                if self.dynamic.contains(&VariableId(var)) {
+                    writeln!(self.c, "    free(m{var});").unwrap();
+                    writeln!(self.c, "    m{var} = calloc({size} ? {size} : 1, sizeof(word));").unwrap();
+                    writeln!(self.c, "    m{var}_len = m{var} ? {size} : 0;").unwrap();
+                } else {
+                    writeln!(self.c, "    m{var}_len = {size};").unwrap();
+                    writeln!(self.c, "    memset(m{var}, 0, sizeof(m{var}));").unwrap();
+                }
+
+                return;
+            },
+            Operation::LoadIndex(var, off) => {
+                let (var, off) = (**var, v(off));
+                writeln!(self.c, "    if ({off} >= m{var}_len) fail({}, {off});", InterpreterError::MemoryAccessOob(0).code()).unwrap();
+                if dst.is_none() {
+                    return;
+                }
+
+                format!("m{var}[{off}]")
+            },
+            Operation::StoreIndex(var, off, val) => {
+                let (var, off) = (**var, v(off));
+                writeln!(self.c, "    if ({off} >= m{var}_len) fail({}, {off});", InterpreterError::MemoryAccessOob(0).code()).unwrap();
+                writeln!(self.c, "    m{var}[{off}] = {};", v(val)).unwrap();
+                return;
+            },
+            Operation::Call(f, args) => match f {
+                Function::LastOk if dst.is_none() => return,
+                Function::LastOk => "last_ok".to_string(),
+                Function::PortRead => format!("port_read({})", v(&args[0])),
+                Function::PortWrite => format!("port_write({}, {})", v(&args[0]), v(&args[1])),
+                Function::ReportError => format!("fail({}, {})", v(&args[0]), v(&args[2])),
+            },
+        };
+
+        writeln!(self.c, "    {assign}{expr};").unwrap();
+    }
+
+    /// Copies the values every Phi node of `to` takes from `from` all at once, then jumps to `to`
+    fn goto(&mut self, from: BlockId, to: BlockId) -> String {
+        let copies: Vec<_> = phi_copies(self.body, from, to).into_iter()
+            .filter(|(dst, _)| self.used.contains(dst))
+            .map(|(dst, src)| (Some(dst), Some(src)))
+            .collect();
+
+        if copies.is_empty() {
+            return format!("goto b{};", *to);
+        }
+
+        // `None` is the temporary that breaks cycles
+        let name = |v: Option<ValueId>| v.map_or("t".to_string(), |v| format!("v{}", *v));
+        let moves = sequentialise(copies, None);
+
+        let mut s = String::from("{ ");
+        if moves.iter().any(|(dst, _)| dst.is_none()) {
+            s.push_str("word t; ");
+        }
+
+        for (dst, src) in moves {
+            write!(s, "{} = {}; ", name(dst), name(src)).unwrap();
+        }
+
+        write!(s, "goto b{}; }}", *to).unwrap();
+        s
+    }
+}
+
+/// Translates `ssa` into a C99 program, where the words are `uint64_t`s masked down to the word
+/// size. Ports go to stdin and stdout, and runtime errors exit with the code the CLI uses for
+/// them.
+///
+/// Variables that are always given a constant size, like the registers and the RAM sized by
+/// `@MINREG`, `@MINHEAP` and `@MINSTACK`, turn into static arrays.
+pub fn to_c(ssa: &(Body, usize, usize)) -> String {
+    let (body, values, variables) = ssa;
+    assert!(body.bits <= 64, "C code only goes up to 64-bit words");
+
+    let instructions = || body.blocks.iter().flat_map(|b| b.instructions.iter());
+    let constants: HashMap<_, _> = instructions()
+        .filter_map(|i| match (i.destination, &i.operation) {
+            (Some(d), Operation::Integer(int)) => Some((d, *int)),
+            _ => None,
+        })
+        .collect();
+
+    // the largest constant size every variable ever gets, unless it isn't always constant
+    let mut sizes = vec![Some(0); *variables];
+    for instr in instructions() {
+        if let Operation::Allocate(var, size) = &instr.operation {
+            sizes[**var] = sizes[**var].zip(constants.get(size)).map(|(a, b)| a.max(*b));
+        }
+    }
+
+    let mut t = Translation {
+        body,
+        c: String::new(),
+        dynamic: sizes.iter().enumerate().filter(|(_, s)| s.is_none()).map(|(v, _)| VariableId(v)).collect(),
+        used: body.blocks.iter()
+            .flat_map(|b| b.instructions.iter().flat_map(|i| i.operation.operands()).chain(match b.terminator {
+                Terminator::Branch(cond, ..) => Some(cond),
+                _ => None,
+            }))
+            .collect(),
+    };
+
+    writeln!(t.c, "/* generated by urcl-io */").unwrap();
+    for header in ["stdint.h", "stdio.h", "stdlib.h", "string.h"] {
+        writeln!(t.c, "#include <{header}>").unwrap();
+    }
+
+    let mask = u64::MAX >> (64 - body.bits);
+    writeln!(t.c, "\ntypedef uint64_t word;").unwrap();
+    writeln!(t.c, "#define BITS {}", body.bits).unwrap();
+    writeln!(t.c, "#define MASK {}", word(mask as u128)).unwrap();
+    writeln!(t.c, "#define SIGN {}\n", word(1 << (body.bits - 1))).unwrap();
+
+    let messages: Vec<_> = InterpreterError::CODES.map(InterpreterError::message_without_detail).collect();
+    let list: Vec<_> = messages.iter().map(|(m, _)| quote(m)).collect();
+    writeln!(t.c, "static const char *const messages[] = {{ {} }};", list.join(", ")).unwrap();
+    let list: Vec<_> = messages.iter().map(|(_, d)| (*d as u8).to_string()).collect();
+    writeln!(t.c, "static const int has_detail[] = {{ {} }};", list.join(", ")).unwrap();
+    writeln!(t.c, "static const char *const unknown = {};\n", quote(&InterpreterError::message_without_detail(u128::MAX).0)).unwrap();
+    t.c.push_str(PRELUDE);
+
+    writeln!(t.c).unwrap();
+    for (var, size) in sizes.iter().enumerate() {
+        match size {
+            Some(size) => writeln!(t.c, "static word m{var}[{}];", size.max(&1)).unwrap(),
+            None => writeln!(t.c, "static word *m{var};").unwrap(),
+        }
+
+        writeln!(t.c, "static word m{var}_len;").unwrap();
+    }
+
+    writeln!(t.c, "\nint main(void) {{").unwrap();
+    for v in 0..*values {
+        if t.used.contains(&ValueId(v)) {
+            writeln!(t.c, "    word v{v} = 0;").unwrap();
+        }
+    }
+
+    // only blocks that get jumped to need a label, unused ones would be warned about
+    let cfg = Cfg::new(body);
+    for blk in body.blocks.iter() {
+        if !cfg.predecessors[*blk.id].is_empty() {
+            writeln!(t.c, "b{}: /* {} */", *blk.id, blk.name).unwrap();
+        } else {
+            writeln!(t.c, "    /* {} */", blk.name).unwrap();
+        }
+
+        for instr in blk.instructions.iter() {
+            t.operation(instr.destination, &instr.operation);
+        }
+
+        let term = match blk.terminator {
+            Terminator::Jump(to) => t.goto(blk.id, to),
+            Terminator::Branch(cond, if_, else_) => {
+                let (if_, else_) = (t.goto(blk.id, if_), t.goto(blk.id, else_));
+                format!("if (v{}) {if_} else {else_}", *cond)
+            },
+            Terminator::Return => "fflush(stdout); return 0;".to_string(),
+            Terminator::Trap | Terminator::None => "abort();".to_string(),
+        };
+
+        writeln!(t.c, "    {term}").unwrap();
+    }
+
+    if body.blocks.is_empty() {
+        writeln!(t.c, "    return 0;").unwrap();
+    }
+
+    writeln!(t.c, "}}").unwrap();
+    t.c
+}
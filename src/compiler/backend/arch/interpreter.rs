@@ -1,41 +1,72 @@
-use crate::compiler::{error::*, common::*, backend::ssa::*};
-use std::{collections::HashMap, io::{Read, Write}};
+use crate::compiler::{error::*, common::*, engine::*, backend::{ssa::*, codegen::{RAM, REG}}};
 use derivative::Derivative;
-use logos::Span;
 
 #[derive(Derivative)]
 #[derivative(Debug)]
-pub struct Interpreter {
+pub struct Interpreter<W: Word> {
     #[derivative(Debug="ignore")]
     ssa: Body,
+    #[derivative(Debug="ignore")]
+    device: Box<dyn Device>,
 
     block_id: BlockId,
     instr_id: usize,
     block_id_old: BlockId,
 
     pub inst_count: usize,
-
-    values: Vec<u64>,
-    variables: Vec<Option<Vec<u64>>>,
+    last_ok: bool,
+
+    mask: W,
+    values: Vec<W>,
+    phi_values: Vec<W>,
+    variables: Vec<Option<Vec<W>>>,
+    /// Whether every variable is still around after optimisation
+    kept: Vec<bool>,
 }
 
-impl Interpreter {
+impl<W: Word> Interpreter<W> {
     pub fn new(ssa: (Body, usize, usize)) -> Self {
         Self {
+            kept: (0..ssa.2).map(|v| ssa.0.allocates(VariableId(v))).collect(),
+            mask: W::mask(ssa.0.bits),
             ssa: ssa.0,
+            device: Box::new(NoDevice),
 
             block_id: BlockId(0),
             instr_id: 0,
             block_id_old: BlockId(0),
 
             inst_count: 0,
+            last_ok: true,
 
-            values: vec![0; ssa.1],
+            values: vec![W::ZERO; ssa.1],
+            phi_values: Vec::new(),
             variables: vec![None; ssa.2],
         }
     }
 
-    pub fn step(&mut self, stdout: &mut impl Write, stdin: &mut impl Read) -> StepResult {
+    fn variable(&self, var: VariableId, index: u128) -> Result<W, InterpreterError> {
+        self.variables.get(*var)
+            .and_then(|v| v.as_ref())
+            .and_then(|v| v.get(usize::try_from(index).ok()?).copied())
+            .ok_or(InterpreterError::MemoryAccessOob(index))
+    }
+
+    fn variable_mut(&mut self, var: VariableId, index: u128) -> Result<&mut W, InterpreterError> {
+        self.variables.get_mut(*var)
+            .and_then(|v| v.as_mut())
+            .and_then(|v| v.get_mut(usize::try_from(index).ok()?))
+            .ok_or(InterpreterError::MemoryAccessOob(index))
+    }
+
+    /// Fails when `var` got promoted into values, so there's nothing left to look at
+    fn kept(&self, var: VariableId) -> Result<(), InterpreterError> {
+        if self.kept[*var] { Ok(()) } else { Err(InterpreterError::OptimisedAway) }
+    }
+}
+
+impl<W: Word> Engine for Interpreter<W> {
+    fn step(&mut self) -> StepResult {
         let block = self.ssa.blocks.get(*self.block_id).unwrap();
         let instr = block.instructions.get(self.instr_id);
 
@@ -46,56 +77,94 @@ impl Interpreter {
         }
 
         let val = match instr.map(|a| &a.operation) {
-            Some(Operation::Integer(imm)) => Some(*imm),
-            Some(Operation::LoadIndex(var, off)) => Some(*self.variables.get(var.0).unwrap().as_ref().unwrap().get(get!(val off) as usize).unwrap()),
+            Some(Operation::Integer(imm)) => Some(W::from_u128(*imm)),
+            Some(Operation::LoadIndex(var, off)) => Some(*self.variables.get(var.0).unwrap().as_ref().unwrap().get(get!(val off).as_usize()).unwrap()),
             Some(Operation::StoreIndex(var, off, dat)) => {
-                *self.variables.get_mut(var.0).unwrap().as_mut().unwrap().get_mut(get!(val off) as usize).unwrap() = get!(val dat);
+                *self.variables.get_mut(var.0).unwrap().as_mut().unwrap().get_mut(get!(val off).as_usize()).unwrap() = get!(val dat);
                 None
             },
             Some(Operation::Allocate(var, siz)) => {
-                self.variables[var.0] = Some(vec![0; get!(val siz) as usize]);
+                self.variables[var.0] = Some(vec![W::ZERO; get!(val siz).as_usize()]);
                 None
             },
-            Some(Operation::BinOp(op, l, r)) => Some(op.operate(get!(val l), get!(val r))),
+            Some(Operation::BinOp(op, l, r)) => match op.operate(get!(val l), get!(val r), self.ssa.bits) {
+                Some(v) => Some(v),
+                None => return StepResult::Error(Error {
+                    kind: InterpreterError::DivisionByZero,
+                    span: block.span_at(self.instr_id).cloned().unwrap_or_default(),
+                }),
+            },
             Some(Operation::Call(f, arg)) => match f {
                 Function::PortWrite => {
                     let p = get!(val arg[0]);
                     let d = get!(val arg[1]);
-                    let _ = unsafe { std::mem::transmute::<&Self, &mut Self>(self) }.port_write(p, d, stdout, stdin);
+                    self.last_ok = self.device.write(p.to_u128(), d.to_u128()).is_ok();
                     None
                 },
                 Function::PortRead => {
                     let p = get!(val arg[0]);
-                    Some(unsafe { std::mem::transmute::<&Self, &mut Self>(self) }.port_read(p, stdout, stdin).unwrap())
+                    let res = self.device.read(p.to_u128());
+                    self.last_ok = res.is_ok();
+                    Some(W::from_u128(res.unwrap_or(0)))
                 },
-                _ => todo!()
+                Function::LastOk => Some(W::from(self.last_ok)),
+                Function::ReportError => {
+                    let kind = get!(val arg[0]).to_u128();
+                    let pc = get!(val arg[1]).as_usize();
+                    let detail = get!(val arg[2]).to_u128();
+                    return StepResult::Error(Error {
+                        kind: InterpreterError::from_code(kind, detail),
+                        span: self.ssa.spans.get(pc).cloned().unwrap_or_default(),
+                    });
+                },
+            },
+            Some(Operation::Phi(_)) => {
+                // every Phi node at the start of the block is evaluated at once, as they all read
+                // the values from before the block was entered
+                let phis = block.instructions[self.instr_id..].iter()
+                    .take_while(|i| matches!(i.operation, Operation::Phi(_)));
+
+                self.phi_values.clear();
+                for phi in phis.clone() {
+                    let Operation::Phi(branches) = &phi.operation else { unreachable!() };
+                    let (val, _) = branches.iter().find(|(_, b)| *b == self.block_id_old).unwrap();
+                    self.phi_values.push(get!(val val));
+                }
+
+                for (phi, val) in phis.zip(self.phi_values.iter()) {
+                    self.values[phi.destination.unwrap().0] = *val;
+                }
+
+                self.instr_id += self.phi_values.len() - 1;
+                None
             },
             None => None,
-            _ => todo!(),
         };
 
-        if let Some(Some(dest)) = instr.map(|a| a.destination) {
-            self.values[dest.0] = val.unwrap();
+        if let (Some(Some(dest)), Some(val)) = (instr.map(|a| a.destination), val) {
+            self.values[dest.0] = val;
         }
 
         self.instr_id += 1;
         if self.instr_id >= block.instructions.len() {
-            self.inst_count += 1;
+            self.inst_count += block.spans.len();
+
             match &block.terminator {
                 Terminator::Jump(blk) => {
                     self.block_id_old = self.block_id;
-                    self.block_id = blk.clone();
+                    self.block_id = *blk;
                 },
                 Terminator::Branch(cond, if_, else_) => {
                     self.block_id_old = self.block_id;
-                    if get!(val cond) != 0 {
-                        self.block_id = if_.clone();
+                    if get!(val cond) != W::ZERO {
+                        self.block_id = *if_;
                     } else {
-                        self.block_id = else_.clone();
+                        self.block_id = *else_;
                     }
                 },
                 Terminator::Return => return StepResult::Halted,
-                _ => todo!(),
+                Terminator::Trap => unreachable!("trapped without reporting an error"),
+                Terminator::None => unreachable!("no terminator!"),
             }
 
             self.instr_id = 0;
@@ -104,36 +173,45 @@ impl Interpreter {
         StepResult::Running
     }
 
-    pub fn port_write(&mut self, port: u64, data: u64, stdout: &mut impl Write, _stdin: &mut impl Read) -> Result<(), InterpreterError> {
-        match unsafe { std::mem::transmute(port) } {
-            Port::Text => write!(stdout, "{}", unsafe { char::from_u32_unchecked(data as u32) }).unwrap(),
-            Port::Number => write!(stdout, "{data}").unwrap(),
+    fn register(&self, index: usize) -> Result<u128, InterpreterError> {
+        if index == 0 {
+            return Ok(0);
+        }
+
+        self.kept(REG)?;
+        self.variable(REG, index as u128 - 1)
+            .map(W::to_u128)
+            .map_err(|_| InterpreterError::RegisterOob(index as u64))
+    }
 
-            /*Port::Profile => {
-                self.debugging = data & 1 != 0;
-                println!("\x1b[1;32mInterpreter:\x1b[0m debugging is now {}", if self.debugging { "enabled" } else { "disabled" });
-            },*/
-            _ => return Err(InterpreterError::UnsupportedPort(port)),
+    fn set_register(&mut self, index: usize, value: u128) -> Result<(), InterpreterError> {
+        if index == 0 {
+            return Ok(());
         }
 
+        self.kept(REG)?;
+        let mask = self.mask;
+        *self.variable_mut(REG, index as u128 - 1).map_err(|_| InterpreterError::RegisterOob(index as u64))? = W::from_u128(value) & mask;
         Ok(())
     }
 
-    pub fn port_read(&mut self, port: u64, _stdout: &mut impl Write, stdin: &mut impl Read) -> Result<u64, InterpreterError> {
-        match unsafe { std::mem::transmute(port) } {
-            Port::Text => Ok({
-                let mut buf = [0];
-                stdin.read_exact(&mut buf).unwrap();
-                buf[0] as u64
-            }),
-            _ => Err(InterpreterError::UnsupportedPort(port))
-        }
+    fn memory(&self, address: u128) -> Result<u128, InterpreterError> {
+        self.kept(RAM)?;
+        self.variable(RAM, address).map(W::to_u128)
     }
-}
 
-#[derive(Debug)]
-pub enum StepResult {
-    Error(Error<InterpreterError>),
-    Running,
-    Halted,
+    fn set_memory(&mut self, address: u128, value: u128) -> Result<(), InterpreterError> {
+        self.kept(RAM)?;
+        let mask = self.mask;
+        *self.variable_mut(RAM, address)? = W::from_u128(value) & mask;
+        Ok(())
+    }
+
+    fn attach_device(&mut self, device: Box<dyn Device>) {
+        self.device = device;
+    }
+
+    fn stats(&self) -> Stats {
+        Stats { inst_count: self.inst_count }
+    }
 }
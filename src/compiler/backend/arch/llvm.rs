@@ -0,0 +1,376 @@
+//! Emits a body as textual LLVM IR, with a `main` that runs it.
+//!
+//! Values are `iN` with N the word size. Ports and errors go through functions that the runtime
+//! linked along with the module has to define, taking and giving words zero-extended to `i64`,
+//! or `i128` with words wider than 64 bits:
+//!
+//! - `urcl_port_read(port) -> word`
+//! - `urcl_port_write(port, data)`
+//! - `urcl_last_ok() -> i32`, whether the last port access went through
+//! - `urcl_report_error(kind, pc, detail)`, with the codes of [`InterpreterError`], which must
+//!   not return
+//!
+//! Pointers are typed, which LLVM 14 needs and later versions still read.
+
+use crate::compiler::{error::*, backend::ssa::*};
+use logos::Span;
+use std::{collections::HashMap, fmt::Write};
+
+/// What a block got translated into
+struct Translated {
+    code: String,
+    /// The LLVM block its terminator ends up in, which Phi nodes have to name
+    end: String,
+}
+
+struct Translation<'a> {
+    body: &'a Body,
+    /// `iN` of a word
+    ty: String,
+    /// The type words are passed to the runtime as
+    abi: &'static str,
+    /// Operands of values defined as integers
+    constants: HashMap<ValueId, String>,
+    /// Size of every variable, unless they aren't always the same constant
+    sizes: Vec<Option<u128>>,
+
+    code: String,
+    /// The LLVM block being emitted into
+    label: String,
+    temps: usize,
+}
+
+impl Translation<'_> {
+    fn temp(&mut self) -> String {
+        self.temps += 1;
+        format!("%t{}", self.temps)
+    }
+
+    fn value(&self, v: &ValueId) -> String {
+        self.constants.get(v).cloned().unwrap_or_else(|| format!("%v{}", **v))
+    }
+
+    fn line(&mut self, line: String) {
+        writeln!(self.code, "  {line}").unwrap();
+    }
+
+    /// Starts a new LLVM block within the same source block
+    fn split(&mut self, label: String) {
+        writeln!(self.code, "{label}:").unwrap();
+        self.label = label;
+    }
+
+    /// Converts `v` between a word and `ty` with `ext`, if they differ in size
+    fn convert(&mut self, v: String, ext: &str, ty: &str) -> String {
+        let (from, to) = (self.body.bits, ty[1..].parse::<usize>().unwrap());
+        if from == to {
+            return v;
+        }
+
+        let t = self.temp();
+        let op = if from < to { ext } else { "trunc" };
+        self.line(format!("{t} = {op} {} {v} to {ty}", self.ty));
+        t
+    }
+
+    /// Puts the `i1` that `expr` gives into `dst` as a word
+    fn bool_into(&mut self, dst: &str, expr: String) {
+        if self.body.bits == 1 {
+            self.line(format!("{dst} = {expr}"));
+            return;
+        }
+
+        let t = self.temp();
+        self.line(format!("{t} = {expr}"));
+        self.line(format!("{dst} = zext i1 {t} to {}", self.ty));
+    }
+
+    /// Reports an error if `cond` holds, going on in a new block otherwise
+    fn check(&mut self, cond: String, kind: InterpreterError, span: &Span, detail: String) {
+        let pc = self.body.spans.iter().position(|s| s == span).map_or(u64::MAX as u128, |pc| pc as u128);
+        self.temps += 1;
+        let (error, ok) = (format!("e{}", self.temps), format!("c{}", self.temps));
+        let detail = self.convert(detail, "zext", self.abi);
+        self.line(format!("br i1 {cond}, label %{error}, label %{ok}"));
+        self.split(error);
+        self.line(format!("call void @urcl_report_error({abi} {}, {abi} {pc}, {abi} {detail})", kind.code(), abi = self.abi));
+        self.line("unreachable".to_string());
+        self.split(ok);
+    }
+
+    /// Gives a `ty*` to `off` in `var`, after checking that it is in bounds
+    fn element(&mut self, var: &VariableId, off: &ValueId, span: &Span) -> String {
+        let (ty, off) = (self.ty.clone(), self.value(off));
+        let (len, oob) = (self.temp(), self.temp());
+        self.line(format!("{len} = load {ty}, {ty}* @m{}.len", **var));
+        self.line(format!("{oob} = icmp uge {ty} {off}, {len}"));
+        self.check(oob, InterpreterError::MemoryAccessOob(0), span, off.clone());
+
+        let index = self.convert(off, "zext", "i64");
+        let ptr = self.temp();
+        match self.sizes[**var] {
+            Some(size) => {
+                let array = format!("[{} x {ty}]", size.max(1));
+                self.line(format!("{ptr} = getelementptr {array}, {array}* @m{}, i64 0, i64 {index}", **var));
+            },
+            None => {
+                let base = self.temp();
+                self.line(format!("{base} = load {ty}*, {ty}** @m{}", **var));
+                self.line(format!("{ptr} = getelementptr {ty}, {ty}* {base}, i64 {index}"));
+            },
+        }
+
+        ptr
+    }
+
+    fn binop(&mut self, dst: &str, op: BinOp, l: &ValueId, r: &ValueId, span: &Span) {
+        let (ty, bits, l, r) = (self.ty.clone(), self.body.bits, self.value(l), self.value(r));
+
+        if matches!(op, BinOp::Div | BinOp::Mod | BinOp::SDiv | BinOp::SMod) {
+            let zero = self.temp();
+            self.line(format!("{zero} = icmp eq {ty} {r}, 0"));
+            self.check(zero, InterpreterError::DivisionByZero, span, "0".to_string());
+        }
+
+        let compare = |t: &mut Self, cond: &str| t.bool_into(dst, format!("icmp {cond} {ty} {l}, {r}"));
+
+        // LLVM gives poison for shifts by the word size or more
+        let shift = |t: &mut Self, op: &str| {
+            let (over, shifted) = (t.temp(), t.temp());
+            t.line(format!("{over} = icmp uge {ty} {r}, {bits}"));
+            t.line(format!("{shifted} = {op} {ty} {l}, {r}"));
+            t.line(format!("{dst} = select i1 {over}, {ty} 0, {ty} {shifted}"));
+        };
+
+        // dividing the smallest number by -1 is undefined, so -1 is swapped out for 1 there
+        let signed = |t: &mut Self, op: &str, by_minus_one: String| {
+            let (minus_one, divisor, res) = (t.temp(), t.temp(), t.temp());
+            t.line(format!("{minus_one} = icmp eq {ty} {r}, -1"));
+            t.line(format!("{divisor} = select i1 {minus_one}, {ty} 1, {ty} {r}"));
+            t.line(format!("{res} = {op} {ty} {l}, {divisor}"));
+            t.line(format!("{dst} = select i1 {minus_one}, {ty} {by_minus_one}, {ty} {res}"));
+        };
+
+        match op {
+            BinOp::Add  => self.line(format!("{dst} = add {ty} {l}, {r}")),
+            BinOp::Sub  => self.line(format!("{dst} = sub {ty} {l}, {r}")),
+            BinOp::Mul  => self.line(format!("{dst} = mul {ty} {l}, {r}")),
+            BinOp::Div  => self.line(format!("{dst} = udiv {ty} {l}, {r}")),
+            BinOp::Mod  => self.line(format!("{dst} = urem {ty} {l}, {r}")),
+            BinOp::SDiv => {
+                let negated = self.temp();
+                self.line(format!("{negated} = sub {ty} 0, {l}"));
+                signed(self, "sdiv", negated);
+            },
+            BinOp::SMod => signed(self, "srem", "0".to_string()),
+            BinOp::And  => self.line(format!("{dst} = and {ty} {l}, {r}")),
+            BinOp::Or   => self.line(format!("{dst} = or {ty} {l}, {r}")),
+            BinOp::Xor  => self.line(format!("{dst} = xor {ty} {l}, {r}")),
+            BinOp::Shl  => shift(self, "shl"),
+            BinOp::Shr  => shift(self, "lshr"),
+            BinOp::Sar  => {
+                // the sign fills everything once the amount gets to the last bit
+                let (over, amount) = (self.temp(), self.temp());
+                self.line(format!("{over} = icmp uge {ty} {r}, {}", bits - 1));
+                self.line(format!("{amount} = select i1 {over}, {ty} {}, {ty} {r}", bits - 1));
+                self.line(format!("{dst} = ashr {ty} {l}, {amount}"));
+            },
+            BinOp::Eq   => compare(self, "eq"),
+            BinOp::Ne   => compare(self, "ne"),
+            BinOp::Lt   => compare(self, "ult"),
+            BinOp::Le   => compare(self, "ule"),
+            BinOp::Gt   => compare(self, "ugt"),
+            BinOp::Ge   => compare(self, "uge"),
+            BinOp::SLt  => compare(self, "slt"),
+            BinOp::SLe  => compare(self, "sle"),
+            BinOp::SGt  => compare(self, "sgt"),
+            BinOp::SGe  => compare(self, "sge"),
+        }
+    }
+
+    fn allocate(&mut self, var: &VariableId, size: &ValueId) {
+        let (ty, size, var) = (self.ty.clone(), self.value(size), **var);
+        match self.sizes[var] {
+            Some(max) => {
+                let array = format!("[{} x {ty}]", max.max(1));
+                self.line(format!("store {ty} {size}, {ty}* @m{var}.len"));
+                self.line(format!("call void @llvm.memset.p0i8.i64(i8* bitcast ({array}* @m{var} to i8*), i8 0, i64 ptrtoint ({array}* getelementptr ({array}, {array}* null, i64 1) to i64), i1 false)"));
+            },
+            None => {
+                // sizes that don't fit in 64 bits are sure to fail, and failing leaves it empty
+                let (old, bytes) = (self.temp(), self.temp());
+                self.line(format!("{old} = load {ty}*, {ty}** @m{var}"));
+                self.line(format!("{bytes} = bitcast {ty}* {old} to i8*"));
+                self.line(format!("call void @free(i8* {bytes})"));
+
+                let mut count = self.convert(size.clone(), "zext", "i64");
+                if self.body.bits > 64 {
+                    let (big, clamped) = (self.temp(), self.temp());
+                    self.line(format!("{big} = icmp ugt {ty} {size}, {}", u64::MAX));
+                    self.line(format!("{clamped} = select i1 {big}, i64 -1, i64 {count}"));
+                    count = clamped;
+                }
+
+                let (bytes, ptr, ok, len) = (self.temp(), self.temp(), self.temp(), self.temp());
+                self.line(format!("{bytes} = call i8* @calloc(i64 {count}, i64 ptrtoint ({ty}* getelementptr ({ty}, {ty}* null, i64 1) to i64))"));
+                self.line(format!("{ptr} = bitcast i8* {bytes} to {ty}*"));
+                self.line(format!("store {ty}* {ptr}, {ty}** @m{var}"));
+                self.line(format!("{ok} = icmp ne i8* {bytes}, null"));
+                self.line(format!("{len} = select i1 {ok}, {ty} {size}, {ty} 0"));
+                self.line(format!("store {ty} {len}, {ty}* @m{var}.len"));
+            },
+        }
+    }
+
+    fn instruction(&mut self, instr: &Instruction, span: &Span) {
+        let ty = self.ty.clone();
+        let abi = self.abi;
+        let dst = instr.destination.map_or_else(|| self.temp(), |d| format!("%v{}", *d));
+
+        match &instr.operation {
+            Operation::Phi(_) | Operation::Integer(_) => {},
+            Operation::BinOp(op, l, r) => self.binop(&dst, *op, l, r, span),
+            Operation::Allocate(var, size) => self.allocate(var, size),
+            Operation::LoadIndex(var, off) => {
+                let ptr = self.element(var, off, span);
+                self.line(format!("{dst} = load {ty}, {ty}* {ptr}"));
+            },
+            Operation::StoreIndex(var, off, val) => {
+                let ptr = self.element(var, off, span);
+                let val = self.value(val);
+                self.line(format!("store {ty} {val}, {ty}* {ptr}"));
+            },
+            Operation::Call(f, args) => {
+                let args: Vec<_> = args.iter().map(|a| {
+                    let a = self.value(a);
+                    format!("{abi} {}", self.convert(a, "zext", abi))
+                }).collect();
+                let args = args.join(", ");
+
+                match f {
+                    Function::LastOk => {
+                        let ok = self.temp();
+                        self.line(format!("{ok} = call i32 @urcl_last_ok()"));
+                        self.bool_into(&dst, format!("icmp ne i32 {ok}, 0"));
+                    },
+                    Function::PortRead => {
+                        if self.body.bits < abi[1..].parse().unwrap() {
+                            let data = self.temp();
+                            self.line(format!("{data} = call {abi} @urcl_port_read({args})"));
+                            self.line(format!("{dst} = trunc {abi} {data} to {ty}"));
+                        } else {
+                            self.line(format!("{dst} = call {abi} @urcl_port_read({args})"));
+                        }
+                    },
+                    Function::PortWrite => self.line(format!("call void @urcl_port_write({args})")),
+                    Function::ReportError => self.line(format!("call void @urcl_report_error({args})")),
+                }
+            },
+        }
+    }
+
+    fn block(&mut self, blk: &Block) -> Translated {
+        self.code.clear();
+        self.label = format!("b{}", *blk.id);
+
+        for (i, instr) in blk.instructions.iter().enumerate() {
+            self.instruction(instr, &blk.span_at(i).cloned().unwrap_or_default());
+        }
+
+        match blk.terminator {
+            Terminator::Jump(to) => self.line(format!("br label %b{}", *to)),
+            Terminator::Branch(cond, if_, else_) => {
+                let (b, cond) = (self.temp(), self.value(&cond));
+                self.line(format!("{b} = icmp ne {} {cond}, 0", self.ty));
+                self.line(format!("br i1 {b}, label %b{}, label %b{}", *if_, *else_));
+            },
+            Terminator::Return => self.line("ret i32 0".to_string()),
+            Terminator::Trap | Terminator::None => self.line("unreachable".to_string()),
+        }
+
+        Translated { code: std::mem::take(&mut self.code), end: self.label.clone() }
+    }
+}
+
+/// Emits `ssa` as an LLVM module, see the [module docs](self) for the runtime it needs
+pub fn to_llvm(ssa: &(Body, usize, usize)) -> String {
+    let (body, _, variables) = ssa;
+    let instructions = || body.blocks.iter().flat_map(|b| b.instructions.iter());
+
+    let ty = format!("i{}", body.bits);
+    let abi = if body.bits <= 64 { "i64" } else { "i128" };
+    let constants: HashMap<_, _> = instructions()
+        .filter_map(|i| match (i.destination, &i.operation) {
+            (Some(d), Operation::Integer(int)) => Some((d, int.to_string())),
+            _ => None,
+        })
+        .collect();
+
+    let mut sizes = vec![Some(0); *variables];
+    for instr in instructions() {
+        if let Operation::Allocate(var, size) = &instr.operation {
+            let size = match instructions().find(|i| i.destination == Some(*size)).map(|i| &i.operation) {
+                Some(Operation::Integer(int)) => Some(*int),
+                _ => None,
+            };
+
+            sizes[**var] = sizes[**var].zip(size).map(|(a, b)| a.max(b));
+        }
+    }
+
+    let mut ll = String::new();
+    writeln!(ll, "; generated by urcl-io\n").unwrap();
+    writeln!(ll, "declare {abi} @urcl_port_read({abi})").unwrap();
+    writeln!(ll, "declare void @urcl_port_write({abi}, {abi})").unwrap();
+    writeln!(ll, "declare i32 @urcl_last_ok()").unwrap();
+    writeln!(ll, "declare void @urcl_report_error({abi}, {abi}, {abi}) noreturn").unwrap();
+    writeln!(ll, "declare i8* @calloc(i64, i64)").unwrap();
+    writeln!(ll, "declare void @free(i8*)").unwrap();
+    writeln!(ll, "declare void @llvm.memset.p0i8.i64(i8*, i8, i64, i1)\n").unwrap();
+
+    for (var, size) in sizes.iter().enumerate() {
+        match size {
+            Some(size) => writeln!(ll, "@m{var} = internal global [{} x {ty}] zeroinitializer", size.max(&1)).unwrap(),
+            None => writeln!(ll, "@m{var} = internal global {ty}* null").unwrap(),
+        }
+
+        writeln!(ll, "@m{var}.len = internal global {ty} 0").unwrap();
+    }
+
+    let mut t = Translation {
+        body,
+        ty: ty.clone(),
+        abi,
+        constants,
+        sizes,
+
+        code: String::new(),
+        label: String::new(),
+        temps: 0,
+    };
+
+    let translated: Vec<_> = body.blocks.iter().map(|b| t.block(b)).collect();
+
+    writeln!(ll, "\ndefine i32 @main() {{").unwrap();
+    writeln!(ll, "entry:").unwrap();
+    if body.blocks.is_empty() {
+        writeln!(ll, "  ret i32 0").unwrap();
+    } else {
+        writeln!(ll, "  br label %b0").unwrap();
+    }
+
+    for (blk, this) in body.blocks.iter().zip(translated.iter()) {
+        writeln!(ll, "\nb{}: ; {}", *blk.id, blk.name).unwrap();
+        for instr in blk.instructions.iter() {
+            if let (Some(dst), Operation::Phi(branches)) = (instr.destination, &instr.operation) {
+                let incoming: Vec<_> = branches.iter().map(|(v, b)| format!("[ {}, %{} ]", t.value(v), translated[**b].end)).collect();
+                writeln!(ll, "  %v{} = phi {ty} {}", *dst, incoming.join(", ")).unwrap();
+            }
+        }
+
+        ll.push_str(&this.code);
+    }
+
+    writeln!(ll, "}}").unwrap();
+    ll
+}
@@ -1 +1,8 @@
 pub mod interpreter;
+pub mod vm;
+pub mod threaded;
+pub mod x86_64;
+pub mod c;
+pub mod wasm;
+pub mod llvm;
+pub mod urcl;
@@ -0,0 +1,322 @@
+use crate::compiler::{error::*, common::*, engine::*, backend::{ssa::{*, Instruction}, codegen::{RAM, REG}}};
+use derivative::Derivative;
+use logos::Span;
+use std::rc::Rc;
+
+/// Everything the compiled closures work on
+struct State<W: Word> {
+    regs: Vec<W>,
+    variables: Vec<Vec<W>>,
+    device: Box<dyn Device>,
+    last_ok: bool,
+    mask: W,
+    /// Phi values on their way to their destinations
+    moving: Vec<W>,
+}
+
+type Inst<W> = Box<dyn Fn(&mut State<W>) -> Result<(), Error<InterpreterError>>>;
+
+/// Where to go after a block
+enum Next {
+    Block(usize),
+    Return,
+}
+
+type Term<W> = Box<dyn Fn(&mut State<W>) -> Next>;
+
+struct ThreadedBlock<W: Word> {
+    insts: Vec<Inst<W>>,
+    term: Term<W>,
+    /// Amount of URCL instructions making up the block
+    count: usize,
+}
+
+/// Compiles the Phi copies of the edge going from `from` to `to` into a closure that switches
+/// over to `to`
+fn edge<W: Word>(body: &Body, from: BlockId, to: BlockId) -> Term<W> {
+    let copies: Vec<(usize, usize)> = body.blocks[*to].instructions.iter()
+        .map_while(|i| match &i.operation {
+            Operation::Phi(branches) => Some((*i.destination.unwrap(), *branches.iter().find(|(_, b)| *b == from).unwrap().0)),
+            _ => None,
+        })
+        .collect();
+    let to = *to;
+
+    match copies.len() {
+        0 => Box::new(move |_| Next::Block(to)),
+        1 => {
+            let (dst, src) = copies[0];
+            Box::new(move |s| {
+                s.regs[dst] = s.regs[src];
+                Next::Block(to)
+            })
+        },
+        _ => Box::new(move |s| {
+            s.moving.clear();
+            for (_, src) in copies.iter() {
+                let v = s.regs[*src];
+                s.moving.push(v);
+            }
+
+            for ((dst, _), v) in copies.iter().zip(s.moving.iter()) {
+                s.regs[*dst] = *v;
+            }
+
+            Next::Block(to)
+        }),
+    }
+}
+
+/// Compiles one instruction, with its operands captured as register indices
+fn compile<W: Word>(instr: &Instruction, span: Span, source: &Rc<[Span]>, bits: usize) -> Option<Inst<W>> {
+    let dst = instr.destination.map_or(0, |d| *d);
+
+    macro_rules! binop {
+        ($l: ident, $r: ident, |$a: ident, $b: ident| $e: expr) => {{
+            let ($l, $r) = (**$l, **$r);
+            Box::new(move |s: &mut State<W>| {
+                let ($a, $b) = (s.regs[$l], s.regs[$r]);
+                s.regs[dst] = $e;
+                Ok(())
+            })
+        }};
+    }
+
+    Some(match &instr.operation {
+        Operation::Phi(_) => return None,
+        Operation::Integer(int) => {
+            let imm = W::from_u128(*int);
+            Box::new(move |s| {
+                s.regs[dst] = imm;
+                Ok(())
+            })
+        },
+        Operation::BinOp(op, l, r) => match op {
+            BinOp::Add => binop!(l, r, |a, b| a.wrapping_add(b)),
+            BinOp::Sub => binop!(l, r, |a, b| a.wrapping_sub(b)),
+            BinOp::And => binop!(l, r, |a, b| a & b),
+            BinOp::Or  => binop!(l, r, |a, b| a | b),
+            BinOp::Xor => binop!(l, r, |a, b| a ^ b),
+            BinOp::Shr => binop!(l, r, |a, b| a.shift_right(b)),
+            BinOp::Eq  => binop!(l, r, |a, b| W::from(a == b)),
+            BinOp::Lt  => binop!(l, r, |a, b| W::from(a < b)),
+            BinOp::Ge  => binop!(l, r, |a, b| W::from(a >= b)),
+            op => {
+                let (op, l, r) = (*op, **l, **r);
+                Box::new(move |s| match op.operate(s.regs[l], s.regs[r], bits) {
+                    Some(v) => {
+                        s.regs[dst] = v;
+                        Ok(())
+                    },
+                    None => Err(Error { kind: InterpreterError::DivisionByZero, span: span.clone() }),
+                })
+            },
+        },
+        Operation::Allocate(var, size) => {
+            let (var, size) = (**var, **size);
+            Box::new(move |s| {
+                s.variables[var] = vec![W::ZERO; s.regs[size].as_usize()];
+                Ok(())
+            })
+        },
+        Operation::LoadIndex(var, off) => {
+            let (var, off) = (**var, **off);
+            Box::new(move |s| match s.variables[var].get(s.regs[off].as_usize()) {
+                Some(v) => {
+                    s.regs[dst] = *v;
+                    Ok(())
+                },
+                None => Err(Error { kind: InterpreterError::MemoryAccessOob(s.regs[off].to_u128()), span: span.clone() }),
+            })
+        },
+        Operation::StoreIndex(var, off, val) => {
+            let (var, off, val) = (**var, **off, **val);
+            Box::new(move |s| {
+                let v = s.regs[val];
+                match s.variables[var].get_mut(s.regs[off].as_usize()) {
+                    Some(slot) => {
+                        *slot = v;
+                        Ok(())
+                    },
+                    None => Err(Error { kind: InterpreterError::MemoryAccessOob(s.regs[off].to_u128()), span: span.clone() }),
+                }
+            })
+        },
+        Operation::Call(f, args) => {
+            let args: Vec<usize> = args.iter().map(|a| **a).collect();
+            match f {
+                Function::PortRead => Box::new(move |s| {
+                    let res = s.device.read(s.regs[args[0]].to_u128());
+                    s.last_ok = res.is_ok();
+                    s.regs[dst] = W::from_u128(res.unwrap_or(0));
+                    Ok(())
+                }),
+                Function::PortWrite => Box::new(move |s| {
+                    s.last_ok = s.device.write(s.regs[args[0]].to_u128(), s.regs[args[1]].to_u128()).is_ok();
+                    Ok(())
+                }),
+                Function::LastOk => Box::new(move |s| {
+                    s.regs[dst] = W::from(s.last_ok);
+                    Ok(())
+                }),
+                Function::ReportError => {
+                    let source = Rc::clone(source);
+                    Box::new(move |s| Err(Error {
+                        kind: InterpreterError::from_code(s.regs[args[0]].to_u128(), s.regs[args[2]].to_u128()),
+                        span: source.get(s.regs[args[1]].as_usize()).cloned().unwrap_or_default(),
+                    }))
+                },
+            }
+        },
+    })
+}
+
+/// Runs a body compiled into closures, one chain per block
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct Threaded<W: Word> {
+    #[derivative(Debug="ignore")]
+    blocks: Vec<ThreadedBlock<W>>,
+    #[derivative(Debug="ignore")]
+    state: State<W>,
+
+    block: usize,
+    halted: bool,
+    pub inst_count: usize,
+    /// Whether every variable is still around after optimisation
+    kept: Vec<bool>,
+}
+
+impl<W: Word> Threaded<W> {
+    pub fn new(ssa: (Body, usize, usize)) -> Self {
+        let (body, values, variables) = ssa;
+        let source: Rc<[Span]> = body.spans.clone().into();
+
+        let blocks = body.blocks.iter().map(|blk| {
+            let insts = blk.instructions.iter().enumerate()
+                .filter_map(|(i, instr)| compile(instr, blk.span_at(i).cloned().unwrap_or_default(), &source, body.bits))
+                .collect();
+
+            let term: Term<W> = match blk.terminator {
+                Terminator::Jump(to) => edge(&body, blk.id, to),
+                Terminator::Branch(cond, if_, else_) => {
+                    let (if_, else_, cond) = (edge(&body, blk.id, if_), edge(&body, blk.id, else_), *cond);
+                    Box::new(move |s| if s.regs[cond] != W::ZERO { if_(s) } else { else_(s) })
+                },
+                Terminator::Return => Box::new(|_| Next::Return),
+                Terminator::Trap | Terminator::None => Box::new(|_| unreachable!("trapped without reporting an error")),
+            };
+
+            ThreadedBlock { insts, term, count: blk.spans.len() }
+        }).collect();
+
+        Self {
+            blocks,
+            state: State {
+                regs: vec![W::ZERO; values],
+                variables: vec![Vec::new(); variables],
+                device: Box::new(NoDevice),
+                last_ok: true,
+                mask: W::mask(body.bits),
+                moving: Vec::new(),
+            },
+
+            block: 0,
+            halted: false,
+            inst_count: 0,
+            kept: (0..variables).map(|v| body.allocates(VariableId(v))).collect(),
+        }
+    }
+
+    fn variable(&self, var: VariableId, index: u128) -> Result<W, InterpreterError> {
+        self.state.variables.get(*var)
+            .and_then(|v| v.get(usize::try_from(index).ok()?).copied())
+            .ok_or(InterpreterError::MemoryAccessOob(index))
+    }
+
+    fn variable_mut(&mut self, var: VariableId, index: u128) -> Result<&mut W, InterpreterError> {
+        self.state.variables.get_mut(*var)
+            .and_then(|v| v.get_mut(usize::try_from(index).ok()?))
+            .ok_or(InterpreterError::MemoryAccessOob(index))
+    }
+
+    /// Fails when `var` got promoted into values, so there's nothing left to look at
+    fn kept(&self, var: VariableId) -> Result<(), InterpreterError> {
+        if self.kept[*var] { Ok(()) } else { Err(InterpreterError::OptimisedAway) }
+    }
+}
+
+impl<W: Word> Engine for Threaded<W> {
+    fn step(&mut self) -> StepResult {
+        self.run(1)
+    }
+
+    fn run(&mut self, budget: usize) -> StepResult {
+        if self.halted || self.blocks.is_empty() {
+            return StepResult::Halted;
+        }
+
+        let end = self.inst_count.saturating_add(budget);
+        while self.inst_count < end {
+            let block = &self.blocks[self.block];
+            for inst in block.insts.iter() {
+                if let Err(err) = inst(&mut self.state) {
+                    return StepResult::Error(err);
+                }
+            }
+
+            self.inst_count += block.count;
+            match (block.term)(&mut self.state) {
+                Next::Block(b) => self.block = b,
+                Next::Return => {
+                    self.halted = true;
+                    return StepResult::Halted;
+                },
+            }
+        }
+
+        StepResult::Running
+    }
+
+    fn register(&self, index: usize) -> Result<u128, InterpreterError> {
+        if index == 0 {
+            return Ok(0);
+        }
+
+        self.kept(REG)?;
+        self.variable(REG, index as u128 - 1)
+            .map(W::to_u128)
+            .map_err(|_| InterpreterError::RegisterOob(index as u64))
+    }
+
+    fn set_register(&mut self, index: usize, value: u128) -> Result<(), InterpreterError> {
+        if index == 0 {
+            return Ok(());
+        }
+
+        self.kept(REG)?;
+        let mask = self.state.mask;
+        *self.variable_mut(REG, index as u128 - 1).map_err(|_| InterpreterError::RegisterOob(index as u64))? = W::from_u128(value) & mask;
+        Ok(())
+    }
+
+    fn memory(&self, address: u128) -> Result<u128, InterpreterError> {
+        self.kept(RAM)?;
+        self.variable(RAM, address).map(W::to_u128)
+    }
+
+    fn set_memory(&mut self, address: u128, value: u128) -> Result<(), InterpreterError> {
+        self.kept(RAM)?;
+        let mask = self.state.mask;
+        *self.variable_mut(RAM, address)? = W::from_u128(value) & mask;
+        Ok(())
+    }
+
+    fn attach_device(&mut self, device: Box<dyn Device>) {
+        self.state.device = device;
+    }
+
+    fn stats(&self) -> Stats {
+        Stats { inst_count: self.inst_count }
+    }
+}
@@ -0,0 +1,1200 @@
+use crate::compiler::{
+    common::Port,
+    error::{Error, InterpreterError, UrclError},
+    backend::{ssa::*, builder::*, analysis::Cfg, codegen::RAM, opt::{mem2reg, dce}, regalloc::{Target, Location, allocate_ranges, phi_copies, sequentialise}},
+};
+use logos::Span;
+use std::{collections::{HashMap, HashSet}, fmt::Write};
+
+/// What the URCL output takes over from the program the IR was generated from
+pub struct Origin<'a> {
+    /// Source of the program, quoted in the comment next to every instruction
+    pub source: &'a str,
+    /// Labels of the program along with the instruction they point at
+    pub labels: &'a [(String, usize)],
+    /// Most registers the output may use
+    pub minreg: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Operand {
+    Reg(usize),
+    Imm(u128),
+}
+
+impl From<usize> for Operand {
+    fn from(r: usize) -> Self {
+        Self::Reg(r)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Add,
+    Nor,
+    Rsh,
+    Lod,
+    Str,
+    Imm,
+    Mov,
+    In,
+    Out,
+    Bge(usize),
+    /// `BGE` which always branches
+    Jump(usize),
+    Label(usize),
+}
+
+/// A URCL instruction, first over virtual registers and then over real ones
+struct MInst {
+    op: Op,
+    /// Register written, `r0` if there is none
+    dst: Option<usize>,
+    srcs: Vec<Operand>,
+    span: Option<Span>,
+}
+
+impl MInst {
+    fn reads(&self) -> impl Iterator<Item = usize> + '_ {
+        self.srcs.iter().filter_map(|s| match s {
+            Operand::Reg(r) => Some(*r),
+            Operand::Imm(_) => None,
+        })
+    }
+}
+
+/// How control leaves a block once the checks URCL does by itself are gone
+enum Exit {
+    Jump(BlockId),
+    /// Goes to the first block if the first operand is at least the second one
+    Branch(Operand, Operand, BlockId, BlockId),
+    Return,
+    Trap,
+}
+
+/// Turns a body into URCL over virtual registers, which are the values of the body and
+/// temporaries numbered after them
+struct Selector<'a> {
+    body: &'a Body,
+    mask: u128,
+    /// Whether the RAM gets spill slots past its end, which makes accesses just past it succeed
+    spills: bool,
+
+    constants: HashMap<ValueId, u128>,
+    /// Values that are the same as another one once every word is `bits` wide, like masked sums
+    aliases: HashMap<ValueId, ValueId>,
+    definitions: HashMap<ValueId, (BlockId, usize)>,
+    uses: HashMap<ValueId, usize>,
+    /// Values computed by the instruction using them, e.g. the comparison of a branch
+    fused: HashSet<ValueId>,
+
+    /// Constant size of the RAM, `None` if the program got rid of it
+    ram_size: Option<u128>,
+    dw: Vec<u128>,
+    /// Stores that went into `dw` instead, by block and instruction
+    initialised: HashSet<(BlockId, usize)>,
+
+    code: Vec<MInst>,
+    /// Name of every label, where the first ones are the blocks
+    labels: Vec<String>,
+    taken_names: HashSet<String>,
+    halt: Option<usize>,
+    vregs: usize,
+    span: Option<Span>,
+}
+
+impl<'a> Selector<'a> {
+    fn new(body: &'a Body, origin: &Origin, vregs: usize, spills: bool) -> Result<Self, Error<UrclError>> {
+        let mut s = Self {
+            body,
+            mask: u128::MAX >> (128 - body.bits),
+            spills,
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            definitions: HashMap::new(),
+            uses: HashMap::new(),
+            fused: HashSet::new(),
+            ram_size: None,
+            dw: Vec::new(),
+            initialised: HashSet::new(),
+            code: Vec::new(),
+            labels: Vec::new(),
+            taken_names: origin.labels.iter().map(|(name, _)| name.clone()).collect(),
+            halt: None,
+            vregs,
+            span: None,
+        };
+
+        for blk in body.blocks.iter() {
+            for (i, instr) in blk.instructions.iter().enumerate() {
+                if let Some(dst) = instr.destination {
+                    s.definitions.insert(dst, (blk.id, i));
+                }
+
+                match &instr.operation {
+                    Operation::Allocate(var, _) | Operation::LoadIndex(var, _) | Operation::StoreIndex(var, _, _) if *var != RAM => {
+                        let span = own_span(blk, i).unwrap_or_default();
+                        return Err(Error { kind: UrclError::UnpromotedVariable(**var), span });
+                    },
+                    _ => {},
+                }
+            }
+        }
+
+        s.fold();
+
+        for blk in body.blocks.iter() {
+            for (i, instr) in blk.instructions.iter().enumerate() {
+                if let Operation::Allocate(_, size) = &instr.operation {
+                    match s.value(*size) {
+                        Operand::Imm(size) => s.ram_size = Some(size),
+                        Operand::Reg(_) => return Err(Error { kind: UrclError::RamResized, span: own_span(blk, i).unwrap_or_default() }),
+                    }
+                }
+            }
+        }
+
+        s.count_uses();
+        s.fuse();
+
+        s.collect_dw();
+
+        // blocks starting at a labelled instruction keep its label
+        let pcs: HashMap<usize, usize> = body.spans.iter().enumerate().map(|(pc, span)| (span.start, pc)).collect();
+        for blk in body.blocks.iter() {
+            let label = blk.spans.first()
+                .and_then(|(_, span)| pcs.get(&span.start))
+                .and_then(|pc| origin.labels.iter().find(|(_, at)| at == pc));
+            let name = match label {
+                Some((name, _)) => name.clone(),
+                None => s.unique_name(&blk.name),
+            };
+            s.labels.push(name);
+        }
+
+        Ok(s)
+    }
+
+    /// Finds every value that turns into a constant or into another value, which is repeated
+    /// since Phi nodes can refer to values defined later
+    fn fold(&mut self) {
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for instr in self.body.blocks.iter().flat_map(|b| b.instructions.iter()) {
+                let Some(dst) = instr.destination else { continue };
+                if self.constants.contains_key(&dst) || self.aliases.contains_key(&dst) {
+                    continue;
+                }
+
+                let folded = match &instr.operation {
+                    Operation::Integer(int) => Some(Operand::Imm(*int & self.mask)),
+                    // a port that doesn't work stops the program by itself
+                    Operation::Call(Function::LastOk, _) => Some(Operand::Imm(1)),
+                    Operation::BinOp(op, l, r) => match (self.value(*l), self.value(*r)) {
+                        (Operand::Imm(l), Operand::Imm(r)) => op.operate(l, r, self.body.bits).map(|v| Operand::Imm(v & self.mask)),
+                        // every word already is `bits` wide
+                        (Operand::Reg(v), Operand::Imm(m)) | (Operand::Imm(m), Operand::Reg(v)) if *op == BinOp::And && m == self.mask => Some(Operand::Reg(v)),
+                        _ => None,
+                    },
+                    Operation::Phi(branches) => {
+                        let inputs: HashSet<_> = branches.iter()
+                            .map(|(v, _)| self.value(*v))
+                            .filter(|v| *v != Operand::Reg(*dst))
+                            .collect();
+                        (inputs.len() == 1).then(|| inputs.into_iter().next().unwrap())
+                    },
+                    _ => None,
+                };
+
+                match folded {
+                    Some(Operand::Imm(int)) => { self.constants.insert(dst, int); },
+                    Some(Operand::Reg(v)) => { self.aliases.insert(dst, ValueId(v)); },
+                    None => continue,
+                }
+
+                changed = true;
+            }
+        }
+    }
+
+    fn count_uses(&mut self) {
+        let mut uses = HashMap::new();
+        for blk in self.body.blocks.iter() {
+            for instr in blk.instructions.iter() {
+                if instr.destination.is_some_and(|d| self.is_replaced(d)) {
+                    continue;
+                }
+
+                for v in instr.operation.operands() {
+                    if let Operand::Reg(v) = self.value(v) {
+                        *uses.entry(ValueId(v)).or_insert(0) += 1;
+                    }
+                }
+            }
+
+            // branches that only check bounds go away, along with the comparison if nothing else
+            // uses it
+            if let Terminator::Branch(cond, if_, else_) = blk.terminator {
+                if let Operand::Reg(v) = self.value(cond) {
+                    if !self.is_bounds_check(ValueId(v), if_, else_) {
+                        *uses.entry(ValueId(v)).or_insert(0) += 1;
+                    }
+                }
+            }
+        }
+
+        self.uses = uses;
+    }
+
+    /// Picks the comparisons that can go straight into a `BGE` and the ORs that are part of a NOR
+    fn fuse(&mut self) {
+        for blk in self.body.blocks.iter() {
+            if let Terminator::Branch(cond, _, _) = blk.terminator {
+                if let Operand::Reg(c) = self.value(cond) {
+                    let c = ValueId(c);
+                    if self.uses.get(&c) == Some(&1) && matches!(self.binop(c, blk.id), Some((BinOp::Ge | BinOp::Lt | BinOp::Le | BinOp::Gt, _, _))) {
+                        self.fused.insert(c);
+                    }
+                }
+            }
+
+            for instr in blk.instructions.iter() {
+                let Operation::BinOp(BinOp::Xor, l, r) = instr.operation else { continue };
+                let or = match (self.value(l), self.value(r)) {
+                    (Operand::Reg(v), Operand::Imm(m)) | (Operand::Imm(m), Operand::Reg(v)) if m == self.mask => ValueId(v),
+                    _ => continue,
+                };
+
+                if self.uses.get(&or) == Some(&1) && matches!(self.binop(or, blk.id), Some((BinOp::Or, _, _))) {
+                    self.fused.insert(or);
+                }
+            }
+        }
+    }
+
+    /// Turns the constant stores the program starts with into `dw` words
+    fn collect_dw(&mut self) {
+        let cfg = Cfg::new(self.body);
+        let mut blk = &self.body.blocks[0];
+        loop {
+            // the instructions of the program itself start at the first span
+            let start = blk.spans.first().map_or(blk.instructions.len(), |(off, _)| *off);
+            for (i, instr) in blk.instructions[..start].iter().enumerate() {
+                match &instr.operation {
+                    Operation::StoreIndex(_, off, val) => match (self.value(*off), self.value(*val)) {
+                        (Operand::Imm(off), Operand::Imm(val)) if off == self.dw.len() as u128 => {
+                            self.dw.push(val);
+                            self.initialised.insert((blk.id, i));
+                        },
+                        _ => return,
+                    },
+                    Operation::LoadIndex(..) | Operation::Call(..) => return,
+                    _ => {},
+                }
+            }
+
+            match blk.terminator {
+                Terminator::Jump(to) if start == blk.instructions.len() && cfg.predecessors[*to].len() == 1 => blk = &self.body.blocks[*to],
+                _ => return,
+            }
+        }
+    }
+
+    fn resolve(&self, mut v: ValueId) -> ValueId {
+        while let Some(a) = self.aliases.get(&v) {
+            v = *a;
+        }
+
+        v
+    }
+
+    fn value(&self, v: ValueId) -> Operand {
+        let v = self.resolve(v);
+        match self.constants.get(&v) {
+            Some(int) => Operand::Imm(*int),
+            None => Operand::Reg(*v),
+        }
+    }
+
+    fn is_replaced(&self, v: ValueId) -> bool {
+        self.constants.contains_key(&v) || self.aliases.contains_key(&v)
+    }
+
+    /// The operation defining `v` if it is a BinOp in `blk`
+    fn binop(&self, v: ValueId, blk: BlockId) -> Option<(BinOp, Operand, Operand)> {
+        let (b, i) = *self.definitions.get(&v)?;
+        match self.body.blocks[*b].instructions[i].operation {
+            Operation::BinOp(op, l, r) if b == blk => Some((op, self.value(l), self.value(r))),
+            _ => None,
+        }
+    }
+
+    fn unique_name(&mut self, base: &str) -> String {
+        let mut name = base.to_string();
+        while self.taken_names.contains(&name) {
+            name.push('_');
+        }
+
+        self.taken_names.insert(name.clone());
+        name
+    }
+
+    fn new_label(&mut self, base: &str) -> usize {
+        let name = self.unique_name(&format!("{base}_{}", self.labels.len()));
+        self.labels.push(name);
+        self.labels.len() - 1
+    }
+
+    fn vreg(&mut self) -> usize {
+        self.vregs += 1;
+        self.vregs - 1
+    }
+
+    fn emit(&mut self, op: Op, dst: Option<usize>, srcs: Vec<Operand>) {
+        self.code.push(MInst { op, dst, srcs, span: self.span.clone() });
+    }
+
+    fn place(&mut self, label: usize) {
+        self.emit(Op::Label(label), None, Vec::new());
+    }
+
+    fn jump(&mut self, label: usize) {
+        self.emit(Op::Jump(label), None, Vec::new());
+    }
+
+    fn mov(&mut self, dst: usize, src: Operand) {
+        match src {
+            Operand::Imm(_) => self.emit(Op::Imm, Some(dst), vec![src]),
+            Operand::Reg(_) => self.emit(Op::Mov, Some(dst), vec![src]),
+        }
+    }
+
+    fn halt(&mut self) -> usize {
+        if self.halt.is_none() {
+            self.halt = Some(self.new_label("halt"));
+        }
+
+        self.halt.unwrap()
+    }
+
+    /// Whether `cond` only checks that the access `ok` starts with is in bounds, which `LOD` and
+    /// `STR` already do by themselves
+    fn is_bounds_check(&self, cond: ValueId, ok: BlockId, err: BlockId) -> bool {
+        let Some(&(b, _)) = self.definitions.get(&cond) else { return false };
+        let Some((BinOp::Lt, addr, size)) = self.binop(cond, b) else { return false };
+        if self.spills || self.ram_size.map(Operand::Imm) != Some(size) {
+            return false;
+        }
+
+        let err = &self.body.blocks[*err];
+        let reports = err.instructions.iter().any(|i| match &i.operation {
+            Operation::Call(Function::ReportError, args) => {
+                self.value(args[0]) == Operand::Imm(InterpreterError::MemoryAccessOob(0).code()) && self.value(args[2]) == addr
+            },
+            _ => false,
+        });
+
+        let accesses = self.body.blocks[*ok].instructions.iter()
+            .find(|i| !matches!(i.operation, Operation::Integer(_) | Operation::BinOp(..) | Operation::Phi(_)))
+            .is_some_and(|i| match i.operation {
+                Operation::LoadIndex(_, off) | Operation::StoreIndex(_, off, _) => self.value(off) == addr,
+                _ => false,
+            });
+
+        reports && matches!(err.terminator, Terminator::Trap) && accesses
+    }
+
+    fn exit(&self, blk: &Block) -> Exit {
+        match blk.terminator {
+            Terminator::Jump(to) => Exit::Jump(to),
+            Terminator::Branch(cond, if_, else_) => {
+                let (a, b, if_, else_) = match self.value(cond) {
+                    Operand::Imm(c) => return Exit::Jump(if c != 0 { if_ } else { else_ }),
+                    Operand::Reg(c) if self.is_bounds_check(ValueId(c), if_, else_) => return Exit::Jump(if_),
+                    Operand::Reg(c) if self.fused.contains(&ValueId(c)) => match self.binop(ValueId(c), blk.id).unwrap() {
+                        (BinOp::Ge, l, r) => (l, r, if_, else_),
+                        (BinOp::Lt, l, r) => (l, r, else_, if_),
+                        (BinOp::Le, l, r) => (r, l, if_, else_),
+                        (_, l, r) => (r, l, else_, if_),
+                    },
+                    c => (c, Operand::Imm(1), if_, else_),
+                };
+
+                match (a, b) {
+                    (Operand::Imm(a), Operand::Imm(b)) => Exit::Jump(if a >= b { if_ } else { else_ }),
+                    _ => Exit::Branch(a, b, if_, else_),
+                }
+            },
+            Terminator::Return => Exit::Return,
+            Terminator::Trap | Terminator::None => Exit::Trap,
+        }
+    }
+
+    fn select(&mut self) {
+        let body = self.body;
+        let exits: Vec<_> = body.blocks.iter().map(|b| self.exit(b)).collect();
+
+        let mut reachable = vec![false; body.blocks.len()];
+        let mut predecessors = vec![Vec::new(); body.blocks.len()];
+        let mut stack = vec![BlockId(0)];
+        while let Some(b) = stack.pop() {
+            if std::mem::replace(&mut reachable[*b], true) {
+                continue;
+            }
+
+            let successors = match exits[*b] {
+                Exit::Jump(to) => vec![to],
+                Exit::Branch(_, _, if_, else_) => vec![if_, else_],
+                Exit::Return | Exit::Trap => Vec::new(),
+            };
+
+            for s in successors {
+                predecessors[*s].push(b);
+                stack.push(s);
+            }
+        }
+
+        // blocks are chained onto the block they are most likely to follow so that they can fall
+        // through into each other, and since the program stops by running off its end returning
+        // goes last
+        let returns = |b: BlockId| matches!(exits[*b], Exit::Return);
+        let mut placed = vec![false; body.blocks.len()];
+        let mut layout = Vec::new();
+        for blk in body.blocks.iter() {
+            let mut b = blk.id;
+            while reachable[*b] && !placed[*b] && !returns(b) {
+                placed[*b] = true;
+                layout.push(b);
+                b = match exits[*b] {
+                    Exit::Jump(to) | Exit::Branch(_, _, _, to) => to,
+                    Exit::Return | Exit::Trap => break,
+                };
+            }
+        }
+        layout.extend(body.blocks.iter().map(|b| b.id).filter(|b| reachable[**b] && returns(*b)));
+
+        // copies onto branch targets with Phi nodes, which go after everything else
+        let mut stubs = Vec::new();
+
+        for (n, b) in layout.iter().enumerate() {
+            let blk = &body.blocks[**b];
+            let next = layout.get(n + 1).copied();
+            // blocks made for checking an instruction, and whatever comes before the first
+            // instruction they got merged with, belong to the instruction they came from
+            let entry = own_span(blk, 0).or_else(|| match predecessors[**b][..] {
+                [from] => body.blocks[*from].spans.last().map(|(_, s)| s.clone()),
+                _ => None,
+            });
+
+            self.span = entry.clone();
+            self.place(**b);
+
+            for (i, instr) in blk.instructions.iter().enumerate() {
+                self.span = own_span(blk, i).or(entry.clone());
+                self.instruction(*b, i, instr);
+            }
+
+            self.span = blk.spans.last().map(|(_, s)| s.clone()).or(entry);
+            match exits[**b] {
+                Exit::Jump(to) => {
+                    let copies = self.copies(*b, to);
+                    self.parallel_copy(copies);
+                    if Some(to) != next {
+                        self.jump(*to);
+                    }
+                },
+                Exit::Branch(l, r, if_, else_) => {
+                    let copies = self.copies(*b, if_);
+                    let target = if copies.is_empty() {
+                        *if_
+                    } else {
+                        let stub = self.new_label("edge");
+                        stubs.push((stub, copies, if_, self.span.clone()));
+                        stub
+                    };
+                    self.emit(Op::Bge(target), None, vec![l, r]);
+
+                    let copies = self.copies(*b, else_);
+                    self.parallel_copy(copies);
+                    if Some(else_) != next {
+                        self.jump(*else_);
+                    }
+                },
+                Exit::Return => if next.is_some() || !stubs.is_empty() {
+                    let halt = self.halt();
+                    self.jump(halt);
+                },
+                Exit::Trap => {
+                    let halt = self.halt();
+                    self.jump(halt);
+                },
+            }
+        }
+
+        for (stub, copies, to, span) in stubs {
+            self.span = span;
+            self.place(stub);
+            self.parallel_copy(copies);
+            self.jump(*to);
+        }
+
+        if let Some(halt) = self.halt {
+            self.span = None;
+            self.place(halt);
+        }
+    }
+
+    /// Values the Phi nodes of `to` take when coming from `from`, along with the span of the
+    /// instruction that made the value if there is one
+    fn copies(&self, from: BlockId, to: BlockId) -> Vec<(usize, Operand, Option<Span>)> {
+        phi_copies(self.body, from, to).into_iter()
+            .filter(|(dst, _)| !self.is_replaced(*dst) && self.uses.contains_key(dst))
+            .map(|(dst, src)| {
+                let span = self.definitions.get(&src).and_then(|(b, i)| own_span(&self.body.blocks[**b], *i));
+                (*dst, self.value(src), span)
+            })
+            .filter(|(dst, src, _)| *src != Operand::Reg(*dst))
+            .collect()
+    }
+
+    /// Does all `copies` at once, moving a value out of the way whenever they form a cycle
+    fn parallel_copy(&mut self, copies: Vec<(usize, Operand, Option<Span>)>) {
+        let span = self.span.clone();
+        let tmp = self.vreg();
+        let moves = sequentialise(copies.iter().map(|(dst, src, _)| (*dst, *src)).collect(), tmp);
+
+        for (dst, src) in moves {
+            // moving a value out of the way of a cycle isn't part of any copy, and copies of
+            // values with no span of their own, like the constants hoisted into the entry block,
+            // get no comment rather than the one of whatever instruction comes before them
+            self.span = match copies.iter().find(|(d, _, _)| *d == dst) {
+                Some((_, _, from)) => from.clone(),
+                None => span.clone(),
+            };
+            self.mov(dst, src);
+        }
+
+        self.span = span;
+    }
+
+    fn instruction(&mut self, blk: BlockId, index: usize, instr: &Instruction) {
+        let dst = instr.destination;
+        if dst.is_some_and(|d| self.is_replaced(d) || self.fused.contains(&d)) {
+            return;
+        }
+
+        let used = dst.filter(|d| self.uses.contains_key(d)).map(|d| *d);
+        if instr.operation.is_pure() && used.is_none() {
+            return;
+        }
+
+        match &instr.operation {
+            Operation::Integer(_) | Operation::Phi(_) | Operation::Allocate(..) => {},
+            Operation::LoadIndex(_, off) => self.emit(Op::Lod, used, vec![self.value(*off)]),
+            Operation::StoreIndex(..) if self.initialised.contains(&(blk, index)) => {},
+            Operation::StoreIndex(_, off, val) => self.emit(Op::Str, None, vec![self.value(*off), self.value(*val)]),
+            Operation::Call(Function::LastOk, _) => {},
+            Operation::Call(Function::PortRead, args) => self.emit(Op::In, used, vec![self.value(args[0])]),
+            Operation::Call(Function::PortWrite, args) => self.emit(Op::Out, None, vec![self.value(args[0]), self.value(args[1])]),
+            // the kind and the detail go to `%SPECIAL`, and the trap after it halts in case that
+            // port happens to work
+            Operation::Call(Function::ReportError, args) => {
+                let (kind, detail) = (self.value(args[0]), self.value(args[2]));
+                self.emit(Op::Out, None, vec![Operand::Imm(Port::Special as u128), kind]);
+                self.emit(Op::Out, None, vec![Operand::Imm(Port::Special as u128), detail]);
+            },
+            Operation::BinOp(op, l, r) => {
+                let (l, r) = (self.value(*l), self.value(*r));
+                self.binop_code(*op, used.unwrap(), l, r);
+            },
+        }
+    }
+
+    fn binop_code(&mut self, op: BinOp, d: usize, l: Operand, r: Operand) {
+        use Operand::*;
+        let mask = self.mask;
+        let bits = self.body.bits as u128;
+        let sign = 1 << (bits - 1);
+
+        match (op, l, r) {
+            (BinOp::Add, l, r) => self.emit(Op::Add, Some(d), vec![l, r]),
+            (BinOp::Sub, l, Imm(r)) => self.emit(Op::Add, Some(d), vec![l, Imm(r.wrapping_neg() & mask)]),
+            // a - b = ~(~a + b)
+            (BinOp::Sub, l, r) => {
+                self.emit(Op::Nor, Some(d), vec![l, l]);
+                self.emit(Op::Add, Some(d), vec![Reg(d), r]);
+                self.emit(Op::Nor, Some(d), vec![Reg(d), Reg(d)]);
+            },
+            (BinOp::Or, l, r) => {
+                // ORs only ever used by a NOR are left to it
+                self.emit(Op::Nor, Some(d), vec![l, r]);
+                self.emit(Op::Nor, Some(d), vec![Reg(d), Reg(d)]);
+            },
+            (BinOp::Xor, Reg(v), Imm(m)) | (BinOp::Xor, Imm(m), Reg(v)) if m == mask => {
+                match self.nor_operands(v) {
+                    Some((a, b)) => self.emit(Op::Nor, Some(d), vec![a, b]),
+                    None => self.emit(Op::Nor, Some(d), vec![Reg(v), Reg(v)]),
+                }
+            },
+            // a & b = ~(~a | ~b)
+            (BinOp::And, v, Imm(c)) | (BinOp::And, Imm(c), v) => {
+                self.emit(Op::Nor, Some(d), vec![v, v]);
+                self.emit(Op::Nor, Some(d), vec![Reg(d), Imm(!c & mask)]);
+            },
+            (BinOp::And, l, r) => {
+                let t = self.vreg();
+                self.emit(Op::Nor, Some(t), vec![l, l]);
+                self.emit(Op::Nor, Some(d), vec![r, r]);
+                self.emit(Op::Nor, Some(d), vec![Reg(d), Reg(t)]);
+            },
+            // a ^ b = ~(~(a | b) | (a & b))
+            (BinOp::Xor, v, Imm(c)) | (BinOp::Xor, Imm(c), v) => {
+                let t = self.vreg();
+                self.emit(Op::Nor, Some(t), vec![v, v]);
+                self.emit(Op::Nor, Some(t), vec![Reg(t), Imm(!c & mask)]);
+                self.emit(Op::Nor, Some(d), vec![v, Imm(c)]);
+                self.emit(Op::Nor, Some(d), vec![Reg(d), Reg(t)]);
+            },
+            (BinOp::Xor, l, r) => {
+                let (t, u) = (self.vreg(), self.vreg());
+                self.emit(Op::Nor, Some(t), vec![l, l]);
+                self.emit(Op::Nor, Some(u), vec![r, r]);
+                self.emit(Op::Nor, Some(u), vec![Reg(t), Reg(u)]);
+                self.emit(Op::Nor, Some(t), vec![l, r]);
+                self.emit(Op::Nor, Some(d), vec![Reg(t), Reg(u)]);
+            },
+            (BinOp::Shl | BinOp::Shr, _, Imm(k)) if k >= bits => self.emit(Op::Imm, Some(d), vec![Imm(0)]),
+            (BinOp::Shl | BinOp::Shr, v, Imm(0)) => self.mov(d, v),
+            (BinOp::Shl, v, Imm(k)) => {
+                self.emit(Op::Add, Some(d), vec![v, v]);
+                for _ in 1..k {
+                    self.emit(Op::Add, Some(d), vec![Reg(d), Reg(d)]);
+                }
+            },
+            (BinOp::Shr, v, Imm(k)) => {
+                self.emit(Op::Rsh, Some(d), vec![v]);
+                for _ in 1..k {
+                    self.emit(Op::Rsh, Some(d), vec![Reg(d)]);
+                }
+            },
+            (BinOp::Shl | BinOp::Shr, v, amount) => {
+                let t = self.vreg();
+                let (zero, repeat, done) = (self.new_label("zero"), self.new_label("shift"), self.new_label("done"));
+                self.mov(d, v);
+                self.mov(t, amount);
+                self.emit(Op::Bge(zero), None, vec![Reg(t), Imm(bits)]);
+                self.place(repeat);
+                self.emit(Op::Bge(done), None, vec![Imm(0), Reg(t)]);
+                match op {
+                    BinOp::Shl => self.emit(Op::Add, Some(d), vec![Reg(d), Reg(d)]),
+                    _ => self.emit(Op::Rsh, Some(d), vec![Reg(d)]),
+                }
+                self.emit(Op::Add, Some(t), vec![Reg(t), Imm(mask)]);
+                self.jump(repeat);
+                self.place(zero);
+                self.emit(Op::Imm, Some(d), vec![Imm(0)]);
+                self.place(done);
+            },
+            // shift and add, going through the bits of `r` from the lowest one up
+            (BinOp::Mul, l, r) => {
+                let (a, b, t) = (self.vreg(), self.vreg(), self.vreg());
+                let (repeat, skip, done) = (self.new_label("multiply"), self.new_label("skip"), self.new_label("done"));
+                self.mov(a, l);
+                self.mov(b, r);
+                self.emit(Op::Imm, Some(d), vec![Imm(0)]);
+                self.place(repeat);
+                self.emit(Op::Bge(done), None, vec![Imm(0), Reg(b)]);
+                // `b` is even if clearing its lowest bit leaves it the same
+                self.emit(Op::Rsh, Some(t), vec![Reg(b)]);
+                self.emit(Op::Add, Some(t), vec![Reg(t), Reg(t)]);
+                self.emit(Op::Bge(skip), None, vec![Reg(t), Reg(b)]);
+                self.emit(Op::Add, Some(d), vec![Reg(d), Reg(a)]);
+                self.place(skip);
+                self.emit(Op::Add, Some(a), vec![Reg(a), Reg(a)]);
+                self.emit(Op::Rsh, Some(b), vec![Reg(b)]);
+                self.jump(repeat);
+                self.place(done);
+            },
+            // restoring division, shifting `l` into the remainder from its highest bit down while
+            // the bits freed up at the bottom of it collect the quotient
+            (BinOp::Div | BinOp::Mod, l, r) => {
+                let (n, rem, count, old) = (self.vreg(), self.vreg(), self.vreg(), self.vreg());
+                let (zero, repeat, carry, shifted, subtract, done) = (
+                    self.new_label("by_zero"), self.new_label("divide"), self.new_label("carry"),
+                    self.new_label("shifted"), self.new_label("subtract"), self.new_label("done"),
+                );
+
+                self.emit(Op::Bge(zero), None, vec![Imm(0), r]);
+                let minus_r = match r {
+                    Imm(r) => Imm(r.wrapping_neg() & mask),
+                    Reg(_) => {
+                        let t = self.vreg();
+                        self.emit(Op::Nor, Some(t), vec![r, r]);
+                        self.emit(Op::Add, Some(t), vec![Reg(t), Imm(1)]);
+                        Reg(t)
+                    },
+                };
+                self.mov(n, l);
+                self.emit(Op::Imm, Some(rem), vec![Imm(0)]);
+                self.emit(Op::Imm, Some(count), vec![Imm(bits)]);
+
+                self.place(repeat);
+                self.emit(Op::Bge(done), None, vec![Imm(0), Reg(count)]);
+                self.emit(Op::Add, Some(count), vec![Reg(count), Imm(mask)]);
+                self.mov(old, Reg(rem));
+                self.emit(Op::Add, Some(rem), vec![Reg(rem), Reg(rem)]);
+                self.emit(Op::Bge(carry), None, vec![Reg(n), Imm(sign)]);
+                self.jump(shifted);
+                self.place(carry);
+                self.emit(Op::Add, Some(rem), vec![Reg(rem), Imm(1)]);
+                self.place(shifted);
+                self.emit(Op::Add, Some(n), vec![Reg(n), Reg(n)]);
+                // a remainder which had its highest bit set is past anything `r` can be now
+                self.emit(Op::Bge(subtract), None, vec![Reg(old), Imm(sign)]);
+                self.emit(Op::Bge(subtract), None, vec![Reg(rem), r]);
+                self.jump(repeat);
+                self.place(subtract);
+                self.emit(Op::Add, Some(rem), vec![Reg(rem), minus_r]);
+                self.emit(Op::Add, Some(n), vec![Reg(n), Imm(1)]);
+                self.jump(repeat);
+
+                // reported like `ReportError` does
+                self.place(zero);
+                self.emit(Op::Out, None, vec![Imm(Port::Special as u128), Imm(InterpreterError::DivisionByZero.code())]);
+                self.emit(Op::Out, None, vec![Imm(Port::Special as u128), Imm(0)]);
+                let halt = self.halt();
+                self.jump(halt);
+
+                self.place(done);
+                self.mov(d, Reg(if op == BinOp::Div { n } else { rem }));
+            },
+            (BinOp::Ge | BinOp::Lt | BinOp::Le | BinOp::Gt, l, r) => self.compare(op, d, l, r),
+            // signed comparisons are unsigned ones with the sign bit flipped
+            (BinOp::SGe | BinOp::SLt | BinOp::SLe | BinOp::SGt, l, r) => {
+                let flip = |s: &mut Self, v: Operand| match v {
+                    Imm(v) => Imm(v ^ sign),
+                    Reg(_) => {
+                        let t = s.vreg();
+                        s.emit(Op::Add, Some(t), vec![v, Imm(sign)]);
+                        Reg(t)
+                    },
+                };
+
+                let (l, r) = (flip(self, l), flip(self, r));
+                let op = match op {
+                    BinOp::SGe => BinOp::Ge,
+                    BinOp::SLt => BinOp::Lt,
+                    BinOp::SLe => BinOp::Le,
+                    _ => BinOp::Gt,
+                };
+                self.compare(op, d, l, r);
+            },
+            // equal if neither is smaller than the other one
+            (BinOp::Eq | BinOp::Ne, l, r) => {
+                let (same, different) = if op == BinOp::Eq { (1, 0) } else { (0, 1) };
+                let (first, second) = (self.new_label("skip"), self.new_label("skip"));
+                self.emit(Op::Imm, Some(d), vec![Imm(same)]);
+                self.emit(Op::Bge(first), None, vec![l, r]);
+                self.emit(Op::Imm, Some(d), vec![Imm(different)]);
+                self.place(first);
+                self.emit(Op::Bge(second), None, vec![r, l]);
+                self.emit(Op::Imm, Some(d), vec![Imm(different)]);
+                self.place(second);
+            },
+            (op, ..) => unimplemented!("`{op}` has no URCL lowering"),
+        }
+    }
+
+    /// Operands of the OR that `v` is, if it was left to the NOR using it
+    fn nor_operands(&self, v: usize) -> Option<(Operand, Operand)> {
+        let v = ValueId(v);
+        if !self.fused.contains(&v) {
+            return None;
+        }
+
+        let (b, _) = self.definitions[&v];
+        self.binop(v, b).map(|(_, l, r)| (l, r))
+    }
+
+    /// Sets `d` to 1 if `l` compares to `r` like `op` says, and to 0 otherwise
+    fn compare(&mut self, op: BinOp, d: usize, l: Operand, r: Operand) {
+        let (l, r, ge, lt) = match op {
+            BinOp::Ge => (l, r, 1, 0),
+            BinOp::Lt => (l, r, 0, 1),
+            BinOp::Le => (r, l, 1, 0),
+            _ => (r, l, 0, 1),
+        };
+
+        let skip = self.new_label("skip");
+        self.emit(Op::Imm, Some(d), vec![Operand::Imm(ge)]);
+        self.emit(Op::Bge(skip), None, vec![l, r]);
+        self.emit(Op::Imm, Some(d), vec![Operand::Imm(lt)]);
+        self.place(skip);
+    }
+}
+
+/// Span of the URCL instruction that instruction `index` of `blk` came from, unless it comes
+/// before the first one
+fn own_span(blk: &Block, index: usize) -> Option<Span> {
+    blk.spans.iter().take_while(|(off, _)| *off <= index).last().map(|(_, span)| span.clone())
+}
+
+/// The virtual registers of some code, numbered in the order they first show up
+struct Lifetimes {
+    vregs: Vec<usize>,
+    /// Points every virtual register is live at
+    ranges: Vec<Vec<(usize, usize)>>,
+    /// Virtual registers every one of them is copied from or into
+    related: Vec<Vec<usize>>,
+}
+
+/// Works out where the virtual registers of `code` are live for [`allocate_ranges`].
+///
+/// Every instruction gets two points, one where it reads and one where it writes, so that the
+/// result can take the register of an operand that dies there.
+fn live_ranges(code: &[MInst]) -> Lifetimes {
+    let mut dense = HashMap::new();
+    let mut vregs = Vec::new();
+    for inst in code.iter() {
+        for v in inst.reads().chain(inst.dst) {
+            dense.entry(v).or_insert_with(|| {
+                vregs.push(v);
+                vregs.len() - 1
+            });
+        }
+    }
+
+    let labels: HashMap<usize, usize> = code.iter().enumerate()
+        .filter_map(|(i, inst)| match inst.op {
+            Op::Label(l) => Some((l, i)),
+            _ => None,
+        })
+        .collect();
+
+    let n = code.len();
+    let words = vregs.len().div_ceil(64);
+    let mut live_in = vec![vec![0_u64; words]; n];
+    let mut live_out = vec![vec![0_u64; words]; n];
+
+    let set = |bits: &mut [u64], v: usize| bits[v / 64] |= 1 << (v % 64);
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in (0..n).rev() {
+            let successors = match code[i].op {
+                Op::Jump(l) => vec![labels[&l]],
+                Op::Bge(l) => vec![labels[&l], i + 1],
+                _ => vec![i + 1],
+            };
+
+            let mut out = vec![0; words];
+            for s in successors.into_iter().filter(|s| *s < n) {
+                for (o, l) in out.iter_mut().zip(live_in[s].iter()) {
+                    *o |= l;
+                }
+            }
+
+            let mut in_ = out.clone();
+            if let Some(d) = code[i].dst {
+                in_[dense[&d] / 64] &= !(1 << (dense[&d] % 64));
+            }
+
+            for v in code[i].reads() {
+                set(&mut in_, dense[&v]);
+            }
+
+            if in_ != live_in[i] || out != live_out[i] {
+                changed = true;
+                live_in[i] = in_;
+                live_out[i] = out;
+            }
+        }
+    }
+
+    // the points every value is live at as sorted ranges that don't touch
+    let mut ranges: Vec<Vec<(usize, usize)>> = vec![Vec::new(); vregs.len()];
+    let mut touch = |v: usize, at: usize| match ranges[v].last_mut() {
+        Some((_, end)) if *end + 1 >= at => *end = at.max(*end),
+        _ => ranges[v].push((at, at)),
+    };
+
+    for (i, inst) in code.iter().enumerate() {
+        for (bits, at) in [(&live_in[i], 2 * i), (&live_out[i], 2 * i + 1)] {
+            for (w, word) in bits.iter().enumerate() {
+                let mut word = *word;
+                while word != 0 {
+                    touch(w * 64 + word.trailing_zeros() as usize, at);
+                    word &= word - 1;
+                }
+            }
+        }
+
+        if let Some(d) = inst.dst {
+            touch(dense[&d], 2 * i + 1);
+        }
+    }
+
+    // values copied into each other, which are best off sharing a register
+    let mut related = vec![Vec::new(); vregs.len()];
+    for inst in code.iter() {
+        if let (Op::Mov, Some(d), Some(Operand::Reg(s))) = (inst.op, inst.dst, inst.srcs.first()) {
+            related[dense[&d]].push(dense[s]);
+            related[dense[s]].push(dense[&d]);
+        }
+    }
+
+    Lifetimes { vregs, ranges, related }
+}
+
+/// Puts the registers of `locations` into `code`, loading spilled values into the `scratch`
+/// registers right before they are read and storing them right after they are written. Stack
+/// slots live in RAM from `base` on.
+fn assign(code: Vec<MInst>, locations: &HashMap<usize, Location>, scratch: [usize; 2], base: u128) -> Vec<MInst> {
+    let slot = |v: &usize| match locations[v] {
+        Location::Stack(s) => Some(Operand::Imm(base + s as u128)),
+        Location::Register(_) => None,
+    };
+    let register = |v: &usize| match locations[v] {
+        Location::Register(r) => Some(r),
+        Location::Stack(_) => None,
+    };
+    let mut out = Vec::with_capacity(code.len());
+
+    for mut inst in code {
+        // copies from and into memory don't need a register in between
+        if matches!(inst.op, Op::Mov | Op::Imm) {
+            let dst = inst.dst.unwrap();
+            match (slot(&dst), inst.srcs[0]) {
+                (Some(to), Operand::Reg(src)) if slot(&src).is_none() => {
+                    out.push(MInst { op: Op::Str, dst: None, srcs: vec![to, Operand::Reg(register(&src).unwrap())], span: inst.span });
+                    continue;
+                },
+                (Some(to), Operand::Imm(int)) => {
+                    out.push(MInst { op: Op::Str, dst: None, srcs: vec![to, Operand::Imm(int)], span: inst.span });
+                    continue;
+                },
+                (None, Operand::Reg(src)) if slot(&src).is_some() => {
+                    out.push(MInst { op: Op::Lod, dst: Some(register(&dst).unwrap()), srcs: vec![slot(&src).unwrap()], span: inst.span });
+                    continue;
+                },
+                _ => {},
+            }
+        }
+
+        let mut loaded: Vec<usize> = Vec::new();
+        for src in inst.srcs.iter_mut() {
+            let Operand::Reg(v) = *src else { continue };
+            match slot(&v) {
+                Some(addr) => {
+                    let i = loaded.iter().position(|l| *l == v).unwrap_or_else(|| {
+                        out.push(MInst { op: Op::Lod, dst: Some(scratch[loaded.len()]), srcs: vec![addr], span: inst.span.clone() });
+                        loaded.push(v);
+                        loaded.len() - 1
+                    });
+                    *src = Operand::Reg(scratch[i]);
+                },
+                None => *src = Operand::Reg(register(&v).unwrap()),
+            }
+        }
+
+        let spilled = inst.dst.and_then(|d| slot(&d));
+        inst.dst = inst.dst.map(|d| register(&d).unwrap_or(scratch[0]));
+
+        let (dst, span) = (inst.dst, inst.span.clone());
+        if inst.op != Op::Mov || inst.srcs[0] != Operand::Reg(dst.unwrap()) {
+            out.push(inst);
+        }
+
+        if let Some(addr) = spilled {
+            out.push(MInst { op: Op::Str, dst: None, srcs: vec![addr, Operand::Reg(dst.unwrap())], span });
+        }
+    }
+
+    out
+}
+
+/// Cleans up the jumps left behind once copies went away: jumps to jumps go straight to where
+/// they end up, jumps to the next instruction and code that can't be reached are dropped
+fn tidy(mut code: Vec<MInst>) -> Vec<MInst> {
+    let target = |inst: &MInst| match inst.op {
+        Op::Bge(l) | Op::Jump(l) => Some(l),
+        _ => None,
+    };
+
+    loop {
+        let len = code.len();
+
+        // labels right in front of a jump
+        let mut forward = HashMap::new();
+        for (i, inst) in code.iter().enumerate() {
+            let Op::Label(l) = inst.op else { continue };
+            if let Some(MInst { op: Op::Jump(to), .. }) = code[i..].iter().find(|i| !matches!(i.op, Op::Label(_))) {
+                if *to != l {
+                    forward.insert(l, *to);
+                }
+            }
+        }
+
+        for inst in code.iter_mut() {
+            if let Op::Bge(l) | Op::Jump(l) = &mut inst.op {
+                // jumps that only ever go round in circles are left as they are
+                let mut seen = HashSet::new();
+                while let Some(to) = forward.get(l) {
+                    if !seen.insert(*l) {
+                        break;
+                    }
+
+                    *l = *to;
+                }
+            }
+        }
+
+        let referenced: HashSet<usize> = code.iter().filter_map(target).collect();
+        let mut tidied = Vec::with_capacity(code.len());
+        let mut reachable = true;
+        for (i, inst) in code.iter().enumerate() {
+            match inst.op {
+                Op::Label(l) if referenced.contains(&l) => reachable = true,
+                Op::Label(_) => continue,
+                _ if !reachable => continue,
+                _ => {},
+            }
+
+            let next_labels = code[i + 1..].iter().map_while(|i| match i.op {
+                Op::Label(l) => Some(l),
+                _ => None,
+            });
+            if target(inst).is_some_and(|l| next_labels.clone().any(|n| n == l)) {
+                continue;
+            }
+
+            reachable &= !matches!(inst.op, Op::Jump(_));
+            tidied.push(i);
+        }
+
+        let mut keep = vec![false; code.len()];
+        for i in tidied {
+            keep[i] = true;
+        }
+
+        let mut keep = keep.into_iter();
+        code.retain(|_| keep.next().unwrap());
+        if code.len() == len {
+            return code;
+        }
+    }
+}
+
+/// Translates `ssa` back into URCL which only needs `origin.minreg` registers, made of the core
+/// instructions along with `IMM`, `MOV`, `IN` and `OUT`.
+///
+/// The checks URCL does by itself, like ports that don't work and accesses past the RAM, are left
+/// to the instructions doing them. Values that don't fit into the registers are spilled into RAM
+/// right after the one of the program, in which case the bounds checks have to stay.
+///
+/// Errors the program stops with write their code and then their detail to `%SPECIAL`, and halt.
+///
+/// Every instruction ends with a comment quoting the source instruction it came from. Fails if
+/// the program uses memory other than the RAM or resizes it, which URCL has no way to do.
+pub fn to_urcl(ssa: (Body, usize, usize), origin: &Origin) -> Result<String, Error<UrclError>> {
+    let mut builder = Builder::from_ssa(ssa);
+    mem2reg(&mut builder);
+    dce(&mut builder);
+    let (body, values, _) = builder.get_ssa();
+
+    let mut minreg = origin.minreg;
+    let mut spills = false;
+    let (selector, code, locations, stack) = loop {
+        let mut selector = Selector::new(&body, origin, values, spills)?;
+        selector.select();
+        let code = std::mem::take(&mut selector.code);
+
+        // `r0` is always zero, and the last two registers are kept for loading spilled values
+        let reserved = if spills { vec![0, minreg - 1, minreg] } else { vec![0] };
+        let lifetimes = live_ranges(&code);
+        let alloc = allocate_ranges(&lifetimes.ranges, &lifetimes.related, &Target { registers: minreg + 1, reserved });
+        if alloc.stack == 0 || spills {
+            let locations = lifetimes.vregs.into_iter().zip(alloc.locations).filter_map(|(v, l)| Some((v, l?))).collect();
+            break (selector, code, locations, alloc.stack);
+        }
+
+        spills = true;
+        minreg = minreg.max(2);
+    };
+
+    let ram_size = selector.ram_size.unwrap_or(0);
+    let code = tidy(assign(code, &locations, [minreg.saturating_sub(1), minreg], ram_size));
+
+    // labels that nothing refers to are left out
+    let referenced: HashSet<usize> = code.iter()
+        .filter_map(|i| match i.op {
+            Op::Bge(l) | Op::Jump(l) => Some(l),
+            _ => None,
+        })
+        .collect();
+
+    let used_registers = code.iter().flat_map(|i| i.reads().chain(i.dst)).max().unwrap_or(0);
+
+    let mut urcl = String::new();
+    writeln!(urcl, "// generated by urcl-io").unwrap();
+    writeln!(urcl, "bits {}", body.bits).unwrap();
+    writeln!(urcl, "minreg {used_registers}").unwrap();
+    writeln!(urcl, "minheap {}", ram_size - selector.dw.len() as u128 + stack as u128).unwrap();
+    writeln!(urcl, "minstack 0").unwrap();
+    for w in selector.dw.iter() {
+        writeln!(urcl, "dw {}", immediate(*w)).unwrap();
+    }
+
+    let source_line = |span: &Span| {
+        let line = origin.source[..span.start].matches('\n').count() + 1;
+        let text = origin.source[span.clone()].lines().next().unwrap_or("").trim();
+        format!("// {line}: {text}")
+    };
+
+    writeln!(urcl).unwrap();
+    for inst in code.iter() {
+        let reg = |r: Option<usize>| format!("r{}", r.unwrap_or(0));
+        let src = |i: usize| match inst.srcs[i] {
+            Operand::Reg(r) => format!("r{r}"),
+            Operand::Imm(int) => immediate(int),
+        };
+        let label = |l: usize| format!(".{}", selector.labels[l]);
+
+        let text = match inst.op {
+            Op::Label(l) if referenced.contains(&l) => {
+                writeln!(urcl, "{}", label(l)).unwrap();
+                continue;
+            },
+            Op::Label(_) => continue,
+            Op::Add => format!("add {} {} {}", reg(inst.dst), src(0), src(1)),
+            Op::Nor => format!("nor {} {} {}", reg(inst.dst), src(0), src(1)),
+            Op::Rsh => format!("rsh {} {}", reg(inst.dst), src(0)),
+            Op::Lod => format!("lod {} {}", reg(inst.dst), src(0)),
+            Op::Str => format!("str {} {}", src(0), src(1)),
+            Op::Imm => format!("imm {} {}", reg(inst.dst), src(0)),
+            Op::Mov => format!("mov {} {}", reg(inst.dst), src(0)),
+            Op::In  => format!("in {} {}", reg(inst.dst), src(0)),
+            Op::Out => format!("out {} {}", src(0), src(1)),
+            Op::Bge(l) => format!("bge {} {} {}", label(l), src(0), src(1)),
+            Op::Jump(l) => format!("bge {} r0 r0", label(l)),
+        };
+
+        match &inst.span {
+            Some(span) => writeln!(urcl, "    {text:<24}{}", source_line(span)).unwrap(),
+            None => writeln!(urcl, "    {text}").unwrap(),
+        }
+    }
+
+    Ok(urcl)
+}
+
+/// Writes a word so that the lexer can read it back, which only goes up to `i128::MAX`
+fn immediate(int: u128) -> String {
+    if int > i128::MAX as u128 {
+        (int as i128).to_string()
+    } else {
+        int.to_string()
+    }
+}
@@ -0,0 +1,397 @@
+use crate::compiler::{error::*, common::*, engine::*, backend::{ssa::*, codegen::{RAM, REG}}};
+use derivative::Derivative;
+use logos::Span;
+
+/// Index of a register, which every SSA value gets one of
+type Reg = u32;
+/// Offset into the code
+type Addr = u32;
+
+/// One bytecode instruction. Phi nodes are gone, their values are copied over on the edges
+/// going into their block instead.
+#[derive(Debug, Clone, Copy)]
+pub enum Op<W: Word> {
+    Const { dst: Reg, imm: W },
+    Copy { dst: Reg, src: Reg },
+
+    Add { dst: Reg, l: Reg, r: Reg },
+    Sub { dst: Reg, l: Reg, r: Reg },
+    And { dst: Reg, l: Reg, r: Reg },
+    Or  { dst: Reg, l: Reg, r: Reg },
+    Xor { dst: Reg, l: Reg, r: Reg },
+    Shr { dst: Reg, l: Reg, r: Reg },
+    Eq  { dst: Reg, l: Reg, r: Reg },
+    Ne  { dst: Reg, l: Reg, r: Reg },
+    Lt  { dst: Reg, l: Reg, r: Reg },
+    Ge  { dst: Reg, l: Reg, r: Reg },
+    /// Every other operation, which may trap
+    BinOp { op: BinOp, dst: Reg, l: Reg, r: Reg },
+
+    Alloc { var: u32, size: Reg },
+    Load { dst: Reg, var: u32, off: Reg },
+    Store { var: u32, off: Reg, val: Reg },
+
+    PortRead { dst: Reg, port: Reg },
+    PortWrite { port: Reg, data: Reg },
+    LastOk { dst: Reg },
+    ReportError { kind: Reg, pc: Reg, detail: Reg },
+
+    /// `count` is the amount of URCL instructions the block that ends here made up
+    Jump { to: Addr, count: u32 },
+    Branch { cond: Reg, if_: Addr, else_: Addr, count: u32 },
+    Return { count: u32 },
+    Trap,
+}
+
+/// A body lowered into bytecode
+pub struct Program<W: Word> {
+    pub code: Vec<Op<W>>,
+    /// Source span of every instruction in `code`
+    pub spans: Vec<Span>,
+    /// Source span of every URCL instruction, for `ReportError`
+    pub source: Vec<Span>,
+    pub registers: usize,
+    pub variables: usize,
+    pub bits: usize,
+}
+
+/// Lowers `ssa` into bytecode. Every block is laid out in order, and a branch into a block with
+/// Phi nodes goes through a stub copying the values over first.
+pub fn lower<W: Word>(ssa: &(Body, usize, usize)) -> Program<W> {
+    let (body, values, variables) = ssa;
+    let scratch = *values as Reg;
+    let mut registers = *values;
+
+    let mut code = Vec::new();
+    let mut spans = Vec::new();
+    // where every block starts, followed by the stubs
+    let mut starts = vec![0; body.blocks.len()];
+
+    // copies every Phi node of `to` takes from `from`, all at once
+    let edge_copies = |from: BlockId, to: BlockId, code: &mut Vec<Op<W>>, registers: &mut usize| {
+        let copies: Vec<_> = body.blocks[*to].instructions.iter()
+            .map_while(|i| match &i.operation {
+                Operation::Phi(branches) => Some((*i.destination.unwrap(), *branches.iter().find(|(_, b)| *b == from).unwrap().0)),
+                _ => None,
+            })
+            .collect();
+
+        if copies.iter().any(|(dst, _)| copies.iter().any(|(_, src)| src == dst)) {
+            *registers = (*registers).max(*values + copies.len());
+            for (i, (_, src)) in copies.iter().enumerate() {
+                code.push(Op::Copy { dst: scratch + i as Reg, src: *src as Reg });
+            }
+
+            for (i, (dst, _)) in copies.iter().enumerate() {
+                code.push(Op::Copy { dst: *dst as Reg, src: scratch + i as Reg });
+            }
+        } else {
+            for (dst, src) in copies {
+                code.push(Op::Copy { dst: dst as Reg, src: src as Reg });
+            }
+        }
+    };
+
+    let has_phis = |b: BlockId| body.blocks[*b].instructions.first().is_some_and(|i| matches!(i.operation, Operation::Phi(_)));
+
+    for blk in body.blocks.iter() {
+        starts[*blk.id] = code.len();
+
+        for (i, instr) in blk.instructions.iter().enumerate() {
+            let dst = instr.destination.map_or(0, |d| *d as Reg);
+            let reg = |v: &ValueId| **v as Reg;
+
+            let op = match &instr.operation {
+                Operation::Phi(_) => continue,
+                Operation::Integer(int) => Op::Const { dst, imm: W::from_u128(*int) },
+                Operation::BinOp(op, l, r) => {
+                    let (l, r) = (reg(l), reg(r));
+                    match op {
+                        BinOp::Add => Op::Add { dst, l, r },
+                        BinOp::Sub => Op::Sub { dst, l, r },
+                        BinOp::And => Op::And { dst, l, r },
+                        BinOp::Or  => Op::Or { dst, l, r },
+                        BinOp::Xor => Op::Xor { dst, l, r },
+                        BinOp::Shr => Op::Shr { dst, l, r },
+                        BinOp::Eq  => Op::Eq { dst, l, r },
+                        BinOp::Ne  => Op::Ne { dst, l, r },
+                        BinOp::Lt  => Op::Lt { dst, l, r },
+                        BinOp::Ge  => Op::Ge { dst, l, r },
+                        op => Op::BinOp { op: *op, dst, l, r },
+                    }
+                },
+                Operation::Allocate(var, size) => Op::Alloc { var: **var as u32, size: reg(size) },
+                Operation::LoadIndex(var, off) => Op::Load { dst, var: **var as u32, off: reg(off) },
+                Operation::StoreIndex(var, off, val) => Op::Store { var: **var as u32, off: reg(off), val: reg(val) },
+                Operation::Call(Function::PortRead, args) => Op::PortRead { dst, port: reg(&args[0]) },
+                Operation::Call(Function::PortWrite, args) => Op::PortWrite { port: reg(&args[0]), data: reg(&args[1]) },
+                Operation::Call(Function::LastOk, _) => Op::LastOk { dst },
+                Operation::Call(Function::ReportError, args) => Op::ReportError { kind: reg(&args[0]), pc: reg(&args[1]), detail: reg(&args[2]) },
+            };
+
+            code.push(op);
+            spans.push(blk.span_at(i).cloned().unwrap_or_default());
+        }
+
+        let count = blk.spans.len() as u32;
+        let span = blk.spans.last().map(|(_, s)| s.clone()).unwrap_or_default();
+        match blk.terminator {
+            Terminator::Jump(to) => {
+                edge_copies(blk.id, to, &mut code, &mut registers);
+                code.push(Op::Jump { to: *to as Addr, count });
+            },
+            Terminator::Branch(cond, if_, else_) => {
+                // stubs get labels after the blocks
+                let mut target = |to: BlockId| if has_phis(to) {
+                    starts.push(0);
+                    starts.len() as Addr - 1
+                } else {
+                    *to as Addr
+                };
+                let (t, f) = (target(if_), target(else_));
+                code.push(Op::Branch { cond: *cond as Reg, if_: t, else_: f, count });
+
+                for (label, to) in [(t, if_), (f, else_)] {
+                    if label as usize >= body.blocks.len() {
+                        starts[label as usize] = code.len();
+                        edge_copies(blk.id, to, &mut code, &mut registers);
+                        code.push(Op::Jump { to: *to as Addr, count: 0 });
+                    }
+                }
+            },
+            Terminator::Return => code.push(Op::Return { count }),
+            Terminator::Trap | Terminator::None => code.push(Op::Trap),
+        }
+
+        spans.resize(code.len(), span);
+    }
+
+    if code.is_empty() {
+        code.push(Op::Return { count: 0 });
+        spans.push(Span::default());
+    }
+
+    for op in code.iter_mut() {
+        match op {
+            Op::Jump { to, .. } => *to = starts[*to as usize] as Addr,
+            Op::Branch { if_, else_, .. } => {
+                *if_ = starts[*if_ as usize] as Addr;
+                *else_ = starts[*else_ as usize] as Addr;
+            },
+            _ => {},
+        }
+    }
+
+    Program {
+        code,
+        spans,
+        source: body.spans.clone(),
+        registers,
+        variables: *variables,
+        bits: body.bits,
+    }
+}
+
+/// Runs bytecode made by [`lower`]
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct Vm<W: Word> {
+    #[derivative(Debug="ignore")]
+    program: Program<W>,
+    #[derivative(Debug="ignore")]
+    device: Box<dyn Device>,
+
+    pc: usize,
+    pub inst_count: usize,
+    last_ok: bool,
+
+    mask: W,
+    regs: Vec<W>,
+    variables: Vec<Vec<W>>,
+    /// Whether every variable is still around after optimisation
+    kept: Vec<bool>,
+}
+
+impl<W: Word> Vm<W> {
+    pub fn new(ssa: (Body, usize, usize)) -> Self {
+        let program = lower(&ssa);
+        Self {
+            mask: W::mask(program.bits),
+            regs: vec![W::ZERO; program.registers],
+            variables: vec![Vec::new(); program.variables],
+            kept: (0..program.variables).map(|v| ssa.0.allocates(VariableId(v))).collect(),
+            program,
+            device: Box::new(NoDevice),
+
+            pc: 0,
+            inst_count: 0,
+            last_ok: true,
+        }
+    }
+
+    fn variable(&self, var: VariableId, index: u128) -> Result<W, InterpreterError> {
+        self.variables.get(*var)
+            .and_then(|v| v.get(usize::try_from(index).ok()?).copied())
+            .ok_or(InterpreterError::MemoryAccessOob(index))
+    }
+
+    fn variable_mut(&mut self, var: VariableId, index: u128) -> Result<&mut W, InterpreterError> {
+        self.variables.get_mut(*var)
+            .and_then(|v| v.get_mut(usize::try_from(index).ok()?))
+            .ok_or(InterpreterError::MemoryAccessOob(index))
+    }
+
+    /// Fails when `var` got promoted into values, so there's nothing left to look at
+    fn kept(&self, var: VariableId) -> Result<(), InterpreterError> {
+        if self.kept[*var] { Ok(()) } else { Err(InterpreterError::OptimisedAway) }
+    }
+}
+
+impl<W: Word> Engine for Vm<W> {
+    fn step(&mut self) -> StepResult {
+        self.run(1)
+    }
+
+    fn run(&mut self, budget: usize) -> StepResult {
+        let end = self.inst_count.saturating_add(budget);
+        let Self { program, device, pc: saved_pc, inst_count, last_ok, regs, variables, .. } = self;
+        let code = &program.code[..];
+        let mut pc = *saved_pc;
+
+        macro_rules! reg {
+            ($r: expr) => {
+                // SAFETY: `lower` only ever uses registers below `program.registers`
+                unsafe { *regs.get_unchecked($r as usize) }
+            };
+            ($r: expr => $v: expr) => {{
+                let v = $v;
+                // SAFETY: same as above
+                unsafe { *regs.get_unchecked_mut($r as usize) = v; }
+            }};
+        }
+
+        macro_rules! error {
+            ($kind: expr) => {{
+                *saved_pc = pc - 1;
+                return StepResult::Error(Error { kind: $kind, span: program.spans[pc - 1].clone() });
+            }};
+        }
+
+        loop {
+            // SAFETY: every block ends in a jump, branch, return or trap to somewhere in `code`
+            let op = unsafe { *code.get_unchecked(pc) };
+            pc += 1;
+
+            match op {
+                Op::Const { dst, imm } => reg!(dst => imm),
+                Op::Copy { dst, src } => reg!(dst => reg!(src)),
+
+                Op::Add { dst, l, r } => reg!(dst => reg!(l).wrapping_add(reg!(r))),
+                Op::Sub { dst, l, r } => reg!(dst => reg!(l).wrapping_sub(reg!(r))),
+                Op::And { dst, l, r } => reg!(dst => reg!(l) & reg!(r)),
+                Op::Or  { dst, l, r } => reg!(dst => reg!(l) | reg!(r)),
+                Op::Xor { dst, l, r } => reg!(dst => reg!(l) ^ reg!(r)),
+                Op::Shr { dst, l, r } => reg!(dst => reg!(l).shift_right(reg!(r))),
+                Op::Eq  { dst, l, r } => reg!(dst => W::from(reg!(l) == reg!(r))),
+                Op::Ne  { dst, l, r } => reg!(dst => W::from(reg!(l) != reg!(r))),
+                Op::Lt  { dst, l, r } => reg!(dst => W::from(reg!(l) < reg!(r))),
+                Op::Ge  { dst, l, r } => reg!(dst => W::from(reg!(l) >= reg!(r))),
+                Op::BinOp { op, dst, l, r } => match op.operate(reg!(l), reg!(r), program.bits) {
+                    Some(v) => reg!(dst => v),
+                    None => error!(InterpreterError::DivisionByZero),
+                },
+
+                Op::Alloc { var, size } => variables[var as usize] = vec![W::ZERO; reg!(size).as_usize()],
+                Op::Load { dst, var, off } => match variables[var as usize].get(reg!(off).as_usize()) {
+                    Some(v) => reg!(dst => *v),
+                    None => error!(InterpreterError::MemoryAccessOob(reg!(off).to_u128())),
+                },
+                Op::Store { var, off, val } => match variables[var as usize].get_mut(reg!(off).as_usize()) {
+                    Some(v) => *v = reg!(val),
+                    None => error!(InterpreterError::MemoryAccessOob(reg!(off).to_u128())),
+                },
+
+                Op::PortRead { dst, port } => {
+                    let res = device.read(reg!(port).to_u128());
+                    *last_ok = res.is_ok();
+                    reg!(dst => W::from_u128(res.unwrap_or(0)));
+                },
+                Op::PortWrite { port, data } => *last_ok = device.write(reg!(port).to_u128(), reg!(data).to_u128()).is_ok(),
+                Op::LastOk { dst } => reg!(dst => W::from(*last_ok)),
+                Op::ReportError { kind, pc: at, detail } => {
+                    *saved_pc = pc - 1;
+                    return StepResult::Error(Error {
+                        kind: InterpreterError::from_code(reg!(kind).to_u128(), reg!(detail).to_u128()),
+                        span: program.source.get(reg!(at).as_usize()).cloned().unwrap_or_default(),
+                    });
+                },
+
+                Op::Jump { to, count } => {
+                    pc = to as usize;
+                    *inst_count += count as usize;
+                    if *inst_count >= end {
+                        break;
+                    }
+                },
+                Op::Branch { cond, if_, else_, count } => {
+                    pc = if reg!(cond) != W::ZERO { if_ } else { else_ } as usize;
+                    *inst_count += count as usize;
+                    if *inst_count >= end {
+                        break;
+                    }
+                },
+                Op::Return { count } => {
+                    *inst_count += count as usize;
+                    *saved_pc = pc - 1;
+                    return StepResult::Halted;
+                },
+                Op::Trap => unreachable!("trapped without reporting an error"),
+            }
+        }
+
+        *saved_pc = pc;
+        StepResult::Running
+    }
+
+    fn register(&self, index: usize) -> Result<u128, InterpreterError> {
+        if index == 0 {
+            return Ok(0);
+        }
+
+        self.kept(REG)?;
+        self.variable(REG, index as u128 - 1)
+            .map(W::to_u128)
+            .map_err(|_| InterpreterError::RegisterOob(index as u64))
+    }
+
+    fn set_register(&mut self, index: usize, value: u128) -> Result<(), InterpreterError> {
+        if index == 0 {
+            return Ok(());
+        }
+
+        self.kept(REG)?;
+        let mask = self.mask;
+        *self.variable_mut(REG, index as u128 - 1).map_err(|_| InterpreterError::RegisterOob(index as u64))? = W::from_u128(value) & mask;
+        Ok(())
+    }
+
+    fn memory(&self, address: u128) -> Result<u128, InterpreterError> {
+        self.kept(RAM)?;
+        self.variable(RAM, address).map(W::to_u128)
+    }
+
+    fn set_memory(&mut self, address: u128, value: u128) -> Result<(), InterpreterError> {
+        self.kept(RAM)?;
+        let mask = self.mask;
+        *self.variable_mut(RAM, address)? = W::from_u128(value) & mask;
+        Ok(())
+    }
+
+    fn attach_device(&mut self, device: Box<dyn Device>) {
+        self.device = device;
+    }
+
+    fn stats(&self) -> Stats {
+        Stats { inst_count: self.inst_count }
+    }
+}
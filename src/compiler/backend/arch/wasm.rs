@@ -0,0 +1,698 @@
+//! Encodes a body as a standalone WebAssembly module.
+//!
+//! The module imports these from `env`, which work like the functions of the same name in the IR:
+//!
+//! - `port_read(port: i64) -> i64`
+//! - `port_write(port: i64, data: i64)`
+//! - `last_ok() -> i32`, whether the last port access went through
+//! - `report_error(kind: i64, pc: i64, detail: i64)`, with the codes of [`InterpreterError`]
+//!
+//! and exports its `memory` and `main() -> i32`, which gives 0 once the program halts and 1 after
+//! it reported an error. Variables live in the memory as arrays of little-endian `i64`s.
+
+use crate::compiler::{error::*, backend::ssa::*};
+use logos::Span;
+
+const I32: u8 = 0x7f;
+const I64: u8 = 0x7e;
+const VOID: u8 = 0x40;
+
+const UNREACHABLE: u8 = 0x00;
+const IF: u8 = 0x04;
+const ELSE: u8 = 0x05;
+const BLOCK: u8 = 0x02;
+const LOOP: u8 = 0x03;
+const END: u8 = 0x0b;
+const BR: u8 = 0x0c;
+const BR_TABLE: u8 = 0x0e;
+const RETURN: u8 = 0x0f;
+const CALL: u8 = 0x10;
+const DROP: u8 = 0x1a;
+const SELECT: u8 = 0x1b;
+const LOCAL_GET: u8 = 0x20;
+const LOCAL_SET: u8 = 0x21;
+const LOCAL_TEE: u8 = 0x22;
+const GLOBAL_GET: u8 = 0x23;
+const GLOBAL_SET: u8 = 0x24;
+const I64_LOAD: u8 = 0x29;
+const I64_STORE: u8 = 0x37;
+const MEMORY_SIZE: u8 = 0x3f;
+const MEMORY_GROW: u8 = 0x40;
+const I32_CONST: u8 = 0x41;
+const I64_CONST: u8 = 0x42;
+const I32_EQ: u8 = 0x46;
+const I32_GT_S: u8 = 0x4a;
+const I64_EQZ: u8 = 0x50;
+const I64_EQ: u8 = 0x51;
+const I64_NE: u8 = 0x52;
+const I64_LT_S: u8 = 0x53;
+const I64_LT_U: u8 = 0x54;
+const I64_GT_S: u8 = 0x55;
+const I64_GT_U: u8 = 0x56;
+const I64_LE_S: u8 = 0x57;
+const I64_LE_U: u8 = 0x58;
+const I64_GE_S: u8 = 0x59;
+const I64_GE_U: u8 = 0x5a;
+const I32_ADD: u8 = 0x6a;
+const I32_SUB: u8 = 0x6b;
+const I32_SHL: u8 = 0x74;
+const I64_ADD: u8 = 0x7c;
+const I64_SUB: u8 = 0x7d;
+const I64_MUL: u8 = 0x7e;
+const I64_DIV_S: u8 = 0x7f;
+const I64_DIV_U: u8 = 0x80;
+const I64_REM_S: u8 = 0x81;
+const I64_REM_U: u8 = 0x82;
+const I64_AND: u8 = 0x83;
+const I64_OR: u8 = 0x84;
+const I64_XOR: u8 = 0x85;
+const I64_SHL: u8 = 0x86;
+const I64_SHR_S: u8 = 0x87;
+const I64_SHR_U: u8 = 0x88;
+const I32_WRAP_I64: u8 = 0xa7;
+const I64_EXTEND_I32_U: u8 = 0xad;
+/// Prefix of `memory.fill`
+const MISC: u8 = 0xfc;
+const MEMORY_FILL: u32 = 11;
+
+/// Imported functions, in the order of their index
+const PORT_READ: u32 = 0;
+const PORT_WRITE: u32 = 1;
+const LAST_OK: u32 = 2;
+const REPORT_ERROR: u32 = 3;
+const MAIN: u32 = 4;
+
+/// Largest variable that gets a fixed place in memory, bigger ones are placed when allocated
+const MAX_FIXED: u128 = 1 << 20;
+/// Memory is 32-bit, so variables placed at runtime must end below this
+const MAX_END: i64 = 0xffff_0000;
+const PAGE: usize = 0x10000;
+
+fn uleb(buf: &mut Vec<u8>, mut v: u64) {
+    loop {
+        let byte = v as u8 & 0x7f;
+        v >>= 7;
+        if v == 0 {
+            buf.push(byte);
+            return;
+        }
+
+        buf.push(byte | 0x80);
+    }
+}
+
+fn sleb(buf: &mut Vec<u8>, mut v: i64) {
+    loop {
+        let byte = v as u8 & 0x7f;
+        v >>= 7;
+        if (v == 0 && byte & 0x40 == 0) || (v == -1 && byte & 0x40 != 0) {
+            buf.push(byte);
+            return;
+        }
+
+        buf.push(byte | 0x80);
+    }
+}
+
+fn name(buf: &mut Vec<u8>, name: &str) {
+    uleb(buf, name.len() as u64);
+    buf.extend_from_slice(name.as_bytes());
+}
+
+/// Appends section `id` holding a vector of `items`
+fn section(module: &mut Vec<u8>, id: u8, items: &[Vec<u8>]) {
+    let mut contents = Vec::new();
+    uleb(&mut contents, items.len() as u64);
+    for item in items.iter() {
+        contents.extend_from_slice(item);
+    }
+
+    module.push(id);
+    uleb(module, contents.len() as u64);
+    module.extend_from_slice(&contents);
+}
+
+/// Where a variable is kept
+#[derive(Clone, Copy)]
+enum Place {
+    /// At a fixed address, with room for the largest size it gets
+    Fixed,
+    /// Wherever there was room when it got allocated
+    Dynamic,
+}
+
+struct Encoder<'a> {
+    body: &'a Body,
+    code: Vec<u8>,
+    places: Vec<Place>,
+    mask: u64,
+
+    // indices of the locals after the values
+    end: u32,
+    next: u32,
+    pages: u32,
+}
+
+impl Encoder<'_> {
+    fn op(&mut self, op: u8) {
+        self.code.push(op);
+    }
+
+    fn op_u(&mut self, op: u8, imm: u32) {
+        self.code.push(op);
+        uleb(&mut self.code, imm as u64);
+    }
+
+    fn i64_const(&mut self, v: i64) {
+        self.code.push(I64_CONST);
+        sleb(&mut self.code, v);
+    }
+
+    fn i32_const(&mut self, v: i32) {
+        self.code.push(I32_CONST);
+        sleb(&mut self.code, v as i64);
+    }
+
+    fn get(&mut self, v: &ValueId) {
+        self.op_u(LOCAL_GET, **v as u32);
+    }
+
+    fn base(var: &VariableId) -> u32 {
+        **var as u32 * 2
+    }
+
+    fn len(var: &VariableId) -> u32 {
+        **var as u32 * 2 + 1
+    }
+
+    fn heap(&self) -> u32 {
+        self.places.len() as u32 * 2
+    }
+
+    /// Masks the value on top of the stack down to the word size
+    fn mask(&mut self) {
+        if self.body.bits < 64 {
+            self.i64_const(self.mask as i64);
+            self.op(I64_AND);
+        }
+    }
+
+    /// Sign-extends the word on top of the stack to the whole `i64`
+    fn sign_extend(&mut self) {
+        if self.body.bits < 64 {
+            let shift = 64 - self.body.bits as i64;
+            self.i64_const(shift);
+            self.op(I64_SHL);
+            self.i64_const(shift);
+            self.op(I64_SHR_S);
+        }
+    }
+
+    /// Reports an error with `detail` on top of the stack and stops running
+    fn report(&mut self, kind: InterpreterError, span: &Span, detail: impl FnOnce(&mut Self)) {
+        let pc = self.body.spans.iter().position(|s| s == span).map_or(-1, |pc| pc as i64);
+        self.i64_const(kind.code() as i64);
+        self.i64_const(pc);
+        detail(self);
+        self.op_u(CALL, REPORT_ERROR);
+        self.i32_const(1);
+        self.op(RETURN);
+    }
+
+    /// Reports a division by zero if `r` is 0
+    fn check_divisor(&mut self, r: &ValueId, span: &Span) {
+        self.get(r);
+        self.op(I64_EQZ);
+        self.op(IF);
+        self.op(VOID);
+        self.report(InterpreterError::DivisionByZero, span, |f| f.i64_const(0));
+        self.op(END);
+    }
+
+    /// Reports an out of bounds access if `off` is past the end of `var`
+    fn check_access(&mut self, var: &VariableId, off: &ValueId, span: &Span) {
+        self.get(off);
+        self.op_u(GLOBAL_GET, Self::len(var));
+        self.op(I64_GE_U);
+        self.op(IF);
+        self.op(VOID);
+        self.report(InterpreterError::MemoryAccessOob(0), span, |f| f.get(off));
+        self.op(END);
+    }
+
+    /// Pushes the address of `off` in `var`
+    fn address(&mut self, var: &VariableId, off: &ValueId) {
+        self.op_u(GLOBAL_GET, Self::base(var));
+        self.get(off);
+        self.op(I32_WRAP_I64);
+        self.i32_const(3);
+        self.op(I32_SHL);
+        self.op(I32_ADD);
+    }
+
+    fn binop(&mut self, op: BinOp, l: &ValueId, r: &ValueId, span: &Span) {
+        // shifts give 0 from 64 on, which isn't what wasm does
+        let shift = |f: &mut Self, op: u8| {
+            f.get(l);
+            f.get(r);
+            f.op(op);
+            f.mask();
+            f.i64_const(0);
+            f.get(r);
+            f.i64_const(64);
+            f.op(I64_LT_U);
+            f.op(SELECT);
+        };
+
+        let simple = |f: &mut Self, op: u8, masked: bool| {
+            f.get(l);
+            f.get(r);
+            f.op(op);
+            if masked {
+                f.mask();
+            }
+        };
+
+        let signed = |f: &mut Self, op: u8| {
+            f.get(l);
+            f.sign_extend();
+            f.get(r);
+            f.sign_extend();
+            f.op(op);
+        };
+
+        if matches!(op, BinOp::Div | BinOp::Mod | BinOp::SDiv | BinOp::SMod) {
+            self.check_divisor(r, span);
+        }
+
+        match op {
+            BinOp::Add  => simple(self, I64_ADD, true),
+            BinOp::Sub  => simple(self, I64_SUB, true),
+            BinOp::Mul  => simple(self, I64_MUL, true),
+            BinOp::Div  => simple(self, I64_DIV_U, false),
+            BinOp::Mod  => simple(self, I64_REM_U, false),
+            BinOp::SDiv => {
+                // wasm traps on the one division that overflows, where negating gives the result
+                self.get(r);
+                self.sign_extend();
+                self.i64_const(-1);
+                self.op(I64_EQ);
+                self.op(IF);
+                self.op(I64);
+                self.i64_const(0);
+                self.get(l);
+                self.op(I64_SUB);
+                self.op(ELSE);
+                signed(self, I64_DIV_S);
+                self.op(END);
+                self.mask();
+            },
+            BinOp::SMod => {
+                signed(self, I64_REM_S);
+                self.mask();
+            },
+            BinOp::And  => simple(self, I64_AND, false),
+            BinOp::Or   => simple(self, I64_OR, false),
+            BinOp::Xor  => simple(self, I64_XOR, false),
+            BinOp::Shl  => shift(self, I64_SHL),
+            BinOp::Shr  => shift(self, I64_SHR_U),
+            BinOp::Sar  => {
+                // the sign fills everything once the amount gets to 63
+                self.get(l);
+                self.sign_extend();
+                self.get(r);
+                self.i64_const(63);
+                self.get(r);
+                self.i64_const(63);
+                self.op(I64_LT_U);
+                self.op(SELECT);
+                self.op(I64_SHR_S);
+                self.mask();
+            },
+            BinOp::Eq   => simple(self, I64_EQ, false),
+            BinOp::Ne   => simple(self, I64_NE, false),
+            BinOp::Lt   => simple(self, I64_LT_U, false),
+            BinOp::Le   => simple(self, I64_LE_U, false),
+            BinOp::Gt   => simple(self, I64_GT_U, false),
+            BinOp::Ge   => simple(self, I64_GE_U, false),
+            BinOp::SLt  => signed(self, I64_LT_S),
+            BinOp::SLe  => signed(self, I64_LE_S),
+            BinOp::SGt  => signed(self, I64_GT_S),
+            BinOp::SGe  => signed(self, I64_GE_S),
+        }
+
+        if matches!(op, BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge | BinOp::SLt | BinOp::SLe | BinOp::SGt | BinOp::SGe) {
+            self.op(I64_EXTEND_I32_U);
+        }
+    }
+
+    fn allocate(&mut self, var: &VariableId, size: &ValueId) {
+        match self.places[**var] {
+            Place::Fixed => {
+                self.get(size);
+                self.op_u(GLOBAL_SET, Self::len(var));
+
+                self.op_u(GLOBAL_GET, Self::base(var));
+                self.i32_const(0);
+                self.get(size);
+                self.op(I32_WRAP_I64);
+                self.i32_const(3);
+                self.op(I32_SHL);
+                self.op(MISC);
+                uleb(&mut self.code, MEMORY_FILL as u64);
+                self.code.push(0);
+            },
+            Place::Dynamic => {
+                // placed past everything else, where the memory is still zeroed. Running out of
+                // memory leaves the variable empty.
+                let (end, pages, heap) = (self.end, self.pages, self.heap());
+                self.get(size);
+                self.i64_const(MAX_END / 8);
+                self.op(I64_GE_U);
+                self.op(IF);
+                self.op(VOID);
+                self.i64_const(0);
+                self.op_u(GLOBAL_SET, Self::len(var));
+                self.op(ELSE);
+
+                self.op_u(GLOBAL_GET, heap);
+                self.op(I64_EXTEND_I32_U);
+                self.get(size);
+                self.i64_const(3);
+                self.op(I64_SHL);
+                self.op(I64_ADD);
+                self.op_u(LOCAL_TEE, end);
+                self.i64_const(PAGE as i64 - 1);
+                self.op(I64_ADD);
+                self.i64_const(16);
+                self.op(I64_SHR_U);
+                self.op(I32_WRAP_I64);
+                self.op(MEMORY_SIZE);
+                self.code.push(0);
+                self.op(I32_SUB);
+                self.op_u(LOCAL_SET, pages);
+
+                // whether it failed
+                self.op_u(LOCAL_GET, end);
+                self.i64_const(MAX_END);
+                self.op(I64_GT_U);
+                self.op(IF);
+                self.op(I32);
+                self.i32_const(1);
+                self.op(ELSE);
+                self.op_u(LOCAL_GET, pages);
+                self.i32_const(0);
+                self.op(I32_GT_S);
+                self.op(IF);
+                self.op(I32);
+                self.op_u(LOCAL_GET, pages);
+                self.op(MEMORY_GROW);
+                self.code.push(0);
+                self.i32_const(-1);
+                self.op(I32_EQ);
+                self.op(ELSE);
+                self.i32_const(0);
+                self.op(END);
+                self.op(END);
+
+                self.op(IF);
+                self.op(VOID);
+                self.i64_const(0);
+                self.op_u(GLOBAL_SET, Self::len(var));
+                self.op(ELSE);
+                self.op_u(GLOBAL_GET, heap);
+                self.op_u(GLOBAL_SET, Self::base(var));
+                self.op_u(LOCAL_GET, end);
+                self.op(I32_WRAP_I64);
+                self.op_u(GLOBAL_SET, heap);
+                self.get(size);
+                self.op_u(GLOBAL_SET, Self::len(var));
+                self.op(END);
+
+                self.op(END);
+            },
+        }
+    }
+
+    fn instruction(&mut self, instr: &Instruction, span: &Span) {
+        match &instr.operation {
+            Operation::Phi(_) => return,
+            Operation::Integer(int) => self.i64_const(*int as u64 as i64),
+            Operation::BinOp(op, l, r) => self.binop(*op, l, r, span),
+            Operation::Allocate(var, size) => self.allocate(var, size),
+            Operation::LoadIndex(var, off) => {
+                self.check_access(var, off, span);
+                self.address(var, off);
+                self.op_u(I64_LOAD, 3);
+                self.code.push(0);
+            },
+            Operation::StoreIndex(var, off, val) => {
+                self.check_access(var, off, span);
+                self.address(var, off);
+                self.get(val);
+                self.op_u(I64_STORE, 3);
+                self.code.push(0);
+            },
+            Operation::Call(f, args) => {
+                for arg in args.iter() {
+                    self.get(arg);
+                }
+
+                match f {
+                    Function::LastOk => {
+                        self.op_u(CALL, LAST_OK);
+                        self.op(I64_EXTEND_I32_U);
+                    },
+                    Function::PortRead => {
+                        self.op_u(CALL, PORT_READ);
+                        self.mask();
+                    },
+                    Function::PortWrite => self.op_u(CALL, PORT_WRITE),
+                    Function::ReportError => {
+                        self.op_u(CALL, REPORT_ERROR);
+                        self.i32_const(1);
+                        self.op(RETURN);
+                    },
+                }
+            },
+        }
+
+        let has_result = match &instr.operation {
+            Operation::Allocate(..) | Operation::StoreIndex(..) => false,
+            Operation::Call(f, _) => matches!(f, Function::LastOk | Function::PortRead),
+            _ => true,
+        };
+
+        match (instr.destination, has_result) {
+            (Some(dst), true) => self.op_u(LOCAL_SET, *dst as u32),
+            (None, true) => self.op(DROP),
+            _ => {},
+        }
+    }
+
+    /// Copies the values every Phi node of `to` takes from `from` all at once through the stack,
+    /// then goes back to the dispatch loop `depth` levels out
+    fn goto(&mut self, from: BlockId, to: BlockId, depth: u32) {
+        let copies: Vec<_> = self.body.blocks[*to].instructions.iter()
+            .map_while(|i| match &i.operation {
+                Operation::Phi(branches) => Some((i.destination.unwrap(), branches.iter().find(|(_, b)| *b == from).unwrap().0)),
+                _ => None,
+            })
+            .collect();
+
+        for (_, src) in copies.iter() {
+            self.get(src);
+        }
+
+        for (dst, _) in copies.iter().rev() {
+            self.op_u(LOCAL_SET, **dst as u32);
+        }
+
+        self.i32_const(*to as i32);
+        self.op_u(LOCAL_SET, self.next);
+        self.op_u(BR, depth);
+    }
+
+    /// Every block goes after the end of its own `block`, which a `br_table` at the innermost
+    /// point picks with the `next` local, all inside of a `loop` to jump back to.
+    fn blocks(&mut self) {
+        let count = self.body.blocks.len() as u32;
+        if count == 0 {
+            self.i32_const(0);
+            return;
+        }
+
+        self.op(LOOP);
+        self.op(VOID);
+        for _ in 0..count {
+            self.op(BLOCK);
+            self.op(VOID);
+        }
+
+        self.op_u(LOCAL_GET, self.next);
+        self.op_u(BR_TABLE, count - 1);
+        for i in 0..count {
+            uleb(&mut self.code, i as u64);
+        }
+
+        self.op(END);
+
+        for (i, blk) in self.body.blocks.iter().enumerate() {
+            let depth = count - 1 - i as u32;
+            for (j, instr) in blk.instructions.iter().enumerate() {
+                self.instruction(instr, &blk.span_at(j).cloned().unwrap_or_default());
+            }
+
+            match blk.terminator {
+                Terminator::Jump(to) => self.goto(blk.id, to, depth),
+                Terminator::Branch(cond, if_, else_) => {
+                    self.get(&cond);
+                    self.op(I64_EQZ);
+                    self.op(IF);
+                    self.op(VOID);
+                    self.goto(blk.id, else_, depth + 1);
+                    self.op(ELSE);
+                    self.goto(blk.id, if_, depth + 1);
+                    self.op(END);
+                },
+                Terminator::Return => {
+                    self.i32_const(0);
+                    self.op(RETURN);
+                },
+                Terminator::Trap | Terminator::None => self.op(UNREACHABLE),
+            }
+
+            if i as u32 + 1 != count {
+                self.op(END);
+            }
+        }
+
+        self.op(END);
+        self.op(UNREACHABLE);
+    }
+}
+
+fn func_type(params: &[u8], results: &[u8]) -> Vec<u8> {
+    let mut ty = vec![0x60];
+    uleb(&mut ty, params.len() as u64);
+    ty.extend_from_slice(params);
+    uleb(&mut ty, results.len() as u64);
+    ty.extend_from_slice(results);
+    ty
+}
+
+/// Encodes `ssa` as a WebAssembly module, see the [module docs](self) for what it imports and
+/// exports.
+pub fn to_wasm(ssa: &(Body, usize, usize)) -> Vec<u8> {
+    let (body, values, variables) = ssa;
+    assert!(body.bits <= 64, "WebAssembly modules only go up to 64-bit words");
+
+    let instructions = || body.blocks.iter().flat_map(|b| b.instructions.iter());
+    let constant = |v: &ValueId| instructions().find_map(|i| match i.operation {
+        Operation::Integer(int) if i.destination == Some(*v) => Some(int),
+        _ => None,
+    });
+
+    // variables that only ever get a small constant size have a place from the start
+    let mut sizes = vec![Some(0); *variables];
+    for instr in instructions() {
+        if let Operation::Allocate(var, size) = &instr.operation {
+            sizes[**var] = sizes[**var].zip(constant(size)).map(|(a, b)| a.max(b)).filter(|s| *s <= MAX_FIXED);
+        }
+    }
+
+    let mut address = 0;
+    let mut globals = Vec::new();
+    let mut places = Vec::new();
+    let global = |ty: u8, init: i64| {
+        let mut g = vec![ty, 1, if ty == I32 { I32_CONST } else { I64_CONST }];
+        sleb(&mut g, init);
+        g.push(END);
+        g
+    };
+
+    for size in sizes.iter() {
+        match size {
+            Some(size) => {
+                places.push(Place::Fixed);
+                globals.push(global(I32, address as i64));
+                address += (*size as usize).max(1) * 8;
+            },
+            None => {
+                places.push(Place::Dynamic);
+                globals.push(global(I32, 0));
+            },
+        }
+
+        globals.push(global(I64, 0));
+    }
+
+    globals.push(global(I32, address as i64));
+
+    let mut f = Encoder {
+        body,
+        code: Vec::new(),
+        places,
+        mask: u64::MAX >> (64 - body.bits),
+
+        end: *values as u32,
+        next: *values as u32 + 1,
+        pages: *values as u32 + 2,
+    };
+
+    f.blocks();
+    f.op(END);
+
+    let mut code = Vec::new();
+    uleb(&mut code, 2);
+    uleb(&mut code, *values as u64 + 1);
+    code.push(I64);
+    uleb(&mut code, 2);
+    code.push(I32);
+    code.extend_from_slice(&f.code);
+
+    let mut sized_code = Vec::new();
+    uleb(&mut sized_code, code.len() as u64);
+    sized_code.extend_from_slice(&code);
+
+    let mut module = b"\0asm".to_vec();
+    module.extend_from_slice(&1_u32.to_le_bytes());
+
+    section(&mut module, 1, &[
+        func_type(&[I64], &[I64]),
+        func_type(&[I64, I64], &[]),
+        func_type(&[], &[I32]),
+        func_type(&[I64, I64, I64], &[]),
+    ]);
+
+    let imports: Vec<_> = ["port_read", "port_write", "last_ok", "report_error"].iter().enumerate().map(|(ty, field)| {
+        let mut import = Vec::new();
+        name(&mut import, "env");
+        name(&mut import, field);
+        import.push(0);
+        uleb(&mut import, ty as u64);
+        import
+    }).collect();
+    section(&mut module, 2, &imports);
+
+    // `main` has the same type as `last_ok`
+    section(&mut module, 3, &[vec![2]]);
+
+    let mut memory = vec![0];
+    uleb(&mut memory, address.div_ceil(PAGE).max(1) as u64);
+    section(&mut module, 5, &[memory]);
+
+    section(&mut module, 6, &globals);
+
+    let export = |field: &str, kind: u8, index: u32| {
+        let mut export = Vec::new();
+        name(&mut export, field);
+        export.push(kind);
+        uleb(&mut export, index as u64);
+        export
+    };
+    section(&mut module, 7, &[export("memory", 2, 0), export("main", 0, MAIN)]);
+
+    section(&mut module, 10, &[sized_code]);
+    module
+}
@@ -0,0 +1,346 @@
+//! Encoder for the handful of x86-64 instructions the backends emit
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reg {
+    Rax, Rcx, Rdx, Rbx, Rsp, Rbp, Rsi, Rdi,
+    R8, R9, R10, R11, R12, R13, R14, R15,
+}
+
+impl Reg {
+    fn low(self) -> u8 {
+        self as u8 & 7
+    }
+
+    fn high(self) -> bool {
+        self as u8 >= 8
+    }
+}
+
+/// Condition codes, as encoded in `Jcc`, `SETcc` and `CMOVcc`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cond {
+    O, No, B, Ae, E, Ne, Be, A, S, Ns, P, Np, L, Ge, Le, G,
+}
+
+/// Two-operand ALU instructions, named after their `r/m, reg` opcode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alu {
+    Add = 0x01,
+    Or  = 0x09,
+    And = 0x21,
+    Sub = 0x29,
+    Xor = 0x31,
+    Cmp = 0x39,
+}
+
+/// Shifts by `cl`, named after their `/digit`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shift {
+    Shl = 4,
+    Shr = 5,
+    Sar = 7,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Label(usize);
+
+#[derive(Debug, Default)]
+pub struct Assembler {
+    pub code: Vec<u8>,
+    labels: Vec<Option<usize>>,
+    /// Places of 32-bit displacements relative to the end of themselves
+    fixups: Vec<(usize, Label)>,
+}
+
+impl Assembler {
+    pub fn new_label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    pub fn bind(&mut self, label: Label) {
+        self.labels[label.0] = Some(self.code.len());
+    }
+
+    pub fn offset(&self, label: Label) -> usize {
+        self.labels[label.0].expect("label never got bound")
+    }
+
+    /// Patches every reference to a label, giving the finished code
+    pub fn finish(mut self) -> Vec<u8> {
+        for (at, label) in self.fixups.iter() {
+            let rel = self.offset(*label) as i64 - (*at as i64 + 4);
+            self.code[*at..*at + 4].copy_from_slice(&(rel as i32).to_le_bytes());
+        }
+
+        self.code
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    /// Places `bytes` as they are, for data living along with the code
+    pub fn data(&mut self, bytes: &[u8]) {
+        self.bytes(bytes);
+    }
+
+    fn rel32(&mut self, label: Label) {
+        self.fixups.push((self.code.len(), label));
+        self.bytes(&[0; 4]);
+    }
+
+    fn rex(&mut self, w: bool, reg: u8, index: u8, base: u8) {
+        let rex = 0x40 | (w as u8) << 3 | (reg >> 3) << 2 | (index >> 3) << 1 | base >> 3;
+        if rex != 0x40 {
+            self.code.push(rex);
+        }
+    }
+
+    fn modrm_reg(&mut self, reg: u8, rm: Reg) {
+        self.code.push(0xc0 | (reg & 7) << 3 | rm.low());
+    }
+
+    /// ModR/M addressing `[base + disp]`
+    fn modrm_mem(&mut self, reg: u8, base: Reg, disp: i32) {
+        let md = if disp == 0 && base.low() != 5 {
+            0
+        } else if i8::try_from(disp).is_ok() {
+            1
+        } else {
+            2
+        };
+
+        self.code.push(md << 6 | (reg & 7) << 3 | base.low());
+        if base.low() == 4 {
+            self.code.push(0x24);
+        }
+
+        match md {
+            1 => self.code.push(disp as u8),
+            2 => self.bytes(&disp.to_le_bytes()),
+            _ => {},
+        }
+    }
+
+    /// `op reg, [base + disp]` with a 64-bit operand size
+    fn op_mem(&mut self, op: &[u8], reg: u8, base: Reg, disp: i32) {
+        self.rex(true, reg, 0, base as u8);
+        self.bytes(op);
+        self.modrm_mem(reg, base, disp);
+    }
+
+    /// `op rm, reg` with a 64-bit operand size
+    fn op_reg(&mut self, op: &[u8], reg: u8, rm: Reg) {
+        self.rex(true, reg, 0, rm as u8);
+        self.bytes(op);
+        self.modrm_reg(reg, rm);
+    }
+
+    /// `mov dst, [base + disp]`
+    pub fn load(&mut self, dst: Reg, base: Reg, disp: i32) {
+        self.op_mem(&[0x8b], dst as u8, base, disp);
+    }
+
+    /// `mov [base + disp], src`
+    pub fn store(&mut self, base: Reg, disp: i32, src: Reg) {
+        self.op_mem(&[0x89], src as u8, base, disp);
+    }
+
+    /// `mov qword [base + disp], imm`, sign-extending `imm`
+    pub fn store_imm(&mut self, base: Reg, disp: i32, imm: i32) {
+        self.op_mem(&[0xc7], 0, base, disp);
+        self.bytes(&imm.to_le_bytes());
+    }
+
+    /// `lea dst, [base + disp]`
+    pub fn lea(&mut self, dst: Reg, base: Reg, disp: i32) {
+        self.op_mem(&[0x8d], dst as u8, base, disp);
+    }
+
+    /// `movzx dst, byte [base + disp]`
+    pub fn load_byte(&mut self, dst: Reg, base: Reg, disp: i32) {
+        self.op_mem(&[0x0f, 0xb6], dst as u8, base, disp);
+    }
+
+    /// `mov byte [base + disp], src`
+    pub fn store_byte(&mut self, base: Reg, disp: i32, src: Reg) {
+        // a REX prefix makes sure that sil and dil are used over dh and bh
+        self.code.push(0x40 | (src.high() as u8) << 2 | base.high() as u8);
+        self.code.push(0x88);
+        self.modrm_mem(src as u8, base, disp);
+    }
+
+    /// `mov dst, [base + index * 8]`
+    pub fn load_index(&mut self, dst: Reg, base: Reg, index: Reg) {
+        assert!(base.low() != 5, "no index addressing off rbp or r13");
+        self.rex(true, dst as u8, index as u8, base as u8);
+        self.bytes(&[0x8b, (dst.low()) << 3 | 4, 0xc0 | index.low() << 3 | base.low()]);
+    }
+
+    /// `mov [base + index * 8], src`
+    pub fn store_index(&mut self, base: Reg, index: Reg, src: Reg) {
+        assert!(base.low() != 5, "no index addressing off rbp or r13");
+        self.rex(true, src as u8, index as u8, base as u8);
+        self.bytes(&[0x89, (src.low()) << 3 | 4, 0xc0 | index.low() << 3 | base.low()]);
+    }
+
+    /// `mov dst, imm`, in the shortest encoding that fits
+    pub fn mov_imm(&mut self, dst: Reg, imm: u64) {
+        if let Ok(imm) = u32::try_from(imm) {
+            self.rex(false, 0, 0, dst as u8);
+            self.code.push(0xb8 + dst.low());
+            self.bytes(&imm.to_le_bytes());
+        } else if let Ok(imm) = i32::try_from(imm as i64) {
+            self.op_reg(&[0xc7], 0, dst);
+            self.bytes(&imm.to_le_bytes());
+        } else {
+            self.rex(true, 0, 0, dst as u8);
+            self.code.push(0xb8 + dst.low());
+            self.bytes(&imm.to_le_bytes());
+        }
+    }
+
+    pub fn mov(&mut self, dst: Reg, src: Reg) {
+        self.op_reg(&[0x89], src as u8, dst);
+    }
+
+    /// `op dst, src`
+    pub fn alu(&mut self, op: Alu, dst: Reg, src: Reg) {
+        self.op_reg(&[op as u8], src as u8, dst);
+    }
+
+    /// `op dst, [base + disp]`
+    pub fn alu_load(&mut self, op: Alu, dst: Reg, base: Reg, disp: i32) {
+        self.op_mem(&[op as u8 + 2], dst as u8, base, disp);
+    }
+
+    /// `op dst, imm`, sign-extending `imm`
+    pub fn alu_imm(&mut self, op: Alu, dst: Reg, imm: i32) {
+        self.op_reg(&[0x81], op as u8 >> 3, dst);
+        self.bytes(&imm.to_le_bytes());
+    }
+
+    /// `op qword [base + disp], imm`, sign-extending `imm`
+    pub fn alu_store_imm(&mut self, op: Alu, base: Reg, disp: i32, imm: i32) {
+        self.op_mem(&[0x81], op as u8 >> 3, base, disp);
+        self.bytes(&imm.to_le_bytes());
+    }
+
+    pub fn test(&mut self, a: Reg, b: Reg) {
+        self.op_reg(&[0x85], b as u8, a);
+    }
+
+    pub fn imul(&mut self, dst: Reg, src: Reg) {
+        self.op_reg(&[0x0f, 0xaf], dst as u8, src);
+    }
+
+    /// `op dst, cl`
+    pub fn shift(&mut self, op: Shift, dst: Reg) {
+        self.op_reg(&[0xd3], op as u8, dst);
+    }
+
+    /// `op dst, imm`
+    pub fn shift_imm(&mut self, op: Shift, dst: Reg, imm: u8) {
+        self.op_reg(&[0xc1], op as u8, dst);
+        self.code.push(imm);
+    }
+
+    pub fn neg(&mut self, dst: Reg) {
+        self.op_reg(&[0xf7], 3, dst);
+    }
+
+    /// `div src`, dividing rdx:rax
+    pub fn div(&mut self, src: Reg) {
+        self.op_reg(&[0xf7], 6, src);
+    }
+
+    /// `idiv src`, dividing rdx:rax
+    pub fn idiv(&mut self, src: Reg) {
+        self.op_reg(&[0xf7], 7, src);
+    }
+
+    /// Sign-extends rax into rdx
+    pub fn cqo(&mut self) {
+        self.bytes(&[0x48, 0x99]);
+    }
+
+    /// `SETcc` into the low byte of `dst`, then zero-extends it to the whole register
+    pub fn set(&mut self, cond: Cond, dst: Reg) {
+        // a REX prefix makes sure that sil and dil are used over dh and bh
+        self.code.push(0x40 | dst.high() as u8);
+        self.bytes(&[0x0f, 0x90 + cond as u8]);
+        self.code.push(0xc0 | dst.low());
+        self.op_reg(&[0x0f, 0xb6], dst as u8, dst);
+    }
+
+    pub fn cmov(&mut self, cond: Cond, dst: Reg, src: Reg) {
+        self.op_reg(&[0x0f, 0x40 + cond as u8], dst as u8, src);
+    }
+
+    pub fn jmp(&mut self, label: Label) {
+        self.code.push(0xe9);
+        self.rel32(label);
+    }
+
+    pub fn jcc(&mut self, cond: Cond, label: Label) {
+        self.bytes(&[0x0f, 0x80 + cond as u8]);
+        self.rel32(label);
+    }
+
+    /// `jmp reg`
+    pub fn jmp_reg(&mut self, reg: Reg) {
+        self.rex(false, 0, 0, reg as u8);
+        self.bytes(&[0xff]);
+        self.modrm_reg(4, reg);
+    }
+
+    /// `lea dst, [rip + label]`
+    pub fn lea_label(&mut self, dst: Reg, label: Label) {
+        self.rex(true, dst as u8, 0, 0);
+        self.bytes(&[0x8d, dst.low() << 3 | 5]);
+        self.rel32(label);
+    }
+
+    /// `call label`
+    pub fn call(&mut self, label: Label) {
+        self.code.push(0xe8);
+        self.rel32(label);
+    }
+
+    /// `call reg`
+    pub fn call_reg(&mut self, reg: Reg) {
+        self.rex(false, 0, 0, reg as u8);
+        self.bytes(&[0xff]);
+        self.modrm_reg(2, reg);
+    }
+
+    /// `call [base + disp]`
+    pub fn call_mem(&mut self, base: Reg, disp: i32) {
+        self.rex(false, 0, 0, base as u8);
+        self.bytes(&[0xff]);
+        self.modrm_mem(2, base, disp);
+    }
+
+    pub fn push(&mut self, reg: Reg) {
+        self.rex(false, 0, 0, reg as u8);
+        self.code.push(0x50 + reg.low());
+    }
+
+    pub fn pop(&mut self, reg: Reg) {
+        self.rex(false, 0, 0, reg as u8);
+        self.code.push(0x58 + reg.low());
+    }
+
+    pub fn ret(&mut self) {
+        self.code.push(0xc3);
+    }
+
+    pub fn syscall(&mut self) {
+        self.bytes(&[0x0f, 0x05]);
+    }
+
+    pub fn ud2(&mut self) {
+        self.bytes(&[0x0f, 0x0b]);
+    }
+}
@@ -0,0 +1,455 @@
+use crate::compiler::{error::*, common::*, backend::{ssa::*, arch::x86_64::{asm::*, lower::*}}};
+
+/// Where the headers and the code get loaded
+const TEXT: u64 = 0x400000;
+/// Where the zeroed data goes, which starts with the context
+const DATA: u64 = 0x10000000;
+
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+const HEADERS: usize = EHDR_SIZE + 2 * PHDR_SIZE;
+
+// Data of the runtime, placed right after the context
+const ERROR_KIND: i32 = HELPERS + HELPER_COUNT as i32 * 8;
+const ERROR_DETAIL: i32 = ERROR_KIND + 8;
+/// File descriptor the output buffer goes to
+const OUT_FD: i32 = ERROR_DETAIL + 8;
+const OUT_LEN: i32 = OUT_FD + 8;
+const OUT_BUF: i32 = OUT_LEN + 8;
+const OUT_CAPACITY: i32 = 0x200;
+/// Where the `[pointer, length]` pairs of the variables start
+const SLOTS: i32 = OUT_BUF + OUT_CAPACITY;
+
+const SYS_READ: u64 = 0;
+const SYS_WRITE: u64 = 1;
+const SYS_MMAP: u64 = 9;
+const SYS_MUNMAP: u64 = 11;
+const SYS_EXIT_GROUP: u64 = 231;
+
+/// Exit code of the CLI for runtime errors
+const EXIT_ERROR: u64 = 3;
+
+/// Labels of the functions making up the runtime
+struct Runtime {
+    helpers: [Label; HELPER_COUNT],
+    flush: Label,
+    putc: Label,
+    puts: Label,
+}
+
+fn syscall(asm: &mut Assembler, number: u64) {
+    asm.mov_imm(Reg::Rax, number);
+    asm.syscall();
+}
+
+/// `flush(ctx)`, writes the output buffer out. Keeps every register but rax, rcx, rdx, rsi and
+/// r11.
+fn flush(asm: &mut Assembler) {
+    let done = asm.new_label();
+    asm.load(Reg::Rdx, Reg::Rdi, OUT_LEN);
+    asm.test(Reg::Rdx, Reg::Rdx);
+    asm.jcc(Cond::E, done);
+
+    asm.push(Reg::Rdi);
+    asm.lea(Reg::Rsi, Reg::Rdi, OUT_BUF);
+    asm.load(Reg::Rdi, Reg::Rdi, OUT_FD);
+    syscall(asm, SYS_WRITE);
+    asm.pop(Reg::Rdi);
+    asm.store_imm(Reg::Rdi, OUT_LEN, 0);
+
+    asm.bind(done);
+    asm.ret();
+}
+
+/// `putc(ctx, byte)`, buffers one byte of output. Keeps the same registers as `flush`.
+fn putc(asm: &mut Assembler, flush: Label) {
+    let room = asm.new_label();
+    asm.load(Reg::Rax, Reg::Rdi, OUT_LEN);
+    asm.alu_imm(Alu::Cmp, Reg::Rax, OUT_CAPACITY);
+    asm.jcc(Cond::B, room);
+    asm.push(Reg::Rsi);
+    asm.call(flush);
+    asm.pop(Reg::Rsi);
+    asm.alu(Alu::Xor, Reg::Rax, Reg::Rax);
+
+    asm.bind(room);
+    asm.alu(Alu::Add, Reg::Rax, Reg::Rdi);
+    asm.store_byte(Reg::Rax, OUT_BUF, Reg::Rsi);
+    asm.alu_store_imm(Alu::Add, Reg::Rdi, OUT_LEN, 1);
+    asm.ret();
+}
+
+/// `puts(ctx, pointer, length)`, buffers a string. Keeps the same registers as `flush`, apart
+/// from r8 and r9.
+fn puts(asm: &mut Assembler, putc: Label) {
+    let (next, done) = (asm.new_label(), asm.new_label());
+    asm.mov(Reg::R8, Reg::Rsi);
+    asm.mov(Reg::R9, Reg::Rsi);
+    asm.alu(Alu::Add, Reg::R9, Reg::Rdx);
+
+    asm.bind(next);
+    asm.alu(Alu::Cmp, Reg::R8, Reg::R9);
+    asm.jcc(Cond::Ae, done);
+    asm.load_byte(Reg::Rsi, Reg::R8, 0);
+    asm.call(putc);
+    asm.alu_imm(Alu::Add, Reg::R8, 1);
+    asm.jmp(next);
+
+    asm.bind(done);
+    asm.ret();
+}
+
+/// Variables live in their own anonymous mappings, which come zeroed. A mapping that fails
+/// leaves the variable empty so that every access to it reports an error.
+fn alloc(asm: &mut Assembler) {
+    let (map, done) = (asm.new_label(), asm.new_label());
+    asm.push(Reg::Rbx);
+    asm.load(Reg::Rbx, Reg::Rdi, VARS);
+    asm.shift_imm(Shift::Shl, Reg::Rsi, 4);
+    asm.alu(Alu::Add, Reg::Rbx, Reg::Rsi);
+    asm.push(Reg::Rdx);
+
+    asm.load(Reg::Rsi, Reg::Rbx, 8);
+    asm.test(Reg::Rsi, Reg::Rsi);
+    asm.jcc(Cond::E, map);
+    asm.shift_imm(Shift::Shl, Reg::Rsi, 3);
+    asm.load(Reg::Rdi, Reg::Rbx, 0);
+    syscall(asm, SYS_MUNMAP);
+    asm.store_imm(Reg::Rbx, 0, 0);
+    asm.store_imm(Reg::Rbx, 8, 0);
+
+    asm.bind(map);
+    asm.load(Reg::Rsi, Reg::Rsp, 0);
+    asm.test(Reg::Rsi, Reg::Rsi);
+    asm.jcc(Cond::E, done);
+    // more than 2^40 words can't be mapped anyway, and would overflow when counted in bytes
+    asm.mov(Reg::Rax, Reg::Rsi);
+    asm.shift_imm(Shift::Shr, Reg::Rax, 40);
+    asm.jcc(Cond::Ne, done);
+
+    asm.shift_imm(Shift::Shl, Reg::Rsi, 3);
+    asm.alu(Alu::Xor, Reg::Rdi, Reg::Rdi);
+    asm.mov_imm(Reg::Rdx, 3); // PROT_READ | PROT_WRITE
+    asm.mov_imm(Reg::R10, 0x22); // MAP_PRIVATE | MAP_ANONYMOUS
+    asm.mov_imm(Reg::R8, u64::MAX);
+    asm.alu(Alu::Xor, Reg::R9, Reg::R9);
+    syscall(asm, SYS_MMAP);
+    asm.mov_imm(Reg::Rdx, -4096i64 as u64);
+    asm.alu(Alu::Cmp, Reg::Rax, Reg::Rdx);
+    asm.jcc(Cond::A, done);
+    asm.store(Reg::Rbx, 0, Reg::Rax);
+    asm.load(Reg::Rax, Reg::Rsp, 0);
+    asm.store(Reg::Rbx, 8, Reg::Rax);
+
+    asm.bind(done);
+    asm.pop(Reg::Rdx);
+    asm.pop(Reg::Rbx);
+    asm.ret();
+}
+
+/// `%TEXT` reads a byte of stdin, giving 0 at its end. Everything else is unsupported.
+fn port_read(asm: &mut Assembler, flush: Label, bits: usize) {
+    let text = asm.new_label();
+    asm.alu_imm(Alu::Cmp, Reg::Rsi, Port::Text as i32);
+    asm.jcc(Cond::E, text);
+    asm.store_imm(Reg::Rdi, LAST_OK, 0);
+    asm.alu(Alu::Xor, Reg::Rax, Reg::Rax);
+    asm.ret();
+
+    // the output so far goes out first, in case it was a prompt
+    asm.bind(text);
+    asm.push(Reg::Rdi);
+    asm.call(flush);
+    asm.push(Reg::Rax);
+    asm.store_imm(Reg::Rsp, 0, 0);
+    asm.alu(Alu::Xor, Reg::Rdi, Reg::Rdi);
+    asm.mov(Reg::Rsi, Reg::Rsp);
+    asm.mov_imm(Reg::Rdx, 1);
+    syscall(asm, SYS_READ);
+    asm.pop(Reg::Rax);
+    asm.pop(Reg::Rdi);
+    asm.store_imm(Reg::Rdi, LAST_OK, 1);
+    if bits < 8 {
+        asm.alu_imm(Alu::And, Reg::Rax, (1 << bits) - 1);
+    }
+
+    asm.ret();
+}
+
+/// `%TEXT` writes a character as UTF-8, where invalid characters turn into U+FFFD, and
+/// `%NUMBER` writes a number in decimal. Everything else is unsupported.
+fn port_write(asm: &mut Assembler, putc: Label) {
+    let (text, number, done) = (asm.new_label(), asm.new_label(), asm.new_label());
+    asm.alu_imm(Alu::Cmp, Reg::Rsi, Port::Text as i32);
+    asm.jcc(Cond::E, text);
+    asm.alu_imm(Alu::Cmp, Reg::Rsi, Port::Number as i32);
+    asm.jcc(Cond::E, number);
+    asm.store_imm(Reg::Rdi, LAST_OK, 0);
+    asm.ret();
+
+    // digits go onto the stack backwards, with r8 pointing at the last one
+    asm.bind(number);
+    let (digit, write) = (asm.new_label(), asm.new_label());
+    asm.alu_imm(Alu::Sub, Reg::Rsp, 24);
+    asm.lea(Reg::R8, Reg::Rsp, 24);
+    asm.mov(Reg::Rax, Reg::Rdx);
+    asm.mov_imm(Reg::Rcx, 10);
+    asm.bind(digit);
+    asm.alu(Alu::Xor, Reg::Rdx, Reg::Rdx);
+    asm.div(Reg::Rcx);
+    asm.alu_imm(Alu::Add, Reg::Rdx, b'0' as i32);
+    asm.alu_imm(Alu::Sub, Reg::R8, 1);
+    asm.store_byte(Reg::R8, 0, Reg::Rdx);
+    asm.test(Reg::Rax, Reg::Rax);
+    asm.jcc(Cond::Ne, digit);
+
+    asm.bind(write);
+    asm.load_byte(Reg::Rsi, Reg::R8, 0);
+    asm.call(putc);
+    asm.alu_imm(Alu::Add, Reg::R8, 1);
+    asm.lea(Reg::Rax, Reg::Rsp, 24);
+    asm.alu(Alu::Cmp, Reg::R8, Reg::Rax);
+    asm.jcc(Cond::B, write);
+    asm.alu_imm(Alu::Add, Reg::Rsp, 24);
+    asm.jmp(done);
+
+    // every byte is `(c >> shift) & mask | lead`
+    asm.bind(text);
+    let lengths = [asm.new_label(), asm.new_label(), asm.new_label(), asm.new_label()];
+    let (surrogate, invalid) = (asm.new_label(), asm.new_label());
+    asm.mov(Reg::R8, Reg::Rdx);
+    for (limit, label) in [(0x80, lengths[0]), (0x800, lengths[1]), (0x10000, surrogate), (0x110000, lengths[3])] {
+        asm.alu_imm(Alu::Cmp, Reg::R8, limit);
+        asm.jcc(Cond::B, label);
+    }
+
+    asm.bind(invalid);
+    asm.mov_imm(Reg::R8, char::REPLACEMENT_CHARACTER as u64);
+    asm.jmp(lengths[2]);
+
+    asm.bind(surrogate);
+    asm.mov(Reg::Rax, Reg::R8);
+    asm.alu_imm(Alu::And, Reg::Rax, 0xf800);
+    asm.alu_imm(Alu::Cmp, Reg::Rax, 0xd800);
+    asm.jcc(Cond::E, invalid);
+    asm.jmp(lengths[2]);
+
+    for (len, label) in lengths.iter().enumerate() {
+        asm.bind(*label);
+        let lead = [0, 0xc0, 0xe0, 0xf0][len];
+        for i in (0..=len).rev() {
+            let (lead, mask) = if i == len { (lead, if len == 0 { 0x7f } else { 0x3f >> len }) } else { (0x80, 0x3f) };
+            asm.mov(Reg::Rsi, Reg::R8);
+            if i != 0 {
+                asm.shift_imm(Shift::Shr, Reg::Rsi, 6 * i as u8);
+            }
+
+            asm.alu_imm(Alu::And, Reg::Rsi, mask);
+            if lead != 0 {
+                asm.alu_imm(Alu::Or, Reg::Rsi, lead);
+            }
+
+            asm.call(putc);
+        }
+
+        asm.jmp(done);
+    }
+
+    asm.bind(done);
+    asm.store_imm(Reg::Rdi, LAST_OK, 1);
+    asm.ret();
+}
+
+/// Lays out every function of the runtime
+fn runtime(asm: &mut Assembler, bits: usize) -> Runtime {
+    let rt = Runtime {
+        helpers: [0; HELPER_COUNT].map(|_| asm.new_label()),
+        flush: asm.new_label(),
+        putc: asm.new_label(),
+        puts: asm.new_label(),
+    };
+
+    asm.bind(rt.flush);
+    flush(asm);
+    asm.bind(rt.putc);
+    putc(asm, rt.flush);
+    asm.bind(rt.puts);
+    puts(asm, rt.putc);
+
+    asm.bind(rt.helpers[Helper::Alloc as usize]);
+    alloc(asm);
+    asm.bind(rt.helpers[Helper::PortRead as usize]);
+    port_read(asm, rt.flush, bits);
+    asm.bind(rt.helpers[Helper::PortWrite as usize]);
+    port_write(asm, rt.putc);
+
+    // errors only get written down, to be printed on the way out
+    asm.bind(rt.helpers[Helper::DivisionByZero as usize]);
+    asm.mov_imm(Reg::Rax, InterpreterError::DivisionByZero.code() as u64);
+    asm.store(Reg::Rdi, ERROR_KIND, Reg::Rax);
+    asm.ret();
+
+    asm.bind(rt.helpers[Helper::MemoryOob as usize]);
+    asm.mov_imm(Reg::Rax, InterpreterError::MemoryAccessOob(0).code() as u64);
+    asm.store(Reg::Rdi, ERROR_KIND, Reg::Rax);
+    asm.store(Reg::Rdi, ERROR_DETAIL, Reg::Rsi);
+    asm.ret();
+
+    asm.bind(rt.helpers[Helper::ReportError as usize]);
+    asm.store(Reg::Rdi, ERROR_KIND, Reg::Rsi);
+    asm.store(Reg::Rdi, ERROR_DETAIL, Reg::Rcx);
+    asm.ret();
+
+    rt
+}
+
+/// Writes `Error: <message>` to stderr like the CLI does, without pointing at the source
+fn report(asm: &mut Assembler, rt: &Runtime) {
+    let strings = asm.new_label();
+    let mut data = Vec::new();
+    let mut string = |s: &str| {
+        data.extend_from_slice(s.as_bytes());
+        (data.len() - s.len(), s.len())
+    };
+
+    let error = string("Error: ");
+    let newline = string("\n");
+    let kinds: Vec<_> = InterpreterError::CODES.map(|code| {
+        let (msg, detail) = InterpreterError::message_without_detail(code);
+        (string(&msg), detail)
+    }).collect();
+    let unknown = string(&InterpreterError::message_without_detail(u128::MAX).0);
+
+    let puts = |asm: &mut Assembler, (off, len): (usize, usize)| {
+        asm.lea_label(Reg::Rsi, strings);
+        asm.alu_imm(Alu::Add, Reg::Rsi, off as i32);
+        asm.mov_imm(Reg::Rdx, len as u64);
+        asm.call(rt.puts);
+    };
+    let number = |asm: &mut Assembler, disp| {
+        asm.load(Reg::Rdx, Reg::Rdi, disp);
+        asm.mov_imm(Reg::Rsi, Port::Number as u64);
+        asm.call(rt.helpers[Helper::PortWrite as usize]);
+    };
+
+    // what went to stdout so far goes out before switching over to stderr
+    asm.call(rt.flush);
+    asm.store_imm(Reg::Rdi, OUT_FD, 2);
+    puts(asm, error);
+
+    let done = asm.new_label();
+    let labels: Vec<_> = kinds.iter().map(|_| asm.new_label()).collect();
+    asm.load(Reg::Rax, Reg::Rdi, ERROR_KIND);
+    for (code, label) in labels.iter().enumerate() {
+        asm.alu_imm(Alu::Cmp, Reg::Rax, code as i32);
+        asm.jcc(Cond::E, *label);
+    }
+
+    puts(asm, unknown);
+    number(asm, ERROR_KIND);
+    asm.jmp(done);
+
+    for ((msg, detail), label) in kinds.iter().zip(labels) {
+        asm.bind(label);
+        puts(asm, *msg);
+        if *detail {
+            number(asm, ERROR_DETAIL);
+        }
+
+        asm.jmp(done);
+    }
+
+    asm.bind(done);
+    puts(asm, newline);
+    asm.call(rt.flush);
+    asm.mov_imm(Reg::Rdi, EXIT_ERROR);
+    syscall(asm, SYS_EXIT_GROUP);
+
+    asm.bind(strings);
+    asm.data(&data);
+}
+
+/// Compiles `ssa` into a static x86-64 Linux executable, which needs nothing but the kernel to
+/// run. Ports go to stdin and stdout, and runtime errors exit with the code the CLI uses for
+/// them.
+pub fn to_elf(ssa: &(Body, usize, usize)) -> Vec<u8> {
+    let (body, _, variables) = ssa;
+    let lowered = lower(ssa);
+
+    let code = TEXT + HEADERS as u64;
+    let runtime_at = code + lowered.code.len() as u64;
+    let regs = DATA + SLOTS as u64 + *variables as u64 * 16;
+    let data_size = regs - DATA + lowered.registers as u64 * 8;
+
+    let mut asm = Assembler::default();
+    let rt = runtime(&mut asm, body.bits);
+
+    let start = asm.new_label();
+    asm.bind(start);
+    asm.mov_imm(Reg::Rdi, DATA);
+    asm.mov_imm(Reg::Rax, regs);
+    asm.store(Reg::Rdi, REGS, Reg::Rax);
+    asm.lea(Reg::Rax, Reg::Rdi, SLOTS);
+    asm.store(Reg::Rdi, VARS, Reg::Rax);
+    asm.mov_imm(Reg::Rax, u64::MAX);
+    asm.store(Reg::Rdi, END, Reg::Rax);
+    asm.store_imm(Reg::Rdi, LAST_OK, 1);
+    asm.store_imm(Reg::Rdi, OUT_FD, 1);
+    for (i, helper) in rt.helpers.iter().enumerate() {
+        asm.lea_label(Reg::Rax, *helper);
+        asm.store(Reg::Rdi, HELPERS + i as i32 * 8, Reg::Rax);
+    }
+
+    let halted = asm.new_label();
+    if let Some(entry) = lowered.blocks.first() {
+        asm.mov_imm(Reg::Rsi, code + *entry as u64);
+        asm.mov_imm(Reg::Rax, code);
+        asm.call_reg(Reg::Rax);
+        asm.mov_imm(Reg::Rdi, DATA);
+        asm.alu_imm(Alu::Cmp, Reg::Rax, HALTED as i32);
+        asm.jcc(Cond::E, halted);
+        report(&mut asm, &rt);
+    }
+
+    asm.bind(halted);
+    asm.call(rt.flush);
+    asm.alu(Alu::Xor, Reg::Rdi, Reg::Rdi);
+    syscall(&mut asm, SYS_EXIT_GROUP);
+
+    let entry = runtime_at + asm.offset(start) as u64;
+    let runtime = asm.finish();
+    let text_size = (HEADERS + lowered.code.len() + runtime.len()) as u64;
+
+    let mut elf = Vec::with_capacity(text_size as usize);
+    // identification: 64-bit, little endian, current version, System V
+    elf.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    elf.extend_from_slice(&2u16.to_le_bytes()); // executable
+    elf.extend_from_slice(&0x3eu16.to_le_bytes()); // x86-64
+    elf.extend_from_slice(&1u32.to_le_bytes());
+    elf.extend_from_slice(&entry.to_le_bytes());
+    elf.extend_from_slice(&(EHDR_SIZE as u64).to_le_bytes());
+    elf.extend_from_slice(&0u64.to_le_bytes()); // no sections
+    elf.extend_from_slice(&0u32.to_le_bytes());
+    elf.extend_from_slice(&(EHDR_SIZE as u16).to_le_bytes());
+    elf.extend_from_slice(&(PHDR_SIZE as u16).to_le_bytes());
+    elf.extend_from_slice(&2u16.to_le_bytes());
+    elf.extend_from_slice(&64u16.to_le_bytes());
+    elf.extend_from_slice(&0u16.to_le_bytes());
+    elf.extend_from_slice(&0u16.to_le_bytes());
+
+    // the headers and code are loaded readable and executable, the data zeroed and writable
+    for (flags, vaddr, file_size, mem_size) in [(5u32, TEXT, text_size, text_size), (6, DATA, 0, data_size)] {
+        elf.extend_from_slice(&1u32.to_le_bytes()); // PT_LOAD
+        elf.extend_from_slice(&flags.to_le_bytes());
+        elf.extend_from_slice(&0u64.to_le_bytes());
+        elf.extend_from_slice(&vaddr.to_le_bytes());
+        elf.extend_from_slice(&vaddr.to_le_bytes());
+        elf.extend_from_slice(&file_size.to_le_bytes());
+        elf.extend_from_slice(&mem_size.to_le_bytes());
+        elf.extend_from_slice(&0x1000u64.to_le_bytes());
+    }
+
+    elf.extend_from_slice(&lowered.code);
+    elf.extend_from_slice(&runtime);
+    elf
+}
@@ -0,0 +1,295 @@
+use crate::compiler::{error::*, common::*, engine::*, backend::{ssa::*, codegen::{RAM, REG}, arch::x86_64::lower::*}};
+use derivative::Derivative;
+use logos::Span;
+use std::{mem::offset_of, ptr};
+
+/// `[pointer, length]` of a variable, as the generated code reads it
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Slot {
+    ptr: *mut u64,
+    len: u64,
+}
+
+/// The context the generated code runs with, laid out as [`lower`] expects
+#[repr(C)]
+struct Context {
+    regs: *mut u64,
+    vars: *mut Slot,
+    inst_count: u64,
+    end: u64,
+    resume: u64,
+    last_ok: u64,
+    helpers: [usize; HELPER_COUNT],
+}
+
+const _: () = {
+    assert!(offset_of!(Context, regs) == REGS as usize);
+    assert!(offset_of!(Context, vars) == VARS as usize);
+    assert!(offset_of!(Context, inst_count) == INST_COUNT as usize);
+    assert!(offset_of!(Context, end) == END as usize);
+    assert!(offset_of!(Context, resume) == RESUME as usize);
+    assert!(offset_of!(Context, last_ok) == LAST_OK as usize);
+    assert!(offset_of!(Context, helpers) == HELPERS as usize);
+};
+
+/// Everything the helpers need. The context comes first so that a pointer to it is a pointer to
+/// this as well.
+#[repr(C)]
+struct Runtime {
+    ctx: Context,
+
+    regs: Vec<u64>,
+    variables: Vec<Vec<u64>>,
+    slots: Vec<Slot>,
+
+    device: Box<dyn Device>,
+    mask: u64,
+    error: Option<Error<InterpreterError>>,
+
+    source: Vec<Span>,
+    divisions: Vec<Span>,
+    accesses: Vec<Span>,
+}
+
+impl Runtime {
+    fn sync_slot(&mut self, var: usize) {
+        let v = &mut self.variables[var];
+        self.slots[var] = Slot { ptr: v.as_mut_ptr(), len: v.len() as u64 };
+    }
+}
+
+// SAFETY of the helpers: the generated code only ever calls them with the context it got, which
+// is the start of a `Runtime` that the engine borrows mutably for the duration of the call.
+
+unsafe extern "C" fn alloc(rt: *mut Runtime, var: u64, size: u64) {
+    let rt = unsafe { &mut *rt };
+    rt.variables[var as usize] = vec![0; size as usize];
+    rt.sync_slot(var as usize);
+}
+
+unsafe extern "C" fn port_read(rt: *mut Runtime, port: u64) -> u64 {
+    let rt = unsafe { &mut *rt };
+    let res = rt.device.read(port as u128);
+    rt.ctx.last_ok = res.is_ok() as u64;
+    res.unwrap_or(0) as u64
+}
+
+unsafe extern "C" fn port_write(rt: *mut Runtime, port: u64, data: u64) {
+    let rt = unsafe { &mut *rt };
+    rt.ctx.last_ok = rt.device.write(port as u128, data as u128).is_ok() as u64;
+}
+
+unsafe extern "C" fn division_by_zero(rt: *mut Runtime, site: u64) {
+    let rt = unsafe { &mut *rt };
+    rt.error = Some(Error { kind: InterpreterError::DivisionByZero, span: rt.divisions[site as usize].clone() });
+}
+
+unsafe extern "C" fn memory_oob(rt: *mut Runtime, index: u64, site: u64) {
+    let rt = unsafe { &mut *rt };
+    rt.error = Some(Error { kind: InterpreterError::MemoryAccessOob(index as u128), span: rt.accesses[site as usize].clone() });
+}
+
+unsafe extern "C" fn report_error(rt: *mut Runtime, kind: u64, pc: u64, detail: u64) {
+    let rt = unsafe { &mut *rt };
+    rt.error = Some(Error {
+        kind: InterpreterError::from_code(kind as u128, detail as u128),
+        span: rt.source.get(pc as usize).cloned().unwrap_or_default(),
+    });
+}
+
+/// Pages of machine code, executable but no longer writable
+struct ExecutableMemory {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+impl ExecutableMemory {
+    fn new(code: &[u8]) -> Self {
+        let len = code.len().max(1);
+
+        // SAFETY: a fresh anonymous mapping doesn't alias anything, and it is only written to
+        // while it is still writable
+        unsafe {
+            let ptr = libc::mmap(ptr::null_mut(), len, libc::PROT_READ | libc::PROT_WRITE, libc::MAP_PRIVATE | libc::MAP_ANONYMOUS, -1, 0);
+            assert!(ptr != libc::MAP_FAILED, "couldn't map memory for the JIT");
+
+            ptr::copy_nonoverlapping(code.as_ptr(), ptr as *mut u8, code.len());
+            assert!(libc::mprotect(ptr, len, libc::PROT_READ | libc::PROT_EXEC) == 0, "couldn't make the JIT code executable");
+
+            Self { ptr, len }
+        }
+    }
+}
+
+impl Drop for ExecutableMemory {
+    fn drop(&mut self) {
+        // SAFETY: the mapping came from `mmap` and nothing points into it anymore
+        unsafe { libc::munmap(self.ptr, self.len) };
+    }
+}
+
+type Entry = unsafe extern "C" fn(*mut Runtime, u64) -> u32;
+
+/// Runs a body compiled into native x86-64 code
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct Jit {
+    #[derivative(Debug="ignore")]
+    code: ExecutableMemory,
+    #[derivative(Debug="ignore")]
+    runtime: Box<Runtime>,
+
+    halted: bool,
+    /// Whether every variable is still around after optimisation
+    kept: Vec<bool>,
+}
+
+impl Jit {
+    pub fn new(ssa: (Body, usize, usize)) -> Self {
+        let lowered = lower(&ssa);
+        let (body, _, variables) = ssa;
+        let code = ExecutableMemory::new(&lowered.code);
+        let kept = (0..variables).map(|v| body.allocates(VariableId(v))).collect();
+
+        let mut runtime = Box::new(Runtime {
+            ctx: Context {
+                regs: ptr::null_mut(),
+                vars: ptr::null_mut(),
+                inst_count: 0,
+                end: 0,
+                resume: lowered.blocks.first().map_or(0, |b| code.ptr as u64 + *b as u64),
+                last_ok: 1,
+                helpers: [
+                    alloc as *const () as usize,
+                    port_read as *const () as usize,
+                    port_write as *const () as usize,
+                    division_by_zero as *const () as usize,
+                    memory_oob as *const () as usize,
+                    report_error as *const () as usize,
+                ],
+            },
+
+            regs: vec![0; lowered.registers],
+            variables: vec![Vec::new(); variables],
+            slots: vec![Slot { ptr: ptr::null_mut(), len: 0 }; variables],
+
+            device: Box::new(NoDevice),
+            mask: u64::mask(body.bits),
+            error: None,
+
+            source: body.spans,
+            divisions: lowered.divisions,
+            accesses: lowered.accesses,
+        });
+
+        for var in 0..variables {
+            runtime.sync_slot(var);
+        }
+
+        runtime.ctx.regs = runtime.regs.as_mut_ptr();
+        runtime.ctx.vars = runtime.slots.as_mut_ptr();
+
+        Self {
+            halted: lowered.blocks.is_empty(),
+            code,
+            runtime,
+            kept,
+        }
+    }
+
+    fn variable(&self, var: VariableId, index: u128) -> Result<u64, InterpreterError> {
+        self.runtime.variables.get(*var)
+            .and_then(|v| v.get(usize::try_from(index).ok()?).copied())
+            .ok_or(InterpreterError::MemoryAccessOob(index))
+    }
+
+    fn variable_mut(&mut self, var: VariableId, index: u128) -> Result<&mut u64, InterpreterError> {
+        self.runtime.variables.get_mut(*var)
+            .and_then(|v| v.get_mut(usize::try_from(index).ok()?))
+            .ok_or(InterpreterError::MemoryAccessOob(index))
+    }
+
+    /// Fails when `var` got promoted into values, so there's nothing left to look at
+    fn kept(&self, var: VariableId) -> Result<(), InterpreterError> {
+        if self.kept[*var] { Ok(()) } else { Err(InterpreterError::OptimisedAway) }
+    }
+}
+
+impl Engine for Jit {
+    fn step(&mut self) -> StepResult {
+        self.run(1)
+    }
+
+    fn run(&mut self, budget: usize) -> StepResult {
+        if self.halted {
+            return StepResult::Halted;
+        }
+
+        let ctx = &mut self.runtime.ctx;
+        ctx.end = ctx.inst_count.saturating_add(budget as u64);
+        let resume = ctx.resume;
+
+        // SAFETY: the code starts with the entry point `lower` promises, and the runtime is
+        // set up the way the helpers expect it
+        let status = unsafe {
+            let entry: Entry = std::mem::transmute(self.code.ptr);
+            entry(&mut *self.runtime, resume)
+        };
+
+        match status {
+            RUNNING => StepResult::Running,
+            HALTED => {
+                self.halted = true;
+                StepResult::Halted
+            },
+            _ => {
+                // the block that failed can't be picked up halfway through
+                self.halted = true;
+                StepResult::Error(self.runtime.error.take().expect("no error was reported"))
+            },
+        }
+    }
+
+    fn register(&self, index: usize) -> Result<u128, InterpreterError> {
+        if index == 0 {
+            return Ok(0);
+        }
+
+        self.kept(REG)?;
+        self.variable(REG, index as u128 - 1)
+            .map(u128::from)
+            .map_err(|_| InterpreterError::RegisterOob(index as u64))
+    }
+
+    fn set_register(&mut self, index: usize, value: u128) -> Result<(), InterpreterError> {
+        if index == 0 {
+            return Ok(());
+        }
+
+        self.kept(REG)?;
+        let mask = self.runtime.mask;
+        *self.variable_mut(REG, index as u128 - 1).map_err(|_| InterpreterError::RegisterOob(index as u64))? = value as u64 & mask;
+        Ok(())
+    }
+
+    fn memory(&self, address: u128) -> Result<u128, InterpreterError> {
+        self.kept(RAM)?;
+        self.variable(RAM, address).map(u128::from)
+    }
+
+    fn set_memory(&mut self, address: u128, value: u128) -> Result<(), InterpreterError> {
+        self.kept(RAM)?;
+        let mask = self.runtime.mask;
+        *self.variable_mut(RAM, address)? = value as u64 & mask;
+        Ok(())
+    }
+
+    fn attach_device(&mut self, device: Box<dyn Device>) {
+        self.runtime.device = device;
+    }
+
+    fn stats(&self) -> Stats {
+        Stats { inst_count: self.runtime.ctx.inst_count as usize }
+    }
+}
@@ -0,0 +1,460 @@
+use crate::compiler::backend::{ssa::*, regalloc::*, arch::x86_64::asm::*};
+use logos::Span;
+
+// Layout of the context the generated code gets in rdi, kept in rbp while it runs.
+/// Pointer to the stack area of the values, kept in rbx while the code runs
+pub const REGS: i32 = 0;
+/// Pointer to a `[pointer, length]` pair for every variable
+pub const VARS: i32 = 8;
+pub const INST_COUNT: i32 = 16;
+/// `INST_COUNT` to stop at
+pub const END: i32 = 24;
+/// Address to continue at after running out of budget
+pub const RESUME: i32 = 32;
+/// Whether the last port access went fine, as 0 or 1
+pub const LAST_OK: i32 = 40;
+/// Addresses of every [`Helper`], in order
+pub const HELPERS: i32 = 48;
+
+/// What the generated code returns
+pub const RUNNING: u32 = 0;
+pub const HALTED: u32 = 1;
+pub const ERROR: u32 = 2;
+
+/// Functions the generated code calls through the context for everything it can't do itself.
+/// They take the context as the first argument, following the System V calling convention.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Helper {
+    /// `(ctx, var, size)`, reallocates a variable and updates its pointer and length
+    Alloc,
+    /// `(ctx, port) -> data`, sets `LAST_OK`
+    PortRead,
+    /// `(ctx, port, data)`, sets `LAST_OK`
+    PortWrite,
+    /// `(ctx, site)`, reports a division by zero at [`Lowered::divisions`] `site`
+    DivisionByZero,
+    /// `(ctx, index, site)`, reports an access out of the bounds of a variable at
+    /// [`Lowered::accesses`] `site`
+    MemoryOob,
+    /// `(ctx, kind, pc, detail)`, reports an error
+    ReportError,
+}
+
+pub const HELPER_COUNT: usize = 6;
+
+/// Registers the values are kept in, which the helpers leave alone. The others hold the context
+/// or are used as scratch.
+const ALLOCATABLE: [Reg; 4] = [Reg::R12, Reg::R13, Reg::R14, Reg::R15];
+
+fn register(r: usize) -> Reg {
+    *ALLOCATABLE.iter().find(|a| **a as usize == r).expect("register that can't be allocated")
+}
+
+/// Machine code for a body
+pub struct Lowered {
+    /// Starts with `extern "C" fn(ctx, entry) -> status`, which jumps to `entry` after setting
+    /// everything up
+    pub code: Vec<u8>,
+    /// Offset of every block
+    pub blocks: Vec<usize>,
+    /// Words the stack area takes up, which holds the values that didn't get a register, a
+    /// scratch one for Phi nodes, and the registers while the code isn't running
+    pub registers: usize,
+    /// Span of every division
+    pub divisions: Vec<Span>,
+    /// Span of every variable access
+    pub accesses: Vec<Span>,
+}
+
+struct Lowering<'a> {
+    body: &'a Body,
+    asm: Assembler,
+    blocks: Vec<Label>,
+    epilogue: Label,
+    error: Label,
+
+    alloc: Allocation,
+    divisions: Vec<Span>,
+    accesses: Vec<Span>,
+}
+
+fn slot(v: usize) -> i32 {
+    i32::try_from(v * 8).expect("too many values")
+}
+
+fn helper(h: Helper) -> i32 {
+    HELPERS + h as i32 * 8
+}
+
+impl Lowering<'_> {
+    fn load(&mut self, dst: Reg, v: &ValueId) {
+        match self.alloc.location(*v) {
+            Location::Register(r) => self.asm.mov(dst, register(r)),
+            Location::Stack(s) => self.asm.load(dst, Reg::Rbx, slot(s)),
+        }
+    }
+
+    fn store(&mut self, dst: Option<ValueId>, src: Reg) {
+        match dst.map(|d| self.alloc.location(d)) {
+            Some(Location::Register(r)) => self.asm.mov(register(r), src),
+            Some(Location::Stack(s)) => self.asm.store(Reg::Rbx, slot(s), src),
+            None => {},
+        }
+    }
+
+    /// Copies between two locations, going through rax when both are in memory
+    fn copy(&mut self, dst: Location, src: Location) {
+        match (dst, src) {
+            (Location::Register(d), Location::Register(s)) => self.asm.mov(register(d), register(s)),
+            (Location::Register(d), Location::Stack(s)) => self.asm.load(register(d), Reg::Rbx, slot(s)),
+            (Location::Stack(d), Location::Register(s)) => self.asm.store(Reg::Rbx, slot(d), register(s)),
+            (Location::Stack(d), Location::Stack(s)) => {
+                self.asm.load(Reg::Rax, Reg::Rbx, slot(s));
+                self.asm.store(Reg::Rbx, slot(d), Reg::Rax);
+            },
+        }
+    }
+
+    fn call(&mut self, h: Helper) {
+        self.asm.mov(Reg::Rdi, Reg::Rbp);
+        self.asm.call_mem(Reg::Rbp, helper(h));
+    }
+
+    /// Leaves the variable's pointer in rdx and the index in rcx, after checking the bounds
+    fn access(&mut self, var: &VariableId, off: &ValueId, span: Span) {
+        let ok = self.asm.new_label();
+        self.load(Reg::Rcx, off);
+        self.asm.load(Reg::Rdx, Reg::Rbp, VARS);
+        self.asm.alu_load(Alu::Cmp, Reg::Rcx, Reg::Rdx, slot(**var * 2 + 1));
+        self.asm.jcc(Cond::B, ok);
+
+        self.asm.mov(Reg::Rsi, Reg::Rcx);
+        self.asm.mov_imm(Reg::Rdx, self.accesses.len() as u64);
+        self.accesses.push(span);
+        self.call(Helper::MemoryOob);
+        self.asm.jmp(self.error);
+
+        self.asm.bind(ok);
+        self.asm.load(Reg::Rdx, Reg::Rdx, slot(**var * 2));
+    }
+
+    fn instruction(&mut self, instr: &Instruction, span: Span) {
+        let dst = instr.destination;
+
+        match &instr.operation {
+            Operation::Phi(_) => {},
+            Operation::Integer(int) => {
+                let imm = *int as u64;
+                match (dst.map(|d| self.alloc.location(d)), i32::try_from(imm as i64)) {
+                    (None, _) => {},
+                    (Some(Location::Register(r)), _) => self.asm.mov_imm(register(r), imm),
+                    (Some(Location::Stack(s)), Ok(imm)) => self.asm.store_imm(Reg::Rbx, slot(s), imm),
+                    (Some(Location::Stack(_)), Err(_)) => {
+                        self.asm.mov_imm(Reg::Rax, imm);
+                        self.store(dst, Reg::Rax);
+                    },
+                }
+            },
+            Operation::BinOp(op, l, r) => self.binop(*op, dst, l, r, span),
+            Operation::Allocate(var, size) => {
+                self.asm.mov_imm(Reg::Rsi, **var as u64);
+                self.load(Reg::Rdx, size);
+                self.call(Helper::Alloc);
+            },
+            Operation::LoadIndex(var, off) => {
+                self.access(var, off, span);
+                self.asm.load_index(Reg::Rax, Reg::Rdx, Reg::Rcx);
+                self.store(dst, Reg::Rax);
+            },
+            Operation::StoreIndex(var, off, val) => {
+                self.access(var, off, span);
+                self.load(Reg::Rax, val);
+                self.asm.store_index(Reg::Rdx, Reg::Rcx, Reg::Rax);
+            },
+            Operation::Call(Function::PortRead, args) => {
+                self.load(Reg::Rsi, &args[0]);
+                self.call(Helper::PortRead);
+                self.store(dst, Reg::Rax);
+            },
+            Operation::Call(Function::PortWrite, args) => {
+                self.load(Reg::Rsi, &args[0]);
+                self.load(Reg::Rdx, &args[1]);
+                self.call(Helper::PortWrite);
+            },
+            Operation::Call(Function::LastOk, _) => {
+                self.asm.load(Reg::Rax, Reg::Rbp, LAST_OK);
+                self.store(dst, Reg::Rax);
+            },
+            Operation::Call(Function::ReportError, args) => {
+                self.load(Reg::Rsi, &args[0]);
+                self.load(Reg::Rdx, &args[1]);
+                self.load(Reg::Rcx, &args[2]);
+                self.call(Helper::ReportError);
+                self.asm.jmp(self.error);
+            },
+        }
+    }
+
+    fn binop(&mut self, op: BinOp, dst: Option<ValueId>, l: &ValueId, r: &ValueId, span: Span) {
+        self.load(Reg::Rax, l);
+        self.load(Reg::Rcx, r);
+
+        let alu = |op| match op {
+            BinOp::Add => Some(Alu::Add),
+            BinOp::Sub => Some(Alu::Sub),
+            BinOp::And => Some(Alu::And),
+            BinOp::Or  => Some(Alu::Or),
+            BinOp::Xor => Some(Alu::Xor),
+            _ => None,
+        };
+
+        let cond = |op| match op {
+            BinOp::Eq  => Some(Cond::E),
+            BinOp::Ne  => Some(Cond::Ne),
+            BinOp::Lt | BinOp::SLt => Some(Cond::B),
+            BinOp::Le | BinOp::SLe => Some(Cond::Be),
+            BinOp::Gt | BinOp::SGt => Some(Cond::A),
+            BinOp::Ge | BinOp::SGe => Some(Cond::Ae),
+            _ => None,
+        };
+
+        if let Some(alu) = alu(op) {
+            self.asm.alu(alu, Reg::Rax, Reg::Rcx);
+        } else if let Some(cond) = cond(op) {
+            // signed comparisons flip the sign bit so that they can compare as unsigned
+            if matches!(op, BinOp::SLt | BinOp::SLe | BinOp::SGt | BinOp::SGe) {
+                self.asm.mov_imm(Reg::Rdx, 1 << (self.body.bits.clamp(1, 64) - 1));
+                self.asm.alu(Alu::Xor, Reg::Rax, Reg::Rdx);
+                self.asm.alu(Alu::Xor, Reg::Rcx, Reg::Rdx);
+            }
+
+            self.asm.alu(Alu::Cmp, Reg::Rax, Reg::Rcx);
+            self.asm.set(cond, Reg::Rax);
+        } else {
+            match op {
+                BinOp::Mul => self.asm.imul(Reg::Rax, Reg::Rcx),
+                BinOp::Shl | BinOp::Shr => {
+                    // everything is shifted out when shifting by the word size or more
+                    self.asm.shift(if op == BinOp::Shl { Shift::Shl } else { Shift::Shr }, Reg::Rax);
+                    self.asm.alu(Alu::Xor, Reg::Rdx, Reg::Rdx);
+                    self.asm.alu_imm(Alu::Cmp, Reg::Rcx, 64);
+                    self.asm.cmov(Cond::Ae, Reg::Rax, Reg::Rdx);
+                },
+                BinOp::Div | BinOp::Mod | BinOp::SDiv | BinOp::SMod => self.division(op, span),
+                BinOp::Sar => {
+                    // arithmetic shifts by the word size or more fill the word with its sign
+                    self.sign_extend(Reg::Rax);
+                    self.asm.mov_imm(Reg::Rdx, 63);
+                    self.asm.alu(Alu::Cmp, Reg::Rcx, Reg::Rdx);
+                    self.asm.cmov(Cond::A, Reg::Rcx, Reg::Rdx);
+                    self.asm.shift(Shift::Sar, Reg::Rax);
+                    self.truncate(Reg::Rax);
+                },
+                _ => unreachable!(),
+            }
+        }
+
+        self.store(dst, Reg::Rax);
+    }
+
+    /// Sign-extends a word of `bits` bits in `reg` to 64 bits
+    fn sign_extend(&mut self, reg: Reg) {
+        let unused = 64 - self.body.bits as u8;
+        if unused != 0 {
+            self.asm.shift_imm(Shift::Shl, reg, unused);
+            self.asm.shift_imm(Shift::Sar, reg, unused);
+        }
+    }
+
+    /// Cuts the bits of `reg` above `bits` off, clobbering rdx
+    fn truncate(&mut self, reg: Reg) {
+        if self.body.bits < 64 {
+            self.asm.mov_imm(Reg::Rdx, u64::MAX >> (64 - self.body.bits));
+            self.asm.alu(Alu::And, reg, Reg::Rdx);
+        }
+    }
+
+    /// Divides rax by rcx, leaving the quotient or the remainder in rax
+    fn division(&mut self, op: BinOp, span: Span) {
+        let nonzero = self.asm.new_label();
+        self.asm.test(Reg::Rcx, Reg::Rcx);
+        self.asm.jcc(Cond::Ne, nonzero);
+        self.asm.mov_imm(Reg::Rsi, self.divisions.len() as u64);
+        self.divisions.push(span);
+        self.call(Helper::DivisionByZero);
+        self.asm.jmp(self.error);
+        self.asm.bind(nonzero);
+
+        if matches!(op, BinOp::Div | BinOp::Mod) {
+            self.asm.alu(Alu::Xor, Reg::Rdx, Reg::Rdx);
+            self.asm.div(Reg::Rcx);
+            if op == BinOp::Mod {
+                self.asm.mov(Reg::Rax, Reg::Rdx);
+            }
+
+            return;
+        }
+
+        // dividing by -1 is a negation, which also keeps `idiv` from overflowing
+        let (divide, done) = (self.asm.new_label(), self.asm.new_label());
+        self.sign_extend(Reg::Rax);
+        self.sign_extend(Reg::Rcx);
+        self.asm.alu_imm(Alu::Cmp, Reg::Rcx, -1);
+        self.asm.jcc(Cond::Ne, divide);
+        if op == BinOp::SDiv {
+            self.asm.neg(Reg::Rax);
+        } else {
+            self.asm.alu(Alu::Xor, Reg::Rax, Reg::Rax);
+        }
+
+        self.asm.jmp(done);
+        self.asm.bind(divide);
+        self.asm.cqo();
+        self.asm.idiv(Reg::Rcx);
+        if op == BinOp::SMod {
+            self.asm.mov(Reg::Rax, Reg::Rdx);
+        }
+
+        self.asm.bind(done);
+        self.truncate(Reg::Rax);
+    }
+
+    /// Copies the values every Phi node of `to` takes from `from`, all at once
+    fn edge(&mut self, from: BlockId, to: BlockId) {
+        let copies = phi_copies(self.body, from, to).into_iter()
+            .map(|(dst, src)| (self.alloc.location(dst), self.alloc.location(src)))
+            .collect();
+
+        // the slot right after the stack area is kept for breaking cycles
+        for (dst, src) in sequentialise(copies, Location::Stack(self.alloc.stack)) {
+            self.copy(dst, src);
+        }
+    }
+
+    /// Counts the block that ends here and goes on to `to`, unless the budget ran out
+    fn goto(&mut self, from: BlockId, to: BlockId, count: usize) {
+        self.edge(from, to);
+        let to = self.blocks[*to];
+
+        if count != 0 {
+            self.asm.alu_store_imm(Alu::Add, Reg::Rbp, INST_COUNT, count as i32);
+        }
+
+        self.asm.load(Reg::Rax, Reg::Rbp, INST_COUNT);
+        self.asm.alu_load(Alu::Cmp, Reg::Rax, Reg::Rbp, END);
+        self.asm.jcc(Cond::B, to);
+
+        self.asm.lea_label(Reg::Rax, to);
+        self.asm.store(Reg::Rbp, RESUME, Reg::Rax);
+        self.asm.mov_imm(Reg::Rax, RUNNING as u64);
+        self.asm.jmp(self.epilogue);
+    }
+
+    fn block(&mut self, blk: &Block) {
+        self.asm.bind(self.blocks[*blk.id]);
+
+        for (i, instr) in blk.instructions.iter().enumerate() {
+            self.instruction(instr, blk.span_at(i).cloned().unwrap_or_default());
+        }
+
+        let count = blk.spans.len();
+        match blk.terminator {
+            Terminator::Jump(to) => self.goto(blk.id, to, count),
+            Terminator::Branch(cond, if_, else_) => {
+                let else_label = self.asm.new_label();
+                self.load(Reg::Rax, &cond);
+                self.asm.test(Reg::Rax, Reg::Rax);
+                self.asm.jcc(Cond::E, else_label);
+                self.goto(blk.id, if_, count);
+
+                self.asm.bind(else_label);
+                self.goto(blk.id, else_, count);
+            },
+            Terminator::Return => {
+                self.asm.alu_store_imm(Alu::Add, Reg::Rbp, INST_COUNT, count as i32);
+                self.asm.mov_imm(Reg::Rax, HALTED as u64);
+                self.asm.jmp(self.epilogue);
+            },
+            Terminator::Trap | Terminator::None => self.asm.ud2(),
+        }
+    }
+}
+
+/// Lowers `ssa` into x86-64 machine code for words of up to 64 bits, keeping as many values in
+/// registers as fit and the rest in memory
+pub fn lower(ssa: &(Body, usize, usize)) -> Lowered {
+    let (body, values, _) = ssa;
+    assert!(body.bits <= 64, "x86-64 code only goes up to 64-bit words");
+
+    let target = Target {
+        registers: 16,
+        reserved: (0..16).filter(|r| !ALLOCATABLE.iter().any(|a| *a as usize == *r)).collect(),
+    };
+    let alloc = allocate(body, *values, &target);
+    // the registers are put after the stack area and the scratch slot when the code returns, as
+    // it may be resumed halfway through
+    let saved = alloc.stack + 1;
+
+    let mut asm = Assembler::default();
+    let blocks = body.blocks.iter().map(|_| asm.new_label()).collect();
+    let (epilogue, error) = (asm.new_label(), asm.new_label());
+
+    // entered with the stack 8 bytes off from being aligned, which the pushes and the
+    // padding fix for the calls to the helpers
+    asm.push(Reg::Rbx);
+    asm.push(Reg::Rbp);
+    for reg in ALLOCATABLE {
+        asm.push(reg);
+    }
+
+    asm.alu_imm(Alu::Sub, Reg::Rsp, 8);
+    asm.mov(Reg::Rbp, Reg::Rdi);
+    asm.load(Reg::Rbx, Reg::Rbp, REGS);
+    for (i, reg) in ALLOCATABLE.into_iter().enumerate() {
+        asm.load(reg, Reg::Rbx, slot(saved + i));
+    }
+
+    asm.jmp_reg(Reg::Rsi);
+
+    let mut lowering = Lowering {
+        body,
+        asm,
+        blocks,
+        epilogue,
+        error,
+
+        alloc,
+        divisions: Vec::new(),
+        accesses: Vec::new(),
+    };
+
+    for blk in body.blocks.iter() {
+        lowering.block(blk);
+    }
+
+    let mut asm = lowering.asm;
+    asm.bind(error);
+    asm.mov_imm(Reg::Rax, ERROR as u64);
+    asm.bind(epilogue);
+    for (i, reg) in ALLOCATABLE.into_iter().enumerate() {
+        asm.store(Reg::Rbx, slot(saved + i), reg);
+    }
+
+    asm.alu_imm(Alu::Add, Reg::Rsp, 8);
+    for reg in ALLOCATABLE.into_iter().rev() {
+        asm.pop(reg);
+    }
+
+    asm.pop(Reg::Rbp);
+    asm.pop(Reg::Rbx);
+    asm.ret();
+
+    let blocks = lowering.blocks.iter().map(|l| asm.offset(*l)).collect();
+    Lowered {
+        code: asm.finish(),
+        blocks,
+        registers: saved + ALLOCATABLE.len(),
+        divisions: lowering.divisions,
+        accesses: lowering.accesses,
+    }
+}
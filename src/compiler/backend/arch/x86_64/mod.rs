@@ -0,0 +1,5 @@
+pub mod asm;
+pub mod lower;
+pub mod elf;
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+pub mod jit;
@@ -1,9 +1,11 @@
-use super::ssa::*;
+use super::{ssa::*, analysis::*};
 use logos::Span;
+use std::rc::Rc;
 
 #[derive(Default)]
 pub struct Builder {
     body: Body,
+    analyses: Analyses,
 
     block_id: BlockId,
     variable_id: VariableId,
@@ -11,27 +13,117 @@ pub struct Builder {
 }
 
 impl Builder {
+    /// Continues building on an already generated body, e.g. to run passes over it
+    pub fn from_ssa(ssa: (Body, usize, usize)) -> Self {
+        Self {
+            block_id: BlockId(ssa.0.blocks.len()),
+            body: ssa.0,
+            analyses: Analyses::default(),
+            value_id: ValueId(ssa.1),
+            variable_id: VariableId(ssa.2),
+        }
+    }
+
     pub fn get_ssa(self) -> (Body, usize, usize) {
         (self.body, *self.value_id, *self.variable_id)
     }
 
+    pub const fn body(&self) -> &Body {
+        &self.body
+    }
+
+    /// Gives out the body for changing it directly, which throws away every analysis of it
+    pub fn body_mut(&mut self) -> &mut Body {
+        self.analyses.invalidate();
+        &mut self.body
+    }
+
+    pub fn cfg(&mut self) -> Rc<Cfg> {
+        self.analyses.cfg(&self.body)
+    }
+
+    pub fn dominators(&mut self) -> Rc<Dominators> {
+        self.analyses.dominators(&self.body)
+    }
+
+    pub fn frontiers(&mut self) -> Rc<DominanceFrontiers> {
+        self.analyses.frontiers(&self.body)
+    }
+
+    pub fn loops(&mut self) -> Rc<LoopNest> {
+        self.analyses.loops(&self.body)
+    }
+
+    pub fn liveness(&mut self) -> Rc<Liveness> {
+        self.analyses.liveness(&self.body)
+    }
+
+    pub fn set_bits(&mut self, bits: usize) {
+        self.body.bits = bits;
+    }
+
+    pub fn set_spans(&mut self, spans: Vec<Span>) {
+        self.body.spans = spans;
+    }
+
     pub fn append_block<A: Into<String>>(&mut self, name: A, span: Option<Span>) -> BlockId {
+        self.analyses.invalidate();
         self.body.blocks.push(Block {
             name: name.into(),
             id: self.block_id,
             instructions: Vec::new(),
             terminator: Terminator::None,
-            span
+            spans: span.into_iter().map(|s| (0, s)).collect(),
         });
         *self.block_id += 1;
         BlockId(*self.block_id - 1)
     }
 
+    /// Removes every block not marked in `keep` and renumbers the rest. Nothing may branch into a
+    /// removed block anymore, Phi nodes just forget about removed predecessors.
+    pub fn retain_blocks(&mut self, keep: &[bool]) {
+        let mut map = Vec::with_capacity(keep.len());
+        let mut n = 0;
+        for k in keep {
+            map.push(k.then(|| {
+                n += 1;
+                BlockId(n - 1)
+            }));
+        }
+
+        self.analyses.invalidate();
+        let mut i = 0;
+        self.body.blocks.retain(|_| {
+            i += 1;
+            keep[i - 1]
+        });
+
+        for blk in self.body.blocks.iter_mut() {
+            blk.id = map[*blk.id].unwrap();
+            for succ in blk.terminator.successors_mut() {
+                *succ = map[**succ].expect("branch into a removed block");
+            }
+
+            for instr in blk.instructions.iter_mut() {
+                if let Operation::Phi(branches) = &mut instr.operation {
+                    branches.retain(|(_, b)| map[**b].is_some());
+                    for (_, b) in branches.iter_mut() {
+                        *b = map[**b].unwrap();
+                    }
+                }
+            }
+        }
+
+        self.block_id = BlockId(n);
+    }
+
     pub fn append_instruction(&mut self, block: BlockId, inst: Instruction) {
+        self.analyses.invalidate();
         self.body.blocks[*block].instructions.push(inst)
     }
 
     pub fn set_terminator(&mut self, block: BlockId, term: Terminator) {
+        self.analyses.invalidate();
         self.body.blocks[*block].terminator = term;
     }
 
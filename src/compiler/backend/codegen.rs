@@ -1,40 +1,50 @@
 use crate::{
     instruction,
     compiler::{
+        error::InterpreterError,
         common::{Any, Instruction as Inst},
         frontend::ast::*,
         backend::{ssa::*, builder::*}
     },
 };
 
+/// The variable holding the RAM of the program
+pub const RAM: VariableId = VariableId(0);
+/// The variable holding `r1` and up, `r0` is never stored
+pub const REG: VariableId = VariableId(1);
+
 pub fn generate_ssa(ast: Ast) -> (Body, usize, usize) {
     let mut builder = Builder::default();
+    builder.set_bits(ast.bits);
+    builder.set_spans(ast.instructions.iter().map(|(_, span)| span.clone()).collect());
 
     let alloc = builder.append_block("alloc", None);
 
     let ram = builder.allocate_variable();
     let ram_size = builder.allocate_value();
-    builder.append_instruction(alloc, instruction!(Operation::Integer((ast.minheap + ast.minstack + ast.dw.len()) as u64) => ram_size));
+    builder.append_instruction(alloc, instruction!(Operation::Integer((ast.minheap + ast.minstack + ast.dw.len()) as u128) => ram_size));
     builder.append_instruction(alloc, instruction!(Operation::Allocate(ram, ram_size)));
 
     let reg = builder.allocate_variable();
     let reg_size = builder.allocate_value();
-    builder.append_instruction(alloc, instruction!(Operation::Integer(ast.minreg as u64) => reg_size));
+    builder.append_instruction(alloc, instruction!(Operation::Integer(ast.minreg as u128) => reg_size));
     builder.append_instruction(alloc, instruction!(Operation::Allocate(reg, reg_size)));
 
     let init = builder.append_block("init", None);
     builder.set_terminator(alloc, Terminator::Jump(init));
 
     let bit_mask = builder.allocate_value();
-    builder.append_instruction(init, instruction!(Operation::Integer((1_u64 << ast.bits) - 1) => bit_mask));
+    builder.append_instruction(init, instruction!(Operation::Integer(ast.mask()) => bit_mask));
     let zero = builder.allocate_value();
     builder.append_instruction(init, instruction!(Operation::Integer(0) => zero));
+    let one = builder.allocate_value();
+    builder.append_instruction(init, instruction!(Operation::Integer(1) => one));
 
     for (i, w) in ast.dw.iter().enumerate() {
         let idx = builder.allocate_value();
         let wrd = builder.allocate_value();
-        builder.append_instruction(init, instruction!(Operation::Integer(i as u64) => idx));
-        builder.append_instruction(init, instruction!(Operation::Integer(*w) => wrd));
+        builder.append_instruction(init, instruction!(Operation::Integer(i as u128) => idx));
+        builder.append_instruction(init, instruction!(Operation::Integer(*w & ast.mask()) => wrd));
         builder.append_instruction(init, instruction!(Operation::StoreIndex(ram, idx, wrd)));
     }
 
@@ -49,7 +59,7 @@ pub fn generate_ssa(ast: Ast) -> (Body, usize, usize) {
     builder.set_terminator(init, Terminator::Jump(blocks.first().unwrap().clone()));
 
     for (i, (inst, _)) in ast.instructions.iter().enumerate() {
-        let block = blocks[i].clone();
+        let mut block = blocks[i];
 
         macro_rules! get_value {
             (any $a: expr) => {{
@@ -64,7 +74,7 @@ pub fn generate_ssa(ast: Ast) -> (Body, usize, usize) {
                 if a != 0 {
                     let a_value = builder.allocate_value();
                     let res_a = builder.allocate_value();
-                    builder.append_instruction(block, instruction!(Operation::Integer(a as u64 - 1) => a_value));
+                    builder.append_instruction(block, instruction!(Operation::Integer(a as u128 - 1) => a_value));
                     builder.append_instruction(block, instruction!(Operation::LoadIndex(reg, a_value) => res_a));
                     res_a
                 } else {
@@ -73,31 +83,123 @@ pub fn generate_ssa(ast: Ast) -> (Body, usize, usize) {
             }};
             (imm $a: expr) => {{
                 let a_value = builder.allocate_value();
-                builder.append_instruction(block, instruction!(Operation::Integer($a) => a_value));
+                builder.append_instruction(block, instruction!(Operation::Integer($a & ast.mask()) => a_value));
                 a_value
             }};
         }
 
+        // every result is truncated to the lower `bits` bits to act like a `bits`-wide machine
+        macro_rules! trunc {
+            ($v: expr) => {{
+                let v = $v;
+                let t = builder.allocate_value();
+                builder.append_instruction(block, instruction!(Operation::BinOp(BinOp::And, v, bit_mask) => t));
+                t
+            }};
+        }
+
         macro_rules! set {
             (reg $d: expr => $v: expr) => {{
                 let d = $d;
+                let v = $v;
                 if d != 0 {
                     let d_nth = builder.allocate_value();
-                    builder.append_instruction(block, instruction!(Operation::Integer(d as u64 - 1) => d_nth));
-                    builder.append_instruction(block, instruction!(Operation::StoreIndex(reg, d_nth, $v)));
+                    builder.append_instruction(block, instruction!(Operation::Integer(d as u128 - 1) => d_nth));
+                    builder.append_instruction(block, instruction!(Operation::StoreIndex(reg, d_nth, v)));
                 } else {}
             }};
         }
 
-        match inst {
-            Inst::ADD(d, a, b) => {
-                let a = get_value!(any a);
-                let b = get_value!(any b);
+        // makes a block that reports `$kind` for this instruction, with `$detail` going into the
+        // payload of the error
+        macro_rules! error_block {
+            ($kind: expr, $detail: expr) => {{
+                let err = builder.append_block(format!("inst_{i}_err"), None);
+                let kind = builder.allocate_value();
+                let pc = builder.allocate_value();
+                builder.append_instruction(err, instruction!(Operation::Integer($kind.code()) => kind));
+                builder.append_instruction(err, instruction!(Operation::Integer(i as u128) => pc));
+                builder.append_instruction(err, instruction!(Operation::Call(Function::ReportError, vec![kind, pc, $detail])));
+                builder.set_terminator(err, Terminator::Trap);
+                err
+            }};
+        }
+
+        // continues in a new block if `$cond` holds and traps otherwise
+        macro_rules! check {
+            ($cond: expr, else $kind: expr, $detail: expr) => {{
+                let ok = builder.append_block(format!("inst_{i}_ok"), None);
+                let err = error_block!($kind, $detail);
+                builder.set_terminator(block, Terminator::Branch($cond, ok, err));
+                block = ok;
+            }};
+        }
+
+        // ends the block, going to the next instruction if the last port call succeeded
+        macro_rules! check_port {
+            ($port: expr) => {{
+                let ok = builder.allocate_value();
+                builder.append_instruction(block, instruction!(Operation::Call(Function::LastOk, Vec::new()) => ok));
+                let err = error_block!(InterpreterError::UnsupportedPort(0), $port);
+                builder.set_terminator(block, Terminator::Branch(ok, blocks[i+1], err));
+            }};
+        }
+
+        // continues in a new block if `$a` is inside the RAM and traps otherwise
+        macro_rules! check_bounds {
+            ($a: expr) => {{
+                let a = $a;
+                let in_bounds = builder.allocate_value();
+                builder.append_instruction(block, instruction!(Operation::BinOp(BinOp::Lt, a, ram_size) => in_bounds));
+                check!(in_bounds, else InterpreterError::MemoryAccessOob(0), a);
+            }};
+        }
+
+        // the block of the instruction a branch goes to, or the end if it is past the last one
+        macro_rules! target {
+            ($addr: expr) => {
+                match $addr {
+                    Any::Immediate(imm) => blocks.get(**imm as usize).unwrap_or_else(|| blocks.last().unwrap()).clone(),
+                    Any::Register(_) => todo!(),
+                    _ => unreachable!(),
+                }
+            };
+        }
+
+        // ends the block, going to `$addr` if `$l $op $r` holds and to the next instruction otherwise
+        macro_rules! branch {
+            ($addr: expr, $op: expr, $l: expr, $r: expr) => {{
+                let (l, r) = ($l, $r);
+                let cond = builder.allocate_value();
+                builder.append_instruction(block, instruction!(Operation::BinOp($op, l, r) => cond));
+                builder.set_terminator(block, Terminator::Branch(cond, target!($addr), blocks[i+1]));
+            }};
+        }
+
+        // sets `$d` to `$l $op $r`, truncated if the operation can carry out of the word
+        macro_rules! binop {
+            ($d: expr => $op: expr, $l: expr, $r: expr $(, $trunc: ident)?) => {{
+                let (l, r) = ($l, $r);
                 let d_tmp = builder.allocate_value();
-                builder.append_instruction(block, instruction!(Operation::BinOp(BinOp::Add, a, b) => d_tmp));
-                set!(reg *d => d_tmp);
+                builder.append_instruction(block, instruction!(Operation::BinOp($op, l, r) => d_tmp));
+                $(let d_tmp = $trunc!(d_tmp);)?
+                set!(reg *$d => d_tmp);
                 builder.set_terminator(block, Terminator::Jump(blocks[i+1]));
-            },
+            }};
+        }
+
+        // sets every bit of `$d` if `$l $op $r` holds and clears them otherwise
+        macro_rules! set_if {
+            ($d: expr => $op: expr, $l: expr, $r: expr) => {{
+                let (l, r) = ($l, $r);
+                let cond = builder.allocate_value();
+                builder.append_instruction(block, instruction!(Operation::BinOp($op, l, r) => cond));
+                binop!($d => BinOp::Sub, zero, cond, trunc);
+            }};
+        }
+
+        match inst {
+            Inst::ADD(d, a, b) => binop!(d => BinOp::Add, get_value!(any a), get_value!(any b), trunc),
             Inst::NOR(d, a, b) => {
                 let a = get_value!(any a);
                 let b = get_value!(any b);
@@ -108,41 +210,133 @@ pub fn generate_ssa(ast: Ast) -> (Body, usize, usize) {
                 set!(reg *d => d_2);
                 builder.set_terminator(block, Terminator::Jump(blocks[i+1]));
             },
-            Inst::BGE(addr, a, b) => {
+            Inst::RSH(d, a) => binop!(d => BinOp::Shr, get_value!(any a), one),
+            Inst::BGE(addr, a, b) => branch!(addr, BinOp::Ge, get_value!(any a), get_value!(any b)),
+            Inst::LOD(d, a) => {
                 let a = get_value!(any a);
-                let b = get_value!(any b);
-                let cond = builder.allocate_value();
-                builder.append_instruction(block, instruction!(Operation::BinOp(BinOp::Ge, a, b) => cond));
+                check_bounds!(a);
 
-                match addr {
-                    Any::Immediate(imm) => {
-                        builder.set_terminator(block, Terminator::Branch(cond, blocks.get(**imm as usize).unwrap_or_else(|| blocks.last().unwrap()).clone(), blocks[i+1]));
-                    },
-                    Any::Register(_) => todo!(),
-                    _ => unreachable!(),
-                }
+                let d_tmp = builder.allocate_value();
+                builder.append_instruction(block, instruction!(Operation::LoadIndex(ram, a) => d_tmp));
+                set!(reg *d => d_tmp);
+                builder.set_terminator(block, Terminator::Jump(blocks[i+1]));
+            },
+            Inst::STR(a, v) => {
+                let a = get_value!(any a);
+                let v = get_value!(any v);
+                check_bounds!(a);
+
+                builder.append_instruction(block, instruction!(Operation::StoreIndex(ram, a, v)));
+                builder.set_terminator(block, Terminator::Jump(blocks[i+1]));
             },
             Inst::MOV(d, a) | Inst::IMM(d, a) => {
                 let a = get_value!(any a);
                 set!(reg *d => a);
                 builder.set_terminator(block, Terminator::Jump(blocks[i+1]));
             },
+            Inst::SUB(d, a, b) => binop!(d => BinOp::Sub, get_value!(any a), get_value!(any b), trunc),
+            Inst::JMP(addr) => builder.set_terminator(block, Terminator::Jump(target!(addr))),
+            Inst::NOP() => builder.set_terminator(block, Terminator::Jump(blocks[i+1])),
+            Inst::LSH(d, a) => binop!(d => BinOp::Shl, get_value!(any a), one, trunc),
+            Inst::INC(d, a) => binop!(d => BinOp::Add, get_value!(any a), one, trunc),
+            Inst::DEC(d, a) => binop!(d => BinOp::Sub, get_value!(any a), one, trunc),
+            Inst::NEG(d, a) => binop!(d => BinOp::Sub, zero, get_value!(any a), trunc),
+            Inst::AND(d, a, b) => binop!(d => BinOp::And, get_value!(any a), get_value!(any b)),
+            Inst::OR(d, a, b) => binop!(d => BinOp::Or, get_value!(any a), get_value!(any b)),
+            Inst::NOT(d, a) => binop!(d => BinOp::Xor, get_value!(any a), bit_mask),
+            Inst::XOR(d, a, b) => binop!(d => BinOp::Xor, get_value!(any a), get_value!(any b)),
+            Inst::XNOR(d, a, b) | Inst::NAND(d, a, b) => {
+                let op = if matches!(inst, Inst::XNOR(..)) { BinOp::Xor } else { BinOp::And };
+                let a = get_value!(any a);
+                let b = get_value!(any b);
+                let d_tmp = builder.allocate_value();
+                builder.append_instruction(block, instruction!(Operation::BinOp(op, a, b) => d_tmp));
+                binop!(d => BinOp::Xor, d_tmp, bit_mask);
+            },
+            Inst::BRL(addr, a, b) => branch!(addr, BinOp::Lt, get_value!(any a), get_value!(any b)),
+            Inst::BRG(addr, a, b) => branch!(addr, BinOp::Gt, get_value!(any a), get_value!(any b)),
+            Inst::BRE(addr, a, b) => branch!(addr, BinOp::Eq, get_value!(any a), get_value!(any b)),
+            Inst::BNE(addr, a, b) => branch!(addr, BinOp::Ne, get_value!(any a), get_value!(any b)),
+            Inst::BLE(addr, a, b) => branch!(addr, BinOp::Le, get_value!(any a), get_value!(any b)),
+            Inst::BOD(addr, a) | Inst::BEV(addr, a) => {
+                let op = if matches!(inst, Inst::BOD(..)) { BinOp::Ne } else { BinOp::Eq };
+                let a = get_value!(any a);
+                let low = builder.allocate_value();
+                builder.append_instruction(block, instruction!(Operation::BinOp(BinOp::And, a, one) => low));
+                branch!(addr, op, low, zero);
+            },
+            Inst::BRZ(addr, a) => branch!(addr, BinOp::Eq, get_value!(any a), zero),
+            Inst::BNZ(addr, a) => branch!(addr, BinOp::Ne, get_value!(any a), zero),
+            // negative numbers have the highest bit set
+            Inst::BRN(addr, a) => branch!(addr, BinOp::Ge, get_value!(any a), get_value!(imm 1 << (ast.bits - 1))),
+            Inst::BRP(addr, a) => branch!(addr, BinOp::Lt, get_value!(any a), get_value!(imm 1 << (ast.bits - 1))),
+            Inst::CPY(a, b) => {
+                let a = get_value!(any a);
+                let b = get_value!(any b);
+                check_bounds!(b);
+                let v = builder.allocate_value();
+                builder.append_instruction(block, instruction!(Operation::LoadIndex(ram, b) => v));
+                check_bounds!(a);
+
+                builder.append_instruction(block, instruction!(Operation::StoreIndex(ram, a, v)));
+                builder.set_terminator(block, Terminator::Jump(blocks[i+1]));
+            },
+            Inst::HLT() => builder.set_terminator(block, Terminator::Jump(end)),
+            Inst::MLT(d, a, b) => binop!(d => BinOp::Mul, get_value!(any a), get_value!(any b), trunc),
+            Inst::DIV(d, a, b) | Inst::MOD(d, a, b) => {
+                let op = if matches!(inst, Inst::DIV(..)) { BinOp::Div } else { BinOp::Mod };
+                let a = get_value!(any a);
+                let b = get_value!(any b);
+                let non_zero = builder.allocate_value();
+                builder.append_instruction(block, instruction!(Operation::BinOp(BinOp::Ne, b, zero) => non_zero));
+                check!(non_zero, else InterpreterError::DivisionByZero, zero);
+                binop!(d => op, a, b);
+            },
+            Inst::BSL(d, a, b) => binop!(d => BinOp::Shl, get_value!(any a), get_value!(any b), trunc),
+            Inst::BSR(d, a, b) => binop!(d => BinOp::Shr, get_value!(any a), get_value!(any b)),
+            Inst::SETE(d, a, b) => set_if!(d => BinOp::Eq, get_value!(any a), get_value!(any b)),
+            Inst::SETNE(d, a, b) => set_if!(d => BinOp::Ne, get_value!(any a), get_value!(any b)),
+            Inst::SETG(d, a, b) => set_if!(d => BinOp::Gt, get_value!(any a), get_value!(any b)),
+            Inst::SETL(d, a, b) => set_if!(d => BinOp::Lt, get_value!(any a), get_value!(any b)),
+            Inst::SETGE(d, a, b) => set_if!(d => BinOp::Ge, get_value!(any a), get_value!(any b)),
+            Inst::SETLE(d, a, b) => set_if!(d => BinOp::Le, get_value!(any a), get_value!(any b)),
+            Inst::LLOD(d, a, b) => {
+                let a = get_value!(any a);
+                let b = get_value!(any b);
+                let sum = builder.allocate_value();
+                builder.append_instruction(block, instruction!(Operation::BinOp(BinOp::Add, a, b) => sum));
+                let addr = trunc!(sum);
+                check_bounds!(addr);
+
+                let d_tmp = builder.allocate_value();
+                builder.append_instruction(block, instruction!(Operation::LoadIndex(ram, addr) => d_tmp));
+                set!(reg *d => d_tmp);
+                builder.set_terminator(block, Terminator::Jump(blocks[i+1]));
+            },
+            Inst::LSTR(a, b, v) => {
+                let a = get_value!(any a);
+                let b = get_value!(any b);
+                let v = get_value!(any v);
+                let sum = builder.allocate_value();
+                builder.append_instruction(block, instruction!(Operation::BinOp(BinOp::Add, a, b) => sum));
+                let addr = trunc!(sum);
+                check_bounds!(addr);
+
+                builder.append_instruction(block, instruction!(Operation::StoreIndex(ram, addr, v)));
+                builder.set_terminator(block, Terminator::Jump(blocks[i+1]));
+            },
             Inst::IN(d, p) => {
                 let p = get_value!(any p);
                 let d_tmp = builder.allocate_value();
                 builder.append_instruction(block, instruction!(Operation::Call(Function::PortRead, vec![p]) => d_tmp));
-                set!(reg *d => d_tmp);
-                builder.set_terminator(block, Terminator::Jump(blocks[i+1]));
+                set!(reg *d => trunc!(d_tmp));
+                check_port!(p);
             },
             Inst::OUT(p, d) => {
                 let p = get_value!(any p);
                 let d = get_value!(any d);
                 builder.append_instruction(block, instruction!(Operation::Call(Function::PortWrite, vec![p, d])));
-                builder.set_terminator(block, Terminator::Jump(blocks[i+1]));
-            },
-            _ => {
-                todo!()
-                // builder.set_terminator(block, Terminator::Jump(blocks[i+1]));
+                check_port!(p);
             },
         }
     }
@@ -0,0 +1,414 @@
+use crate::compiler::{error::*, backend::ssa::*};
+use logos::{Logos, Span};
+
+/// Tokens of the plain-text IR, as printed by `Display for Body`
+#[derive(Clone, Debug, PartialEq, Logos)]
+#[logos(skip r"\s+")]
+pub enum IrToken {
+    #[regex(r"%[0-9]+", callback = |lex| lex.slice()[1..].parse().ok())]
+    Value(usize),
+
+    #[regex(r"#[0-9]+", callback = |lex| lex.slice()[1..].parse().ok())]
+    Variable(usize),
+
+    #[regex(r"\$[0-9]+", callback = |lex| lex.slice()[1..].parse().ok())]
+    Block(usize),
+
+    #[regex(r"[0-9]+", callback = |lex| lex.slice().parse().ok())]
+    Integer(u128),
+
+    #[regex(r"[a-zA-Z_][a-zA-Z_0-9]*", callback = |lex| lex.slice().to_string())]
+    Name(String),
+
+    #[regex(r"//[^\n]*", callback = |lex| lex.slice()[2..].trim().to_string())]
+    Comment(String),
+
+    #[token("=")]
+    Assign,
+    #[token(":")]
+    Colon,
+    #[token(",")]
+    Comma,
+    #[token("(")]
+    ParenStart,
+    #[token(")")]
+    ParenEnd,
+    #[token("[")]
+    ArrayStart,
+    #[token("]")]
+    ArrayEnd,
+    #[token("..")]
+    Range,
+}
+
+struct IrParser {
+    tokens: Vec<(Result<IrToken, ()>, Span)>,
+    index: usize,
+    end: usize,
+
+    values: usize,
+    variables: usize,
+
+    /// Values read by instructions and blocks jumped to, checked once everything is parsed
+    uses: Vec<(ValueId, Span)>,
+    targets: Vec<(BlockId, Span)>,
+    defined: Vec<bool>,
+}
+
+type IrResult<T> = Result<T, Error<IrError>>;
+
+impl IrParser {
+    fn peek(&self) -> Option<&IrToken> {
+        self.tokens.get(self.index).and_then(|(t, _)| t.as_ref().ok())
+    }
+
+    fn span(&self) -> Span {
+        self.tokens.get(self.index).map_or(self.end..self.end, |(_, s)| s.clone())
+    }
+
+    fn error<T>(&self, kind: IrError) -> IrResult<T> {
+        Err(Error { kind, span: self.span() })
+    }
+
+    fn next(&mut self) -> IrResult<IrToken> {
+        match self.tokens.get(self.index) {
+            Some((Ok(tok), _)) => {
+                self.index += 1;
+                Ok(tok.clone())
+            },
+            Some((Err(()), _)) => self.error(IrError::SyntaxError),
+            None => self.error(IrError::Expecting("more input")),
+        }
+    }
+
+    fn expect(&mut self, tok: IrToken, what: &'static str) -> IrResult<()> {
+        if self.peek() == Some(&tok) {
+            self.index += 1;
+            Ok(())
+        } else {
+            self.error(IrError::Expecting(what))
+        }
+    }
+
+    fn value(&mut self) -> IrResult<ValueId> {
+        match self.peek() {
+            Some(IrToken::Value(v)) => {
+                let v = *v;
+                self.values = self.values.max(v + 1);
+                self.index += 1;
+                Ok(ValueId(v))
+            },
+            _ => self.error(IrError::Expecting("a value like `%0`")),
+        }
+    }
+
+    /// A value read by an instruction or terminator
+    fn operand(&mut self) -> IrResult<ValueId> {
+        let span = self.span();
+        let v = self.value()?;
+        self.uses.push((v, span));
+        Ok(v)
+    }
+
+    fn variable(&mut self) -> IrResult<VariableId> {
+        match self.peek() {
+            Some(IrToken::Variable(v)) => {
+                let v = *v;
+                self.variables = self.variables.max(v + 1);
+                self.index += 1;
+                Ok(VariableId(v))
+            },
+            _ => self.error(IrError::Expecting("a variable like `#0`")),
+        }
+    }
+
+    fn block(&mut self) -> IrResult<BlockId> {
+        match self.peek() {
+            Some(IrToken::Block(b)) => {
+                let b = *b;
+                self.index += 1;
+                Ok(BlockId(b))
+            },
+            _ => self.error(IrError::Expecting("a block like `$0`")),
+        }
+    }
+
+    /// A block jumped to or coming into a Phi node
+    fn target(&mut self) -> IrResult<BlockId> {
+        let span = self.span();
+        let b = self.block()?;
+        self.targets.push((b, span));
+        Ok(b)
+    }
+
+    fn integer(&mut self) -> IrResult<u128> {
+        match self.next()? {
+            IrToken::Integer(int) => Ok(int),
+            _ => {
+                self.index -= 1;
+                self.error(IrError::Expecting("an integer"))
+            },
+        }
+    }
+
+    /// `spans [1..2, ...]`, the spans of every URCL instruction
+    fn spans(&mut self) -> IrResult<Vec<Span>> {
+        self.expect(IrToken::ArrayStart, "`[`")?;
+        let mut spans = Vec::new();
+        while self.peek() != Some(&IrToken::ArrayEnd) {
+            if !spans.is_empty() {
+                self.expect(IrToken::Comma, "`,`")?;
+            }
+
+            let start = self.integer()? as usize;
+            self.expect(IrToken::Range, "`..`")?;
+            spans.push(start..self.integer()? as usize);
+        }
+
+        self.index += 1;
+        Ok(spans)
+    }
+
+    /// `name`, `name [0: 1..2, ...]` as written after the label of a block
+    fn block_comment(&self, comment: &str) -> Option<(String, Vec<(usize, Span)>)> {
+        let Some((name, spans)) = comment.split_once(" [") else {
+            return Some((comment.to_string(), Vec::new()));
+        };
+
+        let spans = spans.strip_suffix(']')?
+            .split(", ")
+            .map(|s| {
+                let (off, span) = s.split_once(": ")?;
+                let (start, end) = span.split_once("..")?;
+                Some((off.parse().ok()?, start.parse().ok()?..end.parse().ok()?))
+            })
+            .collect::<Option<_>>()?;
+
+        Some((name.to_string(), spans))
+    }
+
+    fn operation(&mut self) -> IrResult<Operation> {
+        let name = match self.next()? {
+            IrToken::Integer(int) => return Ok(Operation::Integer(int)),
+            IrToken::Name(name) => name,
+            _ => {
+                self.index -= 1;
+                return self.error(IrError::Expecting("an operation"));
+            },
+        };
+
+        match name.as_str() {
+            "call" => {
+                let f = match self.next()? {
+                    IrToken::Name(f) => match Function::from_name(&f) {
+                        Some(f) => f,
+                        None => {
+                            self.index -= 1;
+                            return self.error(IrError::UnknownFunction(f));
+                        },
+                    },
+                    _ => {
+                        self.index -= 1;
+                        return self.error(IrError::Expecting("a function name"));
+                    },
+                };
+
+                self.expect(IrToken::ParenStart, "`(`")?;
+                let mut args = Vec::new();
+                if self.peek() != Some(&IrToken::ParenEnd) {
+                    args.push(self.operand()?);
+                    while self.peek() == Some(&IrToken::Comma) {
+                        self.index += 1;
+                        args.push(self.operand()?);
+                    }
+                }
+
+                self.expect(IrToken::ParenEnd, "`)`")?;
+                Ok(Operation::Call(f, args))
+            },
+            "alloc" => Ok(Operation::Allocate(self.variable()?, self.operand()?)),
+            "load" => {
+                let var = self.variable()?;
+                self.expect(IrToken::ArrayStart, "`[`")?;
+                let off = self.operand()?;
+                self.expect(IrToken::ArrayEnd, "`]`")?;
+                Ok(Operation::LoadIndex(var, off))
+            },
+            "store" => {
+                let var = self.variable()?;
+                self.expect(IrToken::ArrayStart, "`[`")?;
+                let off = self.operand()?;
+                self.expect(IrToken::ArrayEnd, "`]`")?;
+                Ok(Operation::StoreIndex(var, off, self.operand()?))
+            },
+            "phi" => {
+                let mut branches = Vec::new();
+                loop {
+                    let b = self.target()?;
+                    self.expect(IrToken::Colon, "`:`")?;
+                    branches.push((self.operand()?, b));
+
+                    if self.peek() != Some(&IrToken::Comma) {
+                        break;
+                    }
+
+                    self.index += 1;
+                }
+
+                Ok(Operation::Phi(branches))
+            },
+            op => match BinOp::from_name(op) {
+                Some(op) => Ok(Operation::BinOp(op, self.operand()?, self.operand()?)),
+                None => {
+                    self.index -= 1;
+                    self.error(IrError::UnknownOperation(name))
+                },
+            },
+        }
+    }
+
+    fn terminator(&mut self) -> IrResult<Option<Terminator>> {
+        let Some(IrToken::Name(name)) = self.peek() else { return Ok(None) };
+
+        let term = match name.as_str() {
+            "ret"   => Terminator::Return,
+            "trap"  => Terminator::Trap,
+            "none"  => Terminator::None,
+            "jmp"   => {
+                self.index += 1;
+                return Ok(Some(Terminator::Jump(self.target()?)));
+            },
+            "br"    => {
+                self.index += 1;
+                return Ok(Some(Terminator::Branch(self.operand()?, self.target()?, self.target()?)));
+            },
+            _ => return Ok(None),
+        };
+
+        self.index += 1;
+        Ok(Some(term))
+    }
+
+    fn define(&mut self, v: ValueId) {
+        if self.defined.len() <= *v {
+            self.defined.resize(*v + 1, false);
+        }
+
+        self.defined[*v] = true;
+    }
+
+    /// Errors for every value that is read without being defined and every block that is
+    /// jumped to without existing
+    fn dangling(&self, body: &Body) -> Vec<Error<IrError>> {
+        let values = self.uses.iter()
+            .filter(|(v, _)| !self.defined.get(**v).copied().unwrap_or(false))
+            .map(|(v, span)| Error { kind: IrError::UndefinedValue(**v), span: span.clone() });
+        let blocks = self.targets.iter()
+            .filter(|(b, _)| **b >= body.blocks.len())
+            .map(|(b, span)| Error { kind: IrError::UnknownBlock(**b), span: span.clone() });
+
+        let mut errors: Vec<_> = values.chain(blocks).collect();
+        errors.sort_by_key(|e| e.span.start);
+        errors
+    }
+
+    fn body(&mut self) -> IrResult<Body> {
+        let mut body = Body { bits: 64, ..Default::default() };
+
+        if self.peek() == Some(&IrToken::Name("bits".to_string())) {
+            self.index += 1;
+            match self.next()? {
+                IrToken::Integer(bits) if (1..=128).contains(&bits) => body.bits = bits as usize,
+                _ => {
+                    self.index -= 1;
+                    return self.error(IrError::Expecting("a word size between 1 and 128"));
+                },
+            }
+        }
+
+        if self.peek() == Some(&IrToken::Name("spans".to_string())) {
+            self.index += 1;
+            body.spans = self.spans()?;
+        }
+
+        while self.index < self.tokens.len() {
+            if let Some(IrToken::Comment(_)) = self.peek() {
+                self.index += 1;
+                continue;
+            }
+
+            let id = self.block()?;
+            if *id != body.blocks.len() {
+                self.index -= 1;
+                return self.error(IrError::BlockOutOfOrder(body.blocks.len()));
+            }
+
+            self.expect(IrToken::Colon, "`:`")?;
+
+            let (mut name, mut spans) = (String::new(), Vec::new());
+            if let Some(IrToken::Comment(comment)) = self.peek() {
+                (name, spans) = match self.block_comment(comment) {
+                    Some(c) => c,
+                    None => return self.error(IrError::BadBlockComment),
+                };
+                self.index += 1;
+            }
+
+            let mut instructions = Vec::new();
+            let terminator = loop {
+                match self.peek() {
+                    Some(IrToken::Comment(_)) => {
+                        self.index += 1;
+                        continue;
+                    },
+                    Some(IrToken::Value(_)) => {
+                        let dst = self.value()?;
+                        self.define(dst);
+                        self.expect(IrToken::Assign, "`=`")?;
+                        instructions.push(Instruction { destination: Some(dst), operation: self.operation()? });
+                        continue;
+                    },
+                    _ => {},
+                }
+
+                match self.terminator()? {
+                    Some(term) => break term,
+                    None => instructions.push(Instruction { destination: None, operation: self.operation()? }),
+                }
+            };
+
+            body.blocks.push(Block { name, id, instructions, terminator, spans });
+        }
+
+        Ok(body)
+    }
+}
+
+/// Reads IR in the syntax `Display for Body` prints, giving the body along with the amount of
+/// values and variables it uses like [`generate_ssa`](super::codegen::generate_ssa) does.
+///
+/// The word size defaults to 64 bits if the `bits` line is left out, and the `spans` line may be
+/// left out too. Reading a value that is never defined or jumping to a block that doesn't exist
+/// is an error.
+pub fn parse_ir(src: &str) -> Result<(Body, usize, usize), Vec<Error<IrError>>> {
+    let mut parser = IrParser {
+        tokens: IrToken::lexer(src).spanned().collect(),
+        index: 0,
+        end: src.len(),
+
+        values: 0,
+        variables: 0,
+
+        uses: Vec::new(),
+        targets: Vec::new(),
+        defined: Vec::new(),
+    };
+
+    let body = parser.body().map_err(|err| vec![err])?;
+    let errors = parser.dangling(&body);
+    if errors.is_empty() {
+        Ok((body, parser.values, parser.variables))
+    } else {
+        Err(errors)
+    }
+}
@@ -1,5 +1,9 @@
 pub mod ssa;
 pub mod codegen;
 pub mod builder;
+pub mod ir;
+pub mod analysis;
+pub mod opt;
+pub mod regalloc;
 
 pub mod arch;
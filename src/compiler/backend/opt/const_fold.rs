@@ -0,0 +1,143 @@
+use crate::{
+    instruction,
+    compiler::backend::{ssa::*, builder::*},
+};
+use std::collections::HashMap;
+
+/// Folds operations on known constants and propagates the results.
+///
+/// Every constant ends up defined once at the start of the entry block, operations and Phi nodes
+/// with known results are replaced by constants, and branches on a known condition become jumps.
+/// Operations that would trap (e.g. division by zero) are left alone so that they still trap at
+/// runtime.
+pub fn const_fold(builder: &mut Builder) {
+    let body = builder.body_mut();
+    let bits = body.bits;
+
+    // every value is defined once, so constants are known wherever they are
+    let mut consts: HashMap<ValueId, u128> = body.blocks.iter()
+        .flat_map(|blk| blk.instructions.iter())
+        .filter_map(|i| match (i.destination, &i.operation) {
+            (Some(dst), Operation::Integer(int)) => Some((dst, *int)),
+            _ => None,
+        })
+        .collect();
+
+    loop {
+        let mut changed = false;
+        let mut replace = HashMap::new();
+        let mut dead_edges = Vec::new();
+        for blk in body.blocks.iter_mut() {
+            for instr in blk.instructions.iter_mut() {
+                let folded = match &instr.operation {
+                    Operation::BinOp(op, l, r) => match (consts.get(l), consts.get(r)) {
+                        (Some(l), Some(r)) => operate(op, *l, *r, bits),
+                        _ => None,
+                    },
+                    Operation::Phi(branches) => {
+                        let dst = instr.destination.unwrap();
+                        let mut values = branches.iter().map(|(v, _)| *v).filter(|v| *v != dst);
+                        let first = values.next();
+                        let rest: Vec<_> = values.collect();
+                        match first.map(|f| (f, consts.get(&f))) {
+                            // Phi nodes of the same value are that value, constant or not
+                            Some((first, _)) if rest.iter().all(|v| *v == first) => {
+                                replace.insert(dst, first);
+                                changed = true;
+                                None
+                            },
+                            Some((_, Some(int))) if rest.iter().all(|v| consts.get(v) == Some(int)) => Some(*int),
+                            _ => None,
+                        }
+                    },
+                    _ => None,
+                };
+
+                if let Some(int) = folded {
+                    instr.operation = Operation::Integer(int);
+                    consts.insert(instr.destination.unwrap(), int);
+                    changed = true;
+                }
+            }
+
+            if let Terminator::Branch(cond, if_, else_) = blk.terminator {
+                if let Some(c) = consts.get(&cond) {
+                    let (taken, other) = if *c != 0 { (if_, else_) } else { (else_, if_) };
+                    if taken != other {
+                        dead_edges.push((blk.id, other));
+                    }
+
+                    blk.terminator = Terminator::Jump(taken);
+                    changed = true;
+                }
+            }
+        }
+
+        for (from, to) in dead_edges {
+            for instr in body.blocks[*to].instructions.iter_mut() {
+                if let Operation::Phi(branches) = &mut instr.operation {
+                    branches.retain(|(_, b)| *b != from);
+                }
+            }
+        }
+
+        for blk in body.blocks.iter_mut() {
+            blk.retain_instructions(|i| !i.destination.is_some_and(|d| replace.contains_key(&d)));
+        }
+
+        body.replace_uses(|mut v| {
+            while let Some(r) = replace.get(&v) {
+                v = *r;
+            }
+
+            v
+        });
+
+        if !changed {
+            break;
+        }
+    }
+
+    let replace = hoist_constants(body);
+    body.replace_uses(|v| *replace.get(&v).unwrap_or(&v));
+}
+
+/// Moves every constant to the start of the entry block, keeping one definition per value.
+/// Returns what the duplicates should be replaced with.
+fn hoist_constants(body: &mut Body) -> HashMap<ValueId, ValueId> {
+    let mut canonical = HashMap::<u128, ValueId>::new();
+    let mut hoisted = Vec::new();
+    let mut replace = HashMap::new();
+
+    for blk in body.blocks.iter_mut() {
+        blk.retain_instructions(|i| match (i.destination, &i.operation) {
+            (Some(dst), Operation::Integer(int)) => {
+                match canonical.get(int) {
+                    Some(c) => { replace.insert(dst, *c); },
+                    None => {
+                        canonical.insert(*int, dst);
+                        hoisted.push(instruction!(Operation::Integer(*int) => dst));
+                    },
+                }
+
+                false
+            },
+            _ => true,
+        });
+    }
+
+    if let Some(entry) = body.blocks.first_mut() {
+        entry.prepend_instructions(hoisted);
+    }
+
+    replace
+}
+
+/// Evaluates `op` like an engine would, with `u64` words for 64 bits and less
+fn operate(op: &BinOp, l: u128, r: u128, bits: usize) -> Option<u128> {
+    if bits <= 64 {
+        op.operate::<u64>(l as u64, r as u64, bits).map(u128::from)
+    } else {
+        op.operate::<u128>(l, r, bits)
+    }
+}
@@ -0,0 +1,76 @@
+use crate::compiler::backend::{ssa::*, builder::*, analysis::*};
+use logos::Span;
+use std::collections::{HashMap, HashSet};
+
+/// Removes blocks that can't be reached from the entry block and operations whose results are
+/// never used.
+///
+/// Returns the source spans of the URCL instructions that were in unreachable blocks, in source
+/// order.
+pub fn dce(builder: &mut Builder) -> Vec<Span> {
+    let body = builder.body();
+    let cfg = Cfg::new(body);
+
+    let mut reachable = vec![false; body.blocks.len()];
+    for b in cfg.reverse_postorder() {
+        reachable[*b] = true;
+    }
+
+    let mut unreachable: Vec<Span> = body.blocks.iter()
+        .filter(|blk| !reachable[*blk.id])
+        .flat_map(|blk| blk.spans.iter().map(|(_, s)| s.clone()))
+        .collect();
+    unreachable.sort_unstable_by_key(|s| (s.start, s.end));
+    unreachable.dedup();
+
+    builder.retain_blocks(&reachable);
+
+    let body = builder.body_mut();
+    let consts: HashMap<ValueId, u128> = body.blocks.iter()
+        .flat_map(|b| b.instructions.iter())
+        .filter_map(|i| match (i.destination, &i.operation) {
+            (Some(dst), Operation::Integer(int)) => Some((dst, *int)),
+            _ => None,
+        })
+        .collect();
+
+    // an unused operation can only go if it can't trap or change anything
+    let removable = |op: &Operation| match op {
+        Operation::BinOp(BinOp::Div | BinOp::Mod | BinOp::SDiv | BinOp::SMod, _, r) => consts.get(r).is_some_and(|r| *r != 0),
+        Operation::Call(Function::LastOk, _) => true,
+        op => op.is_pure(),
+    };
+
+    let mut defs = HashMap::new();
+    let mut live = HashSet::new();
+    let mut work = Vec::new();
+    for blk in body.blocks.iter() {
+        for instr in blk.instructions.iter() {
+            if let Some(dst) = instr.destination {
+                defs.insert(dst, &instr.operation);
+            }
+
+            if !removable(&instr.operation) {
+                work.extend(instr.operation.operands());
+            }
+        }
+
+        if let Terminator::Branch(cond, _, _) = blk.terminator {
+            work.push(cond);
+        }
+    }
+
+    while let Some(v) = work.pop() {
+        if live.insert(v) {
+            if let Some(op) = defs.get(&v) {
+                work.extend(op.operands());
+            }
+        }
+    }
+
+    for blk in body.blocks.iter_mut() {
+        blk.retain_instructions(|i| !removable(&i.operation) || i.destination.is_some_and(|d| live.contains(&d)));
+    }
+
+    unreachable
+}
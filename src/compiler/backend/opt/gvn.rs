@@ -0,0 +1,128 @@
+use crate::compiler::backend::{ssa::*, builder::*};
+use std::collections::HashMap;
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Integer(u128),
+    BinOp(BinOp, ValueId, ValueId),
+    /// A load from a variable as it was at a point in time
+    LoadIndex(VariableId, ValueId, usize),
+    Phi(BlockId, Vec<(ValueId, BlockId)>),
+}
+
+/// Removes computations that have already been done in a dominating block.
+///
+/// The dominator tree is walked from the entry block, keeping a table of every computation that
+/// dominates the current block. Loads are numbered by the version of the variable they read,
+/// which every `StoreIndex` and `Allocate` bumps, so a store followed by a load of the same slot
+/// forwards the stored value. A block only starts out with the memory of its immediate dominator
+/// if that is its only predecessor, everywhere else memory is assumed to have changed.
+pub fn gvn(builder: &mut Builder) {
+    let cfg = builder.cfg();
+    let doms = builder.dominators();
+    let body = builder.body_mut();
+
+    let mut replace = HashMap::new();
+    let mut table = HashMap::new();
+    let mut undo = Vec::new();
+    let mut version = 0;
+    let mut exit_memory = vec![HashMap::new(); body.blocks.len()];
+
+    enum Visit {
+        Enter(BlockId),
+        Leave(usize),
+    }
+
+    let mut stack = vec![Visit::Enter(BlockId(0))];
+    while let Some(visit) = stack.pop() {
+        let b = match visit {
+            Visit::Enter(b) => b,
+            Visit::Leave(len) => {
+                for key in undo.drain(len..) {
+                    table.remove(&key);
+                }
+
+                continue;
+            },
+        };
+
+        let mut memory = match (doms.idom[*b], &cfg.predecessors[*b][..]) {
+            (Some(d), [p]) if d == *p => exit_memory[*d].clone(),
+            _ => HashMap::new(),
+        };
+        let start = undo.len();
+
+        let resolve = |replace: &HashMap<ValueId, ValueId>, mut v: ValueId| {
+            while let Some(r) = replace.get(&v) {
+                v = *r;
+            }
+
+            v
+        };
+
+        for instr in body.blocks[*b].instructions.iter() {
+            let key = match &instr.operation {
+                Operation::Integer(int) => Key::Integer(*int),
+                Operation::BinOp(op, l, r) => {
+                    let (mut l, mut r) = (resolve(&replace, *l), resolve(&replace, *r));
+                    if op.is_commutative() && *r < *l {
+                        std::mem::swap(&mut l, &mut r);
+                    }
+
+                    Key::BinOp(*op, l, r)
+                },
+                Operation::LoadIndex(var, off) => Key::LoadIndex(*var, resolve(&replace, *off), *memory.entry(*var).or_insert_with(|| {
+                    version += 1;
+                    version
+                })),
+                Operation::Phi(branches) => {
+                    let mut branches: Vec<_> = branches.iter().map(|(v, b)| (resolve(&replace, *v), *b)).collect();
+                    branches.sort_unstable_by_key(|(_, b)| **b);
+                    Key::Phi(b, branches)
+                },
+                Operation::StoreIndex(var, off, val) => {
+                    version += 1;
+                    memory.insert(*var, version);
+
+                    let key = Key::LoadIndex(*var, resolve(&replace, *off), version);
+                    table.insert(key.clone(), resolve(&replace, *val));
+                    undo.push(key);
+                    continue;
+                },
+                Operation::Allocate(var, _) => {
+                    version += 1;
+                    memory.insert(*var, version);
+                    continue;
+                },
+                Operation::Call(..) => continue,
+            };
+
+            let Some(dst) = instr.destination else { continue };
+            match table.get(&key) {
+                Some(v) => { replace.insert(dst, *v); },
+                None => {
+                    table.insert(key.clone(), dst);
+                    undo.push(key);
+                },
+            }
+        }
+
+        exit_memory[*b] = memory;
+        stack.push(Visit::Leave(start));
+        for child in doms.children[*b].iter().rev() {
+            stack.push(Visit::Enter(*child));
+        }
+    }
+
+    for blk in body.blocks.iter_mut() {
+        blk.retain_instructions(|i| !i.destination.is_some_and(|d| replace.contains_key(&d)));
+    }
+
+    body.replace_uses(|mut v| {
+        while let Some(r) = replace.get(&v) {
+            v = *r;
+        }
+
+        v
+    });
+}
@@ -0,0 +1,135 @@
+use crate::compiler::backend::{ssa::*, builder::*, opt::*};
+use std::str::FromStr;
+
+/// A named transformation over the SSA IR
+#[derive(Clone, Copy)]
+pub struct Pass {
+    pub name: &'static str,
+    pub run: fn(&mut Builder),
+}
+
+/// Every pass that the pass manager knows about
+pub const PASSES: &[Pass] = &[
+    Pass { name: "mem2reg",         run: mem2reg },
+    Pass { name: "const-fold",      run: const_fold },
+    Pass { name: "simplify-cfg",    run: simplify_cfg },
+    Pass { name: "gvn",             run: gvn },
+    Pass { name: "dce",             run: |b| { dce(b); } },
+];
+
+/// Looks up a pass in [`PASSES`] by its name
+pub fn pass(name: &str) -> Option<Pass> {
+    PASSES.iter().find(|p| p.name == name).copied()
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OptLevel {
+    /// Runs the IR as it comes out of code generation
+    O0,
+    /// Only promotes registers and cleans up the control flow
+    O1,
+    #[default]
+    O2,
+    /// Goes over everything a second time
+    O3,
+}
+
+impl OptLevel {
+    /// Names of the passes making up the level, in order
+    pub const fn passes(self) -> &'static [&'static str] {
+        match self {
+            Self::O0 => &[],
+            Self::O1 => &["mem2reg", "simplify-cfg", "dce"],
+            Self::O2 => &["mem2reg", "const-fold", "simplify-cfg", "gvn", "dce"],
+            Self::O3 => &["mem2reg", "const-fold", "simplify-cfg", "gvn", "const-fold", "simplify-cfg", "gvn", "dce"],
+        }
+    }
+}
+
+impl FromStr for OptLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim_start_matches(['O', 'o']) {
+            "0" => Ok(Self::O0),
+            "1" => Ok(Self::O1),
+            "2" => Ok(Self::O2),
+            "3" => Ok(Self::O3),
+            _ => Err(format!("unknown optimisation level `{s}`, expected one of: 0, 1, 2, 3")),
+        }
+    }
+}
+
+/// Runs a list of passes over a body, dumping the IR and timing passes along the way.
+///
+/// In debug builds the IR is verified after every pass, so that a broken pass gets caught right
+/// where it went wrong instead of somewhere down the line.
+pub struct PassManager {
+    passes: Vec<Pass>,
+    dump_after: Vec<String>,
+    verify: bool,
+
+    /// IR after every pass that was asked for with [`PassManager::dump_after`], along with the
+    /// name of the pass
+    pub dumps: Vec<(&'static str, String)>,
+    /// How long every pass took in seconds, in the order they ran
+    pub timings: Vec<(&'static str, f64)>,
+}
+
+impl PassManager {
+    pub fn new(level: OptLevel) -> Self {
+        Self {
+            passes: level.passes().iter().map(|n| pass(n).unwrap()).collect(),
+            dump_after: Vec::new(),
+            verify: cfg!(debug_assertions),
+
+            dumps: Vec::new(),
+            timings: Vec::new(),
+        }
+    }
+
+    pub fn add_pass(&mut self, pass: Pass) {
+        self.passes.push(pass);
+    }
+
+    /// Dumps the IR every time the pass `name` ran, `all` dumps after every pass and `codegen`
+    /// right before the first
+    pub fn dump_after<A: Into<String>>(&mut self, name: A) {
+        self.dump_after.push(name.into());
+    }
+
+    pub fn set_verify(&mut self, verify: bool) {
+        self.verify = verify;
+    }
+
+    pub fn run(&mut self, builder: &mut Builder) {
+        self.check("codegen", builder.body());
+        self.dump("codegen", builder.body());
+
+        for pass in self.passes.clone() {
+            let start = crate::now();
+            (pass.run)(builder);
+            self.timings.push((pass.name, crate::now() - start));
+
+            self.check(pass.name, builder.body());
+            self.dump(pass.name, builder.body());
+        }
+    }
+
+    fn check(&self, name: &str, body: &Body) {
+        if !self.verify {
+            return;
+        }
+
+        if let Err(errors) = verify(body) {
+            let errors: Vec<_> = errors.iter().map(|e| e.to_string()).collect();
+            panic!("`{name}` broke the IR:\n{}\n{body}", errors.join("\n"));
+        }
+    }
+
+    fn dump(&mut self, name: &'static str, body: &Body) {
+        if self.dump_after.iter().any(|d| d == name || d == "all") {
+            self.dumps.push((name, body.to_string()));
+        }
+    }
+}
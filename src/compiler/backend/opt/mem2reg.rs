@@ -0,0 +1,192 @@
+use crate::{
+    instruction,
+    compiler::backend::{ssa::*, builder::*},
+};
+use std::collections::HashMap;
+
+/// Promotes every variable which is only ever indexed by constants (the URCL register file) into
+/// plain SSA values, with Phi nodes where different definitions of a slot meet.
+///
+/// Phi nodes are first placed at every join point and the trivial ones are then removed again,
+/// which leaves the loop headers and merges that really need them.
+pub fn mem2reg(builder: &mut Builder) {
+    // what every slot starts out as
+    let zero = builder.allocate_value();
+
+    let cfg = builder.cfg();
+    let body = builder.body();
+
+    let mut consts = HashMap::new();
+    for instr in body.blocks.iter().flat_map(|b| b.instructions.iter()) {
+        if let (Some(dst), Operation::Integer(int)) = (instr.destination, &instr.operation) {
+            consts.insert(dst, *int);
+        }
+    }
+
+    // size of every variable that can be promoted, `None` if it can't be
+    let mut sizes = HashMap::<VariableId, Option<u128>>::new();
+    for instr in body.blocks.iter().flat_map(|b| b.instructions.iter()) {
+        match &instr.operation {
+            Operation::Allocate(var, size) => {
+                let size = if sizes.contains_key(var) { None } else { consts.get(size).copied() };
+                sizes.insert(*var, size);
+            },
+            Operation::LoadIndex(var, off) | Operation::StoreIndex(var, off, _) if !consts.contains_key(off) => {
+                sizes.insert(*var, None);
+            },
+            _ => {},
+        }
+    }
+
+    for instr in body.blocks.iter().flat_map(|b| b.instructions.iter()) {
+        if let Operation::LoadIndex(var, off) | Operation::StoreIndex(var, off, _) = &instr.operation {
+            if !matches!(sizes.get(var), Some(Some(size)) if consts[off] < *size) {
+                sizes.insert(*var, None);
+            }
+        }
+    }
+
+    let mut base = HashMap::new();
+    let mut slots = 0;
+    let mut promoted: Vec<_> = sizes.iter().filter_map(|(v, s)| s.map(|s| (*v, s as usize))).collect();
+    promoted.sort_unstable_by_key(|(v, _)| **v);
+    for (var, size) in promoted {
+        base.insert(var, slots);
+        slots += size;
+    }
+
+    if slots == 0 {
+        return;
+    }
+
+    let slot_of = |var: &VariableId, off: &ValueId| base.get(var).map(|b| b + consts[off] as usize);
+
+    // definitions at the end of every block, and loads that need the definition at block entry
+    let mut end_defs = vec![vec![None; slots]; body.blocks.len()];
+    let mut pending = Vec::new();
+    let mut replace = HashMap::new();
+    for (b, blk) in body.blocks.iter().enumerate() {
+        let defs = &mut end_defs[b];
+        for instr in blk.instructions.iter() {
+            match &instr.operation {
+                Operation::LoadIndex(var, off) => if let Some(slot) = slot_of(var, off) {
+                    let dst = instr.destination.unwrap();
+                    match defs[slot] {
+                        Some(def) => { replace.insert(dst, def); },
+                        None => pending.push((dst, b, slot)),
+                    }
+                },
+                Operation::StoreIndex(var, off, val) => if let Some(slot) = slot_of(var, off) {
+                    defs[slot] = Some(*val);
+                },
+                Operation::Allocate(var, _) => if let Some(base) = base.get(var) {
+                    // allocations are zeroed
+                    for def in defs[*base..*base + sizes[var].unwrap() as usize].iter_mut() {
+                        *def = Some(zero);
+                    }
+                },
+                _ => {},
+            }
+        }
+    }
+
+    let order = cfg.reverse_postorder();
+    let phi_sites: Vec<_> = order.iter()
+        .filter(|b| ***b != 0 && cfg.predecessors[***b].len() >= 2)
+        .copied()
+        .collect();
+
+    let remove: Vec<Vec<bool>> = body.blocks.iter()
+        .map(|blk| blk.instructions.iter().map(|instr| match &instr.operation {
+            Operation::LoadIndex(var, off) | Operation::StoreIndex(var, off, _) => base.contains_key(var) && consts.contains_key(off),
+            Operation::Allocate(var, _) => base.contains_key(var),
+            _ => false,
+        }).collect())
+        .collect();
+
+    let mut phis = HashMap::new();
+    for b in phi_sites.iter() {
+        let ids: Vec<_> = (0..slots).map(|_| builder.allocate_value()).collect();
+        phis.insert(*b, ids);
+    }
+
+    // definitions at the entry of every block, unreachable blocks only ever see zeroes
+    let mut entry_defs = vec![vec![zero; slots]; end_defs.len()];
+    for b in order.iter() {
+        if let Some(ids) = phis.get(b) {
+            entry_defs[**b] = ids.clone();
+        } else if let [pred] = cfg.predecessors[**b][..] {
+            entry_defs[**b] = (0..slots)
+                .map(|s| end_defs[*pred][s].unwrap_or(entry_defs[*pred][s]))
+                .collect();
+        }
+    }
+
+    let end_def = |b: BlockId, s: usize| end_defs[*b][s].unwrap_or(entry_defs[*b][s]);
+    let mut phi_operands = HashMap::new();
+    for (b, ids) in phis.iter() {
+        for (s, id) in ids.iter().enumerate() {
+            phi_operands.insert(*id, cfg.predecessors[**b].iter().map(|p| (end_def(*p, s), *p)).collect::<Vec<_>>());
+        }
+    }
+
+    for (dst, b, slot) in pending {
+        replace.insert(dst, entry_defs[b][slot]);
+    }
+
+    fn resolve(replace: &HashMap<ValueId, ValueId>, mut v: ValueId) -> ValueId {
+        while let Some(r) = replace.get(&v) {
+            v = *r;
+        }
+
+        v
+    }
+
+    // removes phi nodes that only ever see one value besides themselves
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (id, ops) in phi_operands.iter() {
+            if replace.contains_key(id) {
+                continue;
+            }
+
+            let mut same = None;
+            let mut trivial = true;
+            for (op, _) in ops.iter() {
+                let op = resolve(&replace, *op);
+                if op == *id || Some(op) == same {
+                    continue;
+                }
+
+                if same.is_some() {
+                    trivial = false;
+                    break;
+                }
+
+                same = Some(op);
+            }
+
+            if trivial {
+                replace.insert(*id, same.unwrap_or(zero));
+                changed = true;
+            }
+        }
+    }
+
+    let body = builder.body_mut();
+    for (b, blk) in body.blocks.iter_mut().enumerate() {
+        let mut keep = remove[b].iter().map(|r| !r);
+        blk.retain_instructions(|_| keep.next().unwrap());
+
+        if let Some(ids) = phis.get(&BlockId(b)) {
+            let nodes = ids.iter()
+                .filter(|id| !replace.contains_key(id))
+                .map(|id| instruction!(Operation::Phi(phi_operands.remove(id).unwrap()) => *id));
+            blk.prepend_instructions(nodes.collect());
+        }
+    }
+
+    body.blocks[0].prepend_instructions(vec![instruction!(Operation::Integer(0) => zero)]);
+    body.replace_uses(|v| resolve(&replace, v));
+}
@@ -0,0 +1,13 @@
+pub mod mem2reg;
+pub mod const_fold;
+pub mod simplify_cfg;
+pub mod dce;
+pub mod gvn;
+pub mod manager;
+
+pub use mem2reg::*;
+pub use const_fold::*;
+pub use simplify_cfg::*;
+pub use dce::*;
+pub use gvn::*;
+pub use manager::*;
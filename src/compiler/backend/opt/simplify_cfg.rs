@@ -0,0 +1,168 @@
+use crate::compiler::backend::{ssa::*, builder::*, analysis::*};
+use std::collections::HashMap;
+
+/// Cleans up the control-flow graph that code generation leaves behind.
+///
+/// Branches with both targets the same become jumps, jumps to empty blocks go straight to where
+/// that block jumps to, and a block is merged into its predecessor if it is the only one. Empty
+/// blocks that nothing goes to anymore are removed. The source lines of removed blocks move along
+/// with the code, so that the instruction count stays the same.
+pub fn simplify_cfg(builder: &mut Builder) {
+    loop {
+        let body = builder.body_mut();
+        let mut changed = false;
+
+        for blk in body.blocks.iter_mut() {
+            if let Terminator::Branch(_, if_, else_) = blk.terminator {
+                if if_ == else_ {
+                    blk.terminator = Terminator::Jump(if_);
+                    changed = true;
+                }
+            }
+        }
+
+        changed |= thread_jumps(body);
+        let (keep, replace) = merge_blocks(body);
+        changed |= keep.iter().any(|k| !k);
+
+        body.replace_uses(|mut v| {
+            while let Some(r) = replace.get(&v) {
+                v = *r;
+            }
+
+            v
+        });
+        builder.retain_blocks(&keep);
+
+        if !changed {
+            break;
+        }
+    }
+}
+
+/// Redirects edges into empty blocks that only jump somewhere else. Edges that would count the
+/// source lines of the skipped block only go through if they are jumps, which can take the lines
+/// with them.
+fn thread_jumps(body: &mut Body) -> bool {
+    let cfg = Cfg::new(body);
+    let mut changed = false;
+
+    for x in (1..body.blocks.len()).map(BlockId) {
+        let Terminator::Jump(y) = body.blocks[*x].terminator else { continue };
+        if y == x || !body.blocks[*x].instructions.is_empty() {
+            continue;
+        }
+
+        for p in cfg.predecessors[*x].iter().copied() {
+            let pred = &body.blocks[*p];
+            let jumps = matches!(pred.terminator, Terminator::Jump(_));
+            if p == x || !pred.terminator.successors().contains(&x) || (!body.blocks[*x].spans.is_empty() && !jumps) {
+                continue;
+            }
+
+            // Phi nodes of `y` now see `p` instead of `x`, which doesn't work out if `p` already
+            // goes to `y` with other values
+            let already = pred.terminator.successors().contains(&y);
+            let phis_agree = body.blocks[*y].instructions.iter().all(|i| match &i.operation {
+                Operation::Phi(branches) => {
+                    let from_x = branches.iter().find(|(_, b)| *b == x).map(|(v, _)| *v);
+                    !already || branches.iter().find(|(_, b)| *b == p).map(|(v, _)| *v) == from_x
+                },
+                _ => true,
+            });
+            if !phis_agree {
+                continue;
+            }
+
+            if !already {
+                for instr in body.blocks[*y].instructions.iter_mut() {
+                    if let Operation::Phi(branches) = &mut instr.operation {
+                        if let Some((v, _)) = branches.iter().find(|(_, b)| *b == x).copied() {
+                            branches.push((v, p));
+                        }
+                    }
+                }
+            }
+
+            let spans = body.blocks[*x].spans.clone();
+            let pred = &mut body.blocks[*p];
+            for succ in pred.terminator.successors_mut() {
+                if *succ == x {
+                    *succ = y;
+                }
+            }
+
+            let at = pred.instructions.len();
+            pred.spans.extend(spans.into_iter().map(|(_, s)| (at, s)));
+            changed = true;
+        }
+    }
+
+    changed
+}
+
+/// Merges every block into its predecessor if that one only jumps to it. Returns which blocks are
+/// left and the values that the Phi nodes of merged blocks became.
+fn merge_blocks(body: &mut Body) -> (Vec<bool>, HashMap<ValueId, ValueId>) {
+    let mut cfg = Cfg::new(body);
+    let mut keep = vec![true; body.blocks.len()];
+    let mut replace = HashMap::new();
+
+    for a in (0..body.blocks.len()).map(BlockId) {
+        if !keep[*a] {
+            continue;
+        }
+
+        while let Terminator::Jump(b) = body.blocks[*a].terminator {
+            if b == a || *b == 0 || cfg.predecessors[*b][..] != [a] {
+                break;
+            }
+
+            let mut merged = std::mem::replace(&mut body.blocks[*b], Block {
+                name: String::new(),
+                id: b,
+                instructions: Vec::new(),
+                terminator: Terminator::None,
+                spans: Vec::new(),
+            });
+            merged.retain_instructions(|i| match &i.operation {
+                Operation::Phi(branches) => {
+                    replace.insert(i.destination.unwrap(), branches[0].0);
+                    false
+                },
+                _ => true,
+            });
+
+            for succ in merged.terminator.successors() {
+                for pred in cfg.predecessors[*succ].iter_mut() {
+                    if *pred == b {
+                        *pred = a;
+                    }
+                }
+
+                for instr in body.blocks[*succ].instructions.iter_mut() {
+                    if let Operation::Phi(branches) = &mut instr.operation {
+                        for (_, blk) in branches.iter_mut() {
+                            if *blk == b {
+                                *blk = a;
+                            }
+                        }
+                    }
+                }
+            }
+
+            body.blocks[*a].append_block(merged);
+            keep[*b] = false;
+        }
+    }
+
+    // empty blocks left behind by threading
+    let cfg = Cfg::new(body);
+    for (b, blk) in body.blocks.iter().enumerate() {
+        if b != 0 && keep[b] && cfg.predecessors[b].iter().all(|p| !keep[**p]) && blk.instructions.is_empty() && matches!(blk.terminator, Terminator::Jump(_)) {
+            keep[b] = false;
+        }
+    }
+
+    (keep, replace)
+}
@@ -0,0 +1,281 @@
+use crate::compiler::backend::{ssa::*, analysis::{Cfg, Liveness}};
+use std::{cmp::Reverse, collections::{BinaryHeap, HashMap}};
+
+/// Registers of the machine a backend emits code for
+#[derive(Debug, Clone)]
+pub struct Target {
+    /// Amount of registers, numbered from 0
+    pub registers: usize,
+    /// Registers that are never handed out, like the ones the backend uses as scratch
+    pub reserved: Vec<usize>,
+}
+
+impl Target {
+    /// Registers values can be put in, in the order they are tried
+    pub fn allocatable(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.registers).filter(|r| !self.reserved.contains(r))
+    }
+}
+
+/// Where a value is kept for the whole time it is live
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Location {
+    Register(usize),
+    /// Slot of the stack area, which is [`Allocation::stack`] words big
+    Stack(usize),
+}
+
+#[derive(Debug, Clone)]
+pub struct Allocation {
+    /// Location of every value, `None` for the ones that are never defined nor read
+    pub locations: Vec<Option<Location>>,
+    /// Amount of slots the values that didn't get a register take up
+    pub stack: usize,
+}
+
+impl Allocation {
+    pub fn location(&self, v: ValueId) -> Location {
+        self.locations[*v].unwrap_or_else(|| panic!("{v} has no location"))
+    }
+}
+
+/// Gives every value of `body` a register of `target`, or a stack slot when they run out.
+///
+/// The blocks are laid out in reverse postorder with the unreachable ones at the end, and every
+/// value gets the points it is live at as a list of ranges, which [`allocate_ranges`] then hands
+/// out the registers for. Values prefer the registers of the values Phi nodes copy them from or
+/// into.
+pub fn allocate(body: &Body, values: usize, target: &Target) -> Allocation {
+    let cfg = Cfg::new(body);
+    let liveness = Liveness::new(body, &cfg);
+
+    let mut order = cfg.reverse_postorder();
+    let mut placed = vec![false; body.blocks.len()];
+    for b in order.iter() {
+        placed[**b] = true;
+    }
+
+    // unreachable blocks still get code emitted for them, which needs their values somewhere
+    order.extend(body.blocks.iter().map(|b| b.id).filter(|b| !placed[**b]));
+    let ranges = live_ranges(body, values, &order, &liveness);
+
+    // values flowing into Phi nodes, which save a copy when they share a register with them
+    let mut related = vec![Vec::new(); values];
+    for instr in body.blocks.iter().flat_map(|b| b.instructions.iter()) {
+        if let (Some(dst), Operation::Phi(branches)) = (instr.destination, &instr.operation) {
+            for (v, _) in branches.iter() {
+                related[*dst].push(**v);
+                related[**v].push(*dst);
+            }
+        }
+    }
+
+    allocate_ranges(&ranges, &related, target)
+}
+
+/// Gives every value a register of `target`, or a stack slot when they run out, where value `v`
+/// is live at the points in `ranges[v]` and would rather share a register with `related[v]`.
+///
+/// Ranges are sorted and include both ends, values without any are left without a location.
+/// This works on anything that can be numbered that way, be it SSA values or the virtual
+/// registers of machine code.
+///
+/// Values take the first register that is free everywhere they are live in the order they start,
+/// trying the registers of their related values first. When none is free, the register whose
+/// values live on for the longest gets spilled instead, as long as they outlive the new one.
+///
+/// Stack slots are handed out the same way once the registers are done, without looking for
+/// holes in the lifetimes.
+pub fn allocate_ranges(ranges: &[Vec<(usize, usize)>], related: &[Vec<usize>], target: &Target) -> Allocation {
+    let values = ranges.len();
+    let start = |v: usize| ranges[v].first().map_or(0, |r| r.0);
+    let end = |v: usize| ranges[v].last().map_or(0, |r| r.1);
+    let mut queue: Vec<usize> = (0..values).filter(|v| !ranges[*v].is_empty()).collect();
+    queue.sort_by_key(|v| (start(*v), *v));
+
+    let registers: Vec<usize> = target.allocatable().collect();
+    let mut locations = vec![None; values];
+    // values in every register that haven't ended yet
+    let mut active = vec![Vec::new(); target.registers];
+    let mut spilled = Vec::new();
+
+    for v in queue {
+        for r in registers.iter() {
+            active[*r].retain(|a| end(*a) >= start(v));
+        }
+
+        let conflicts = |active: &[Vec<usize>], r: usize| -> Vec<usize> {
+            active[r].iter().copied().filter(|a| overlaps(&ranges[*a], &ranges[v])).collect()
+        };
+
+        let hints = related[v].iter().filter_map(|r| match locations[*r] {
+            Some(Location::Register(r)) => Some(r),
+            _ => None,
+        });
+
+        let free = hints.chain(registers.iter().copied()).find(|r| conflicts(&active, *r).is_empty());
+        let taken = free.or_else(|| registers.iter()
+            .map(|r| (*r, conflicts(&active, *r).into_iter().map(end).min().unwrap_or(0)))
+            .filter(|(_, until)| *until > end(v))
+            .max_by_key(|(_, until)| *until)
+            .map(|(r, _)| r));
+
+        match taken {
+            Some(r) => {
+                for a in conflicts(&active, r) {
+                    locations[a] = None;
+                    active[r].retain(|x| *x != a);
+                    spilled.push(a);
+                }
+
+                locations[v] = Some(Location::Register(r));
+                active[r].push(v);
+            },
+            None => spilled.push(v),
+        }
+    }
+
+    spilled.sort_by_key(|v| (start(*v), *v));
+    let mut stack = 0;
+    let mut busy = BinaryHeap::new();
+    let mut free = Vec::new();
+    for v in spilled {
+        while let Some(Reverse((until, slot))) = busy.peek().copied() {
+            if until >= start(v) {
+                break;
+            }
+
+            busy.pop();
+            free.push(slot);
+        }
+
+        let slot = free.pop().unwrap_or_else(|| {
+            stack += 1;
+            stack - 1
+        });
+
+        busy.push(Reverse((end(v), slot)));
+        locations[v] = Some(Location::Stack(slot));
+    }
+
+    Allocation { locations, stack }
+}
+
+/// The points every value is live at as sorted ranges, including both ends.
+///
+/// Instruction `i` of a block reads at its point `2i` and writes at `2i + 1`, so that a result
+/// can take the register of an operand that dies there. The terminator reads after the last
+/// instruction and the block ends on the point after it, where the copies into the Phi nodes of
+/// its successors happen. Phi nodes are written at the first point of their block.
+fn live_ranges(body: &Body, values: usize, order: &[BlockId], liveness: &Liveness) -> Vec<Vec<(usize, usize)>> {
+    let mut ranges = vec![Vec::new(); values];
+    let mut start = 0;
+
+    for b in order.iter() {
+        let blk = &body.blocks[**b];
+        let term = start + 2 * blk.instructions.len();
+        let end = term + 1;
+
+        // last point of the block every value that is live in it is read at
+        let mut live: HashMap<ValueId, usize> = liveness.live_out[**b].iter().map(|v| (*v, end)).collect();
+        if let Terminator::Branch(cond, ..) = blk.terminator {
+            live.entry(cond).or_insert(term);
+        }
+
+        for (i, instr) in blk.instructions.iter().enumerate().rev() {
+            let at = start + 2 * i;
+            let phi = matches!(instr.operation, Operation::Phi(_));
+
+            if let Some(dst) = instr.destination {
+                let def = if phi { start } else { at + 1 };
+                ranges[*dst].push((def, live.remove(&dst).unwrap_or(def)));
+            }
+
+            if !phi {
+                for v in instr.operation.operands() {
+                    live.entry(v).or_insert(at);
+                }
+            }
+        }
+
+        for (v, until) in live {
+            ranges[*v].push((start, until));
+        }
+
+        start = end + 1;
+    }
+
+    for r in ranges.iter_mut() {
+        r.sort_unstable();
+        let mut merged: Vec<(usize, usize)> = Vec::with_capacity(r.len());
+        for (from, to) in r.drain(..) {
+            match merged.last_mut() {
+                Some((_, end)) if from <= *end + 1 => *end = to.max(*end),
+                _ => merged.push((from, to)),
+            }
+        }
+
+        *r = merged;
+    }
+
+    ranges
+}
+
+/// Whether two sorted lists of ranges including both ends share a point
+fn overlaps(a: &[(usize, usize)], b: &[(usize, usize)]) -> bool {
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i].1 < b[j].0 {
+            i += 1;
+        } else if b[j].1 < a[i].0 {
+            j += 1;
+        } else {
+            return true;
+        }
+    }
+
+    false
+}
+
+/// The copies going from `from` to `to` does for the Phi nodes of `to`, as `(dst, src)` pairs
+/// which all happen at once
+pub fn phi_copies(body: &Body, from: BlockId, to: BlockId) -> Vec<(ValueId, ValueId)> {
+    body.blocks[*to].instructions.iter()
+        .map_while(|i| match &i.operation {
+            Operation::Phi(branches) => Some((i.destination.unwrap(), branches.iter().find(|(_, b)| *b == from).unwrap().0)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Orders `copies` which all happen at once into ones that can be done one after another, where
+/// each `(dst, src)` pair has `dst` take `src`.
+///
+/// Copies that go around in a cycle get one of their destinations moved into `temp` first, which
+/// none of them may read or write.
+pub fn sequentialise<L, S>(mut copies: Vec<(L, S)>, temp: L) -> Vec<(L, S)>
+where
+    L: Copy + PartialEq,
+    S: Copy + PartialEq + From<L>,
+{
+    // these would never get out of their own way
+    copies.retain(|(dst, src)| S::from(*dst) != *src);
+
+    let mut moves = Vec::with_capacity(copies.len());
+    while !copies.is_empty() {
+        let free = copies.iter().position(|(dst, _)| !copies.iter().any(|(_, src)| *src == S::from(*dst)));
+        if let Some(i) = free {
+            moves.push(copies.remove(i));
+        } else {
+            let dst = copies[0].0;
+            moves.push((temp, S::from(dst)));
+            for (_, src) in copies.iter_mut() {
+                if *src == S::from(dst) {
+                    *src = S::from(temp);
+                }
+            }
+        }
+    }
+
+    moves
+}
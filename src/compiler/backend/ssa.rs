@@ -1,4 +1,5 @@
-use std::fmt::{self, Display, Formatter};
+use crate::compiler::{common::Word, backend::analysis::*};
+use std::{fmt::{self, Display, Formatter}, collections::HashMap};
 use logos::Span;
 
 macro_rules! type_wrapper {
@@ -29,7 +30,8 @@ macro_rules! type_wrapper {
 }
 
 macro_rules! binop {
-    ($($name: ident = $op: tt),* $(,)?) => {
+    ($($name: ident = |$l: ident, $r: ident, $bits: ident| $op: expr),* $(,)?) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
         pub enum BinOp {
             $($name),*
         }
@@ -43,15 +45,47 @@ macro_rules! binop {
         }
 
         impl BinOp {
-            pub fn operate(&self, lhs: u64, rhs: u64) -> u64 {
+            /// Looks up an operation by the name it is displayed with
+            pub fn from_name(name: &str) -> Option<Self> {
+                $(if stringify!($name).to_lowercase() == name {
+                    return Some(BinOp::$name);
+                })*
+
+                None
+            }
+
+            /// Applies the operation on two `bits`-wide values, giving `None` if it traps (e.g.
+            /// divided by zero). Results are not truncated down to `bits`.
+            #[allow(unused_variables)]
+            pub fn operate<W: Word>(&self, lhs: W, rhs: W, bits: usize) -> Option<W> {
                 match self {
-                    $(BinOp::$name => (lhs $op rhs) as u64),*
+                    $(BinOp::$name => {
+                        let ($l, $r, $bits) = (lhs, rhs, bits);
+                        Some($op)
+                    }),*
                 }
             }
         }
     };
 }
 
+fn sign_bit<W: Word>(bits: usize) -> W {
+    W::ONE.shift_left(W::from_u128(bits as u128 - 1))
+}
+
+fn is_negative<W: Word>(v: W, bits: usize) -> bool {
+    v & sign_bit(bits) != W::ZERO
+}
+
+/// Two's complement negation in `bits` bits
+fn negate<W: Word>(v: W, bits: usize) -> W {
+    (!v).wrapping_add(W::ONE) & W::mask(bits)
+}
+
+fn abs<W: Word>(v: W, bits: usize) -> W {
+    if is_negative(v, bits) { negate(v, bits) } else { v }
+}
+
 type_wrapper!(pub ValueId = pub(crate) usize    : "%{}");
 type_wrapper!(pub VariableId = pub(crate) usize : "#{}");
 type_wrapper!(pub BlockId = pub(crate) usize    : "${}");
@@ -59,6 +93,10 @@ type_wrapper!(pub BlockId = pub(crate) usize    : "${}");
 #[derive(Default)]
 pub struct Body {
     pub blocks: Vec<Block>,
+    /// Word size of the program, every value fits in this many bits
+    pub bits: usize,
+    /// Source span of every URCL instruction, indexed by program counter
+    pub spans: Vec<Span>,
 }
 
 pub struct Block {
@@ -66,7 +104,9 @@ pub struct Block {
     pub id: BlockId,
     pub instructions: Vec<Instruction>,
     pub terminator: Terminator,
-    pub span: Option<Span>,
+    /// Source spans of the URCL instructions making up the block, each with the index of the
+    /// first instruction it covers
+    pub spans: Vec<(usize, Span)>,
 }
 
 pub struct Instruction {
@@ -75,7 +115,7 @@ pub struct Instruction {
 }
 
 pub enum Operation {
-    Integer(u64),
+    Integer(u128),
     BinOp(BinOp, ValueId, ValueId),
     Call(Function, Vec<ValueId>),
     Allocate(VariableId, ValueId),
@@ -87,41 +127,252 @@ pub enum Operation {
 #[derive(Debug)]
 pub enum Function {
     LastOk,             // LastOk() -> bool
-    PortRead,           // PortRead(port: int) -> int
+    PortRead,           // PortRead(port: int) -> int, as the device gave it without truncating
     PortWrite,          // PortWrite(port: int, data: int)
-    ReportError,        // ReportError(kind: int, program_counter: int) -> !
+    ReportError,        // ReportError(kind: int, program_counter: int, detail: int) -> !
 }
 
 binop!(
-    Add = +,
-    Sub = -,
-    Mul = *,
-    Div = /,
-    Mod = %,
-    And = &,
-    Or  = |,
-    Xor = ^,
-    Shl = <<,
-    Shr = >>,
-    Eq  = ==,
-    Ne  = !=,
-    Lt  = <,
-    Le  = <=,
-    Gt  = >,
-    Ge  = >=,
+    Add  = |l, r, _b| l.wrapping_add(r),
+    Sub  = |l, r, _b| l.wrapping_sub(r),
+    Mul  = |l, r, _b| l.wrapping_mul(r),
+    Div  = |l, r, _b| l.checked_div(r)?,
+    Mod  = |l, r, _b| l.checked_rem(r)?,
+    SDiv = |l, r, b| {
+        let q = abs(l, b).checked_div(abs(r, b))?;
+        if is_negative(l, b) != is_negative(r, b) { negate(q, b) } else { q }
+    },
+    SMod = |l, r, b| {
+        let m = abs(l, b).checked_rem(abs(r, b))?;
+        if is_negative(l, b) { negate(m, b) } else { m }
+    },
+    And  = |l, r, _b| l & r,
+    Or   = |l, r, _b| l | r,
+    Xor  = |l, r, _b| l ^ r,
+    Shl  = |l, r, _b| l.shift_left(r),
+    Shr  = |l, r, _b| l.shift_right(r),
+    Sar  = |l, r, b| {
+        let mask = W::mask(b);
+        if !is_negative(l, b) {
+            l.shift_right(r)
+        } else if r >= W::from_u128(b as u128) {
+            mask
+        } else {
+            l.shift_right(r) | (mask & !mask.shift_right(r))
+        }
+    },
+    Eq   = |l, r, _b| W::from(l == r),
+    Ne   = |l, r, _b| W::from(l != r),
+    Lt   = |l, r, _b| W::from(l < r),
+    Le   = |l, r, _b| W::from(l <= r),
+    Gt   = |l, r, _b| W::from(l > r),
+    Ge   = |l, r, _b| W::from(l >= r),
+    // signed comparisons flip the sign bit so that they can compare as unsigned
+    SLt  = |l, r, b| W::from((l ^ sign_bit(b)) < (r ^ sign_bit(b))),
+    SLe  = |l, r, b| W::from((l ^ sign_bit(b)) <= (r ^ sign_bit(b))),
+    SGt  = |l, r, b| W::from((l ^ sign_bit(b)) > (r ^ sign_bit(b))),
+    SGe  = |l, r, b| W::from((l ^ sign_bit(b)) >= (r ^ sign_bit(b))),
 );
 
 pub enum Terminator {
     Return,
     Jump(BlockId),
     Branch(ValueId, BlockId, BlockId),
+    /// Execution never gets past this, ends blocks which called `ReportError`
+    Trap,
     None,
 }
 
+impl Body {
+    /// Whether `var` is still allocated anywhere, which it isn't once it got promoted into values
+    pub fn allocates(&self, var: VariableId) -> bool {
+        self.blocks.iter()
+            .flat_map(|b| b.instructions.iter())
+            .any(|i| matches!(i.operation, Operation::Allocate(v, _) if v == var))
+    }
+
+    /// Rewrites every use of a value through `f`
+    pub fn replace_uses(&mut self, mut f: impl FnMut(ValueId) -> ValueId) {
+        for blk in self.blocks.iter_mut() {
+            for instr in blk.instructions.iter_mut() {
+                for v in instr.operation.operands_mut() {
+                    *v = f(*v);
+                }
+            }
+
+            if let Some(v) = blk.terminator.operand_mut() {
+                *v = f(*v);
+            }
+        }
+    }
+}
+
+impl Block {
+    /// Span of the URCL instruction that instruction `index` belongs to
+    pub fn span_at(&self, index: usize) -> Option<&Span> {
+        self.spans.iter()
+            .take_while(|(off, _)| *off <= index)
+            .last()
+            .or(self.spans.first())
+            .map(|(_, span)| span)
+    }
+
+    /// Removes instructions like `Vec::retain` while keeping the span table in sync
+    pub fn retain_instructions(&mut self, mut f: impl FnMut(&Instruction) -> bool) {
+        let mut kept_before = Vec::with_capacity(self.instructions.len() + 1);
+        let mut kept = 0;
+        let keep: Vec<bool> = self.instructions.iter()
+            .map(|i| {
+                kept_before.push(kept);
+                let k = f(i);
+                kept += k as usize;
+                k
+            })
+            .collect();
+        kept_before.push(kept);
+
+        for (off, _) in self.spans.iter_mut() {
+            *off = kept_before[*off];
+        }
+
+        let mut keep = keep.into_iter();
+        self.instructions.retain(|_| keep.next().unwrap());
+    }
+
+    /// Inserts instructions in front of everything else, e.g. Phi nodes
+    pub fn prepend_instructions(&mut self, instrs: Vec<Instruction>) {
+        for (off, _) in self.spans.iter_mut() {
+            *off += instrs.len();
+        }
+
+        self.instructions.splice(0..0, instrs);
+    }
+
+    /// Appends the instructions and spans of `other`, which execution continues into
+    pub fn append_block(&mut self, other: Self) {
+        let base = self.instructions.len();
+        self.spans.extend(other.spans.into_iter().map(|(off, span)| (off + base, span)));
+        self.instructions.extend(other.instructions);
+        self.terminator = other.terminator;
+    }
+}
+
+impl Function {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "LastOk"        => Some(Self::LastOk),
+            "PortRead"      => Some(Self::PortRead),
+            "PortWrite"     => Some(Self::PortWrite),
+            "ReportError"   => Some(Self::ReportError),
+            _ => None,
+        }
+    }
+
+    /// Amount of arguments the function takes
+    pub const fn arity(&self) -> usize {
+        match self {
+            Self::LastOk        => 0,
+            Self::PortRead      => 1,
+            Self::PortWrite     => 2,
+            Self::ReportError   => 3,
+        }
+    }
+
+    pub const fn returns_value(&self) -> bool {
+        matches!(self, Self::LastOk | Self::PortRead)
+    }
+}
+
+impl BinOp {
+    /// Whether swapping the operands gives the same result
+    pub const fn is_commutative(&self) -> bool {
+        matches!(self, Self::Add | Self::Mul | Self::And | Self::Or | Self::Xor | Self::Eq | Self::Ne)
+    }
+}
+
+impl Operation {
+    /// Every value read by the operation
+    pub fn operands(&self) -> Vec<ValueId> {
+        match self {
+            Self::Integer(_)                => Vec::new(),
+            Self::BinOp(_, l, r)            => vec![*l, *r],
+            Self::Call(_, args)             => args.clone(),
+            Self::Allocate(_, size)         => vec![*size],
+            Self::LoadIndex(_, off)         => vec![*off],
+            Self::StoreIndex(_, off, val)   => vec![*off, *val],
+            Self::Phi(branches)             => branches.iter().map(|(v, _)| *v).collect(),
+        }
+    }
+
+    /// Whether the operation does nothing besides producing its result, divisions still count
+    /// even though they can trap
+    pub const fn is_pure(&self) -> bool {
+        matches!(self, Self::Integer(_) | Self::BinOp(..) | Self::LoadIndex(..) | Self::Phi(_))
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut ValueId> {
+        match self {
+            Self::Integer(_)                => Vec::new(),
+            Self::BinOp(_, l, r)            => vec![l, r],
+            Self::Call(_, args)             => args.iter_mut().collect(),
+            Self::Allocate(_, size)         => vec![size],
+            Self::LoadIndex(_, off)         => vec![off],
+            Self::StoreIndex(_, off, val)   => vec![off, val],
+            Self::Phi(branches)             => branches.iter_mut().map(|(v, _)| v).collect(),
+        }
+    }
+}
+
+impl Terminator {
+    /// Blocks that can be executed next
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Self::Jump(blk)                 => vec![*blk],
+            Self::Branch(_, if_, else_)     => vec![*if_, *else_],
+            Self::Return | Self::Trap | Self::None => Vec::new(),
+        }
+    }
+
+    pub fn successors_mut(&mut self) -> Vec<&mut BlockId> {
+        match self {
+            Self::Jump(blk)                 => vec![blk],
+            Self::Branch(_, if_, else_)     => vec![if_, else_],
+            Self::Return | Self::Trap | Self::None => Vec::new(),
+        }
+    }
+
+    pub fn operand_mut(&mut self) -> Option<&mut ValueId> {
+        match self {
+            Self::Branch(cond, _, _) => Some(cond),
+            _ => None,
+        }
+    }
+}
+
+/// Wraps `text` in an ANSI colour when formatting with `{:#}`, the plain form is what
+/// [`parse_ir`](super::ir::parse_ir) reads back
+fn paint(fmt: &Formatter, color: &str, text: impl Display) -> String {
+    if fmt.alternate() {
+        format!("\x1b[{color}m{text}\x1b[0m")
+    } else {
+        text.to_string()
+    }
+}
+
 impl Display for Body {
     fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        writeln!(fmt, "{} {}", paint(fmt, "33", "bits"), self.bits)?;
+        if !self.spans.is_empty() {
+            let spans: Vec<_> = self.spans.iter().map(|s| format!("{}..{}", s.start, s.end)).collect();
+            writeln!(fmt, "{} [{}]", paint(fmt, "33", "spans"), spans.join(", "))?;
+        }
+
         for blk in self.blocks.iter() {
-            write!(fmt, "{blk}")?;
+            if fmt.alternate() {
+                write!(fmt, "{blk:#}")?;
+            } else {
+                write!(fmt, "{blk}")?;
+            }
         }
 
         Ok(())
@@ -130,25 +381,38 @@ impl Display for Body {
 
 impl Display for Block {
     fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
-        writeln!(fmt, "\x1b[35m${}\x1b[0m: \x1b[90m// {} {:?}\x1b[0m", *self.id, self.name, self.span)?;
+        let mut comment = format!("// {}", self.name);
+        if !self.spans.is_empty() {
+            let spans: Vec<_> = self.spans.iter().map(|(off, s)| format!("{off}: {}..{}", s.start, s.end)).collect();
+            comment.push_str(&format!(" [{}]", spans.join(", ")));
+        }
+
+        writeln!(fmt, "{}: {}", paint(fmt, "35", self.id), paint(fmt, "90", comment))?;
 
         for instr in &self.instructions {
-            writeln!(fmt, "    {}", instr)?;
+            if fmt.alternate() {
+                writeln!(fmt, "    {instr:#}")?;
+            } else {
+                writeln!(fmt, "    {instr}")?;
+            }
         }
 
-        writeln!(fmt, "    {}\x1b[0m", self.terminator)?;
-
-        Ok(())
+        if fmt.alternate() {
+            writeln!(fmt, "    {:#}", self.terminator)
+        } else {
+            writeln!(fmt, "    {}", self.terminator)
+        }
     }
 }
 
 impl Display for Terminator {
     fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
         match self {
-            Self::Return                    => write!(fmt, "\x1b[32mret"),
-            Self::Jump(block)               => write!(fmt, "\x1b[32mjmp \x1b[35m{block}"),
-            Self::Branch(cond, if_, else_)  => write!(fmt, "\x1b[32mbr \x1b[36m{cond} \x1b[35m{if_} {else_}"),
-            Self::None                      => write!(fmt, "\x1b[1;31mno terminator!"),
+            Self::Return                    => write!(fmt, "{}", paint(fmt, "32", "ret")),
+            Self::Jump(block)               => write!(fmt, "{} {}", paint(fmt, "32", "jmp"), paint(fmt, "35", block)),
+            Self::Branch(cond, if_, else_)  => write!(fmt, "{} {} {} {}", paint(fmt, "32", "br"), paint(fmt, "36", cond), paint(fmt, "35", if_), paint(fmt, "35", else_)),
+            Self::Trap                      => write!(fmt, "{}", paint(fmt, "31", "trap")),
+            Self::None                      => write!(fmt, "{}", paint(fmt, "1;31", "none")),
         }
     }
 }
@@ -156,31 +420,33 @@ impl Display for Terminator {
 impl Display for Instruction {
     fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
         if let Some(dst) = self.destination {
-            write!(fmt, "\x1b[36m{dst}\x1b[0m = ")?;
+            write!(fmt, "{} = ", paint(fmt, "36", dst))?;
         }
 
-        write!(fmt, "{}\x1b[0m", self.operation)
+        if fmt.alternate() {
+            write!(fmt, "{:#}", self.operation)
+        } else {
+            write!(fmt, "{}", self.operation)
+        }
     }
 }
 
 impl Display for Operation {
     fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        let list = |items: Vec<String>| items.join(", ");
+
         match self {
             Self::Integer(int)      => write!(fmt, "{int}"),
-            Self::BinOp(op, l, r)   => write!(fmt, "\x1b[33m{op} \x1b[36m{l} {r}"),
-            Self::Call(f, args)     => write!(fmt, "\x1b[33mcall \x1b[34m{f:?}\x1b[0m({})", args.iter()
-                .map(|e| format!("\x1b[36m{}\x1b[0m", e))
-                .collect::<Vec<String>>()
-                .join(", ")
-            ),
-            Self::Allocate(v, size) => write!(fmt, "\x1b[33malloc \x1b[34m{v} \x1b[36m{size}"),
-            Self::LoadIndex(var, off)       => write!(fmt, "\x1b[33mload \x1b[34m{var}\x1b[0m[\x1b[36m{off}\x1b[0m]"),
-            Self::StoreIndex(v, off, val)   => write!(fmt, "\x1b[33mstore \x1b[34m{v}\x1b[0m[\x1b[36m{off}\x1b[0m] \x1b[36m{val}"),
-            Self::Phi(branches)     => write!(fmt, "\x1b[33mphi \x1b[34m{}", branches.iter()
-                .map(|(val, block)| format!("{}: {}", block, val))
-                .collect::<Vec<String>>()
-                .join(", ")
-            ),
+            Self::BinOp(op, l, r)   => write!(fmt, "{} {} {}", paint(fmt, "33", op), paint(fmt, "36", l), paint(fmt, "36", r)),
+            Self::Call(f, args)     => write!(fmt, "{} {}({})", paint(fmt, "33", "call"), paint(fmt, "34", format!("{f:?}")), list(
+                args.iter().map(|a| paint(fmt, "36", a)).collect()
+            )),
+            Self::Allocate(v, size) => write!(fmt, "{} {} {}", paint(fmt, "33", "alloc"), paint(fmt, "34", v), paint(fmt, "36", size)),
+            Self::LoadIndex(v, off) => write!(fmt, "{} {}[{}]", paint(fmt, "33", "load"), paint(fmt, "34", v), paint(fmt, "36", off)),
+            Self::StoreIndex(v, off, val)   => write!(fmt, "{} {}[{}] {}", paint(fmt, "33", "store"), paint(fmt, "34", v), paint(fmt, "36", off), paint(fmt, "36", val)),
+            Self::Phi(branches)     => write!(fmt, "{} {}", paint(fmt, "33", "phi"), list(
+                branches.iter().map(|(val, block)| format!("{}: {}", paint(fmt, "35", block), paint(fmt, "36", val))).collect()
+            )),
         }
     }
 }
@@ -194,3 +460,177 @@ macro_rules! instruction {
         $crate::compiler::backend::ssa::Instruction { operation: $oper, destination: None }
     }};
 }
+
+/// Something wrong with the IR, found by [`verify`]
+#[derive(Debug)]
+pub struct VerifyError {
+    pub block: BlockId,
+    pub block_name: String,
+    /// Index of the offending instruction in the block, `None` for the terminator
+    pub instruction: Option<usize>,
+    pub message: String,
+}
+
+impl Display for VerifyError {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match self.instruction {
+            Some(i) => write!(fmt, "{} ({}) instruction {i}: {}", self.block, self.block_name, self.message),
+            None => write!(fmt, "{} ({}) terminator: {}", self.block, self.block_name, self.message),
+        }
+    }
+}
+
+/// Checks that `body` is well-formed, which the engines and passes rely on.
+///
+/// Every block is terminated, every value is defined once and dominates its uses, the Phi nodes
+/// sit at the start of their block with one input per predecessor, every variable is allocated
+/// before it is indexed, and every call has as many arguments as its function takes.
+pub fn verify(body: &Body) -> Result<(), Vec<VerifyError>> {
+    let mut errors = Vec::new();
+
+    macro_rules! error {
+        ($blk: expr, $instr: expr, $($fmt: tt)*) => {
+            errors.push(VerifyError {
+                block: $blk.id,
+                block_name: $blk.name.clone(),
+                instruction: $instr,
+                message: format!($($fmt)*),
+            })
+        };
+    }
+
+    // the CFG can't be built with edges going nowhere
+    for blk in body.blocks.iter() {
+        for succ in blk.terminator.successors() {
+            if *succ >= body.blocks.len() {
+                error!(blk, None, "branches to {succ}, which does not exist");
+            }
+        }
+
+        for (i, instr) in blk.instructions.iter().enumerate() {
+            if let Operation::Phi(branches) = &instr.operation {
+                for (_, p) in branches.iter().filter(|(_, p)| **p >= body.blocks.len()) {
+                    error!(blk, Some(i), "phi input comes from {p}, which does not exist");
+                }
+            }
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let cfg = Cfg::new(body);
+    let doms = Dominators::new(&cfg);
+
+    // where every value is defined and every variable allocated
+    let mut defs = HashMap::new();
+    let mut allocs = HashMap::<VariableId, Vec<(BlockId, usize)>>::new();
+    for blk in body.blocks.iter() {
+        for (i, instr) in blk.instructions.iter().enumerate() {
+            if let Some(dst) = instr.destination {
+                if defs.insert(dst, (blk.id, i)).is_some() {
+                    error!(blk, Some(i), "{dst} is defined more than once");
+                }
+            }
+
+            if let Operation::Allocate(var, _) = instr.operation {
+                allocs.entry(var).or_default().push((blk.id, i));
+            }
+        }
+    }
+
+    // whether something at `def` is there by the time `at` runs
+    let before = |def: (BlockId, usize), at: (BlockId, usize)| if def.0 == at.0 {
+        def.1 < at.1
+    } else {
+        doms.dominates(def.0, at.0)
+    };
+
+    for (b, blk) in body.blocks.iter().enumerate() {
+        if *blk.id != b {
+            error!(blk, None, "block is listed at index {b}");
+        }
+
+        let reachable = doms.is_reachable(blk.id);
+        let mut phis_done = false;
+        for (i, instr) in blk.instructions.iter().enumerate() {
+            let here = (blk.id, i);
+
+            match &instr.operation {
+                Operation::Phi(branches) => {
+                    if phis_done {
+                        error!(blk, Some(i), "phi node after other instructions");
+                    }
+
+                    let mut from: Vec<_> = branches.iter().map(|(_, b)| *b).collect();
+                    from.sort_unstable_by_key(|b| **b);
+                    let mut preds = cfg.predecessors[b].clone();
+                    preds.sort_unstable_by_key(|b| **b);
+                    if from != preds {
+                        let list = |l: &[BlockId]| l.iter().map(|b| b.to_string()).collect::<Vec<_>>().join(", ");
+                        error!(blk, Some(i), "phi inputs come from [{}] but the predecessors are [{}]", list(&from), list(&preds));
+                    }
+
+                    for (v, p) in branches.iter() {
+                        match defs.get(v) {
+                            None => error!(blk, Some(i), "{v} is never defined"),
+                            Some(def) if reachable && doms.is_reachable(*p) && !doms.dominates(def.0, *p) => {
+                                error!(blk, Some(i), "{v} does not dominate the end of {p}");
+                            },
+                            _ => {},
+                        }
+                    }
+                },
+                op => {
+                    phis_done = true;
+                    for v in op.operands() {
+                        match defs.get(&v) {
+                            None => error!(blk, Some(i), "{v} is never defined"),
+                            Some(def) if reachable && !before(*def, here) => error!(blk, Some(i), "{v} does not dominate its use"),
+                            _ => {},
+                        }
+                    }
+                },
+            }
+
+            let has_result = match &instr.operation {
+                Operation::Call(f, _) => f.returns_value(),
+                Operation::Allocate(..) | Operation::StoreIndex(..) => false,
+                _ => true,
+            };
+            if instr.destination.is_some() != has_result {
+                error!(blk, Some(i), "{}", if has_result { "result is not assigned" } else { "operation has no result to assign" });
+            }
+
+            match &instr.operation {
+                Operation::LoadIndex(var, _) | Operation::StoreIndex(var, _, _) => {
+                    let allocated = allocs.get(var).is_some_and(|a| a.iter().any(|a| !reachable || before(*a, here)));
+                    if !allocated {
+                        error!(blk, Some(i), "{var} is indexed before being allocated");
+                    }
+                },
+                Operation::Call(f, args) if args.len() != f.arity() => {
+                    error!(blk, Some(i), "{f:?} takes {} arguments but got {}", f.arity(), args.len());
+                },
+                _ => {},
+            }
+        }
+
+        match &blk.terminator {
+            Terminator::None => error!(blk, None, "block has no terminator"),
+            Terminator::Branch(cond, _, _) => match defs.get(cond) {
+                None => error!(blk, None, "{cond} is never defined"),
+                Some(def) if reachable && !before(*def, (blk.id, usize::MAX)) => error!(blk, None, "{cond} does not dominate its use"),
+                _ => {},
+            },
+            _ => {},
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
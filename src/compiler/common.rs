@@ -1,3 +1,5 @@
+use std::{fmt::{Debug, Display}, hash::Hash, ops::{BitAnd, BitOr, BitXor, Not}};
+
 #[derive(Debug, Clone)]
 #[allow(clippy::upper_case_acronyms)]
 pub enum Instruction {
@@ -10,11 +12,123 @@ pub enum Instruction {
     IMM(Register, Any),
 
     MOV(Register, Any),
+    SUB(Register, Any, Any),
+    JMP(Any),
+    NOP(),
+    LSH(Register, Any),
+    INC(Register, Any),
+    DEC(Register, Any),
+    NEG(Register, Any),
+    AND(Register, Any, Any),
+    OR(Register, Any, Any),
+    NOT(Register, Any),
+    XOR(Register, Any, Any),
+    XNOR(Register, Any, Any),
+    NAND(Register, Any, Any),
+    BRL(Any, Any, Any),
+    BRG(Any, Any, Any),
+    BRE(Any, Any, Any),
+    BNE(Any, Any, Any),
+    BLE(Any, Any, Any),
+    BOD(Any, Any),
+    BEV(Any, Any),
+    BRZ(Any, Any),
+    BNZ(Any, Any),
+    BRN(Any, Any),
+    BRP(Any, Any),
+    CPY(Any, Any),
+    HLT(),
+
+    MLT(Register, Any, Any),
+    DIV(Register, Any, Any),
+    MOD(Register, Any, Any),
+    BSL(Register, Any, Any),
+    BSR(Register, Any, Any),
+    SETE(Register, Any, Any),
+    SETNE(Register, Any, Any),
+    SETG(Register, Any, Any),
+    SETL(Register, Any, Any),
+    SETGE(Register, Any, Any),
+    SETLE(Register, Any, Any),
+    LLOD(Register, Any, Any),
+    LSTR(Any, Any, Any),
 
     IN(Register, Any),
     OUT(Any, Any),
 }
 
+impl Instruction {
+    /// Where the instruction may go instead of the next one, if it is a branch
+    pub const fn target(&self) -> Option<&Any> {
+        match self {
+            Self::BGE(t, _, _) | Self::JMP(t)
+                | Self::BRL(t, _, _) | Self::BRG(t, _, _) | Self::BRE(t, _, _) | Self::BNE(t, _, _) | Self::BLE(t, _, _)
+                | Self::BOD(t, _) | Self::BEV(t, _) | Self::BRZ(t, _) | Self::BNZ(t, _) | Self::BRN(t, _) | Self::BRP(t, _) => Some(t),
+            _ => None,
+        }
+    }
+
+    /// The mnemonic of the instruction, in lowercase
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::ADD(..) => "add",
+            Self::RSH(..) => "rsh",
+            Self::LOD(..) => "lod",
+            Self::STR(..) => "str",
+            Self::BGE(..) => "bge",
+            Self::NOR(..) => "nor",
+            Self::IMM(..) => "imm",
+            Self::MOV(..) => "mov",
+            Self::SUB(..) => "sub",
+            Self::JMP(..) => "jmp",
+            Self::NOP(..) => "nop",
+            Self::LSH(..) => "lsh",
+            Self::INC(..) => "inc",
+            Self::DEC(..) => "dec",
+            Self::NEG(..) => "neg",
+            Self::AND(..) => "and",
+            Self::OR(..) => "or",
+            Self::NOT(..) => "not",
+            Self::XOR(..) => "xor",
+            Self::XNOR(..) => "xnor",
+            Self::NAND(..) => "nand",
+            Self::BRL(..) => "brl",
+            Self::BRG(..) => "brg",
+            Self::BRE(..) => "bre",
+            Self::BNE(..) => "bne",
+            Self::BLE(..) => "ble",
+            Self::BOD(..) => "bod",
+            Self::BEV(..) => "bev",
+            Self::BRZ(..) => "brz",
+            Self::BNZ(..) => "bnz",
+            Self::BRN(..) => "brn",
+            Self::BRP(..) => "brp",
+            Self::CPY(..) => "cpy",
+            Self::HLT(..) => "hlt",
+            Self::MLT(..) => "mlt",
+            Self::DIV(..) => "div",
+            Self::MOD(..) => "mod",
+            Self::BSL(..) => "bsl",
+            Self::BSR(..) => "bsr",
+            Self::SETE(..) => "sete",
+            Self::SETNE(..) => "setne",
+            Self::SETG(..) => "setg",
+            Self::SETL(..) => "setl",
+            Self::SETGE(..) => "setge",
+            Self::SETLE(..) => "setle",
+            Self::LLOD(..) => "llod",
+            Self::LSTR(..) => "lstr",
+            Self::IN(..) => "in",
+            Self::OUT(..) => "out",
+        }
+    }
+
+    /// Whether the instruction may go on with the next one
+    pub const fn falls_through(&self) -> bool {
+        !matches!(self, Self::JMP(_) | Self::HLT())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Any {
     Register(Register),
@@ -24,7 +138,7 @@ pub enum Any {
 }
 
 pub type Register = usize;
-pub type Immediate = Box<u64>;
+pub type Immediate = Box<u128>;
 
 #[derive(Debug)]
 #[repr(u64)]
@@ -36,3 +150,75 @@ pub enum Port {
     Addr, Bus, Page, SSpecial = 39,
     Rng, Note, Instr, NLeg, Wait, NAddr, Data, MSpecial,
 }
+
+/// An unsigned machine word which values are stored in.
+///
+/// Engines are generic over this so that programs with 64 bits or less can keep on using `u64`,
+/// while wider programs fall back to `u128`.
+pub trait Word:
+    Copy + Default + Eq + Ord + Hash + Debug + Display + From<bool> + 'static
+    + BitAnd<Output = Self> + BitOr<Output = Self> + BitXor<Output = Self> + Not<Output = Self>
+{
+    const BITS: u32;
+    const ZERO: Self;
+    const ONE: Self;
+
+    /// Truncates `v` down to the word
+    fn from_u128(v: u128) -> Self;
+    fn to_u128(self) -> u128;
+    /// Converts the word into an index, saturating at `usize::MAX`
+    fn as_usize(self) -> usize;
+
+    fn wrapping_add(self, rhs: Self) -> Self;
+    fn wrapping_sub(self, rhs: Self) -> Self;
+    fn wrapping_mul(self, rhs: Self) -> Self;
+    fn checked_div(self, rhs: Self) -> Option<Self>;
+    fn checked_rem(self, rhs: Self) -> Option<Self>;
+    /// Shifts left by `amount`, everything is shifted out when `amount` is at least `BITS`
+    fn shift_left(self, amount: Self) -> Self;
+    /// Shifts right by `amount`, everything is shifted out when `amount` is at least `BITS`
+    fn shift_right(self, amount: Self) -> Self;
+
+    /// All ones in the lower `bits` bits
+    fn mask(bits: usize) -> Self {
+        Self::from_u128(u128::MAX >> (128 - bits))
+    }
+}
+
+macro_rules! impl_word {
+    ($($type: ty),* $(,)?) => {$(
+        impl Word for $type {
+            const BITS: u32 = <$type>::BITS;
+            const ZERO: Self = 0;
+            const ONE: Self = 1;
+
+            #[inline(always)]
+            fn from_u128(v: u128) -> Self { v as $type }
+            #[inline(always)]
+            fn to_u128(self) -> u128 { self as u128 }
+            #[inline(always)]
+            fn as_usize(self) -> usize { usize::try_from(self).unwrap_or(usize::MAX) }
+
+            #[inline(always)]
+            fn wrapping_add(self, rhs: Self) -> Self { <$type>::wrapping_add(self, rhs) }
+            #[inline(always)]
+            fn wrapping_sub(self, rhs: Self) -> Self { <$type>::wrapping_sub(self, rhs) }
+            #[inline(always)]
+            fn wrapping_mul(self, rhs: Self) -> Self { <$type>::wrapping_mul(self, rhs) }
+            #[inline(always)]
+            fn checked_div(self, rhs: Self) -> Option<Self> { <$type>::checked_div(self, rhs) }
+            #[inline(always)]
+            fn checked_rem(self, rhs: Self) -> Option<Self> { <$type>::checked_rem(self, rhs) }
+            #[inline(always)]
+            fn shift_left(self, amount: Self) -> Self {
+                if amount < Self::BITS as $type { self << amount } else { 0 }
+            }
+            #[inline(always)]
+            fn shift_right(self, amount: Self) -> Self {
+                if amount < Self::BITS as $type { self >> amount } else { 0 }
+            }
+        }
+    )*};
+}
+
+impl_word!(u64, u128);
@@ -0,0 +1,114 @@
+use crate::compiler::{
+    common::Any,
+    frontend::ast::*,
+    backend::ssa::*,
+};
+use std::fmt::Write;
+
+/// Escapes `lines` into a left-justified Graphviz label
+fn label<'a>(lines: impl IntoIterator<Item = &'a str>) -> String {
+    let mut label = String::new();
+    for line in lines {
+        label.push_str(&line.replace('\\', "\\\\").replace('"', "\\\"").replace('\t', "    "));
+        label.push_str("\\l");
+    }
+
+    label
+}
+
+/// Renders the control-flow graph of `body` as a DOT graph, with one node per block listing its
+/// instructions.
+pub fn ssa_to_dot(body: &Body) -> String {
+    let mut dot = String::from("digraph ssa {\n    node [shape=box, fontname=\"monospace\"];\n");
+
+    for blk in body.blocks.iter() {
+        let text = blk.to_string();
+        writeln!(dot, "    b{} [label=\"{}\"];", *blk.id, label(text.lines().map(|l| l.trim_start()))).unwrap();
+
+        match blk.terminator {
+            Terminator::Jump(to) => writeln!(dot, "    b{} -> b{};", *blk.id, *to).unwrap(),
+            Terminator::Branch(_, if_, else_) => {
+                writeln!(dot, "    b{} -> b{} [label=\"true\"];", *blk.id, *if_).unwrap();
+                writeln!(dot, "    b{} -> b{} [label=\"false\"];", *blk.id, *else_).unwrap();
+            },
+            Terminator::Return | Terminator::Trap | Terminator::None => {},
+        }
+    }
+
+    dot.push_str("}\n");
+    dot
+}
+
+/// Renders the URCL program `ast` parsed from `src` as a DOT graph of its basic blocks, named
+/// after the labels pointing at them.
+///
+/// Errors like unsupported ports aren't drawn, and a branch to a register only gets its
+/// fall-through edge as the target isn't known.
+pub fn source_to_dot(ast: &Ast, src: &str) -> String {
+    let len = ast.instructions.len();
+    let target = |any: &Any| match any {
+        Any::Immediate(imm) => Some((**imm).min(len as u128) as usize),
+        _ => None,
+    };
+
+    // instructions starting a basic block
+    let mut leader = vec![false; len + 1];
+    leader[0] = true;
+    leader[len] = true;
+    for (_, pc) in ast.labels.iter() {
+        leader[(*pc).min(len)] = true;
+    }
+
+    for (pc, (inst, _)) in ast.instructions.iter().enumerate() {
+        if inst.target().is_some() || !inst.falls_through() {
+            leader[pc + 1] = true;
+        }
+
+        if let Some(t) = inst.target().and_then(target) {
+            leader[t] = true;
+        }
+    }
+
+    let name = |pc: usize| {
+        if pc == len {
+            return "\"halt\"".to_string();
+        }
+
+        let labels: Vec<_> = ast.labels.iter().filter(|(_, l)| *l == pc).map(|(n, _)| format!(".{n}")).collect();
+        if labels.is_empty() {
+            format!("\"inst_{pc}\"")
+        } else {
+            format!("\"{}\"", labels.join(" ").replace('\\', "\\\\").replace('"', "\\\""))
+        }
+    };
+
+    let mut dot = String::from("digraph urcl {\n    node [shape=box, fontname=\"monospace\"];\n");
+    writeln!(dot, "    {} [shape=oval];", name(len)).unwrap();
+
+    let mut start = 0;
+    for pc in (1..=len).filter(|pc| leader[*pc]) {
+        let lines = ast.instructions[start..pc].iter().map(|(_, span)| src[span.clone()].trim());
+        writeln!(dot, "    {} [label=\"{}\"];", name(start), label(lines)).unwrap();
+
+        let last = &ast.instructions[pc - 1].0;
+        match (last.target(), last.falls_through()) {
+            (Some(addr), true) => {
+                if let Some(t) = target(addr) {
+                    writeln!(dot, "    {} -> {} [label=\"true\"];", name(start), name(t)).unwrap();
+                }
+
+                writeln!(dot, "    {} -> {} [label=\"false\"];", name(start), name(pc)).unwrap();
+            },
+            (Some(addr), false) => if let Some(t) = target(addr) {
+                writeln!(dot, "    {} -> {};", name(start), name(t)).unwrap();
+            },
+            (None, true) => writeln!(dot, "    {} -> {};", name(start), name(pc)).unwrap(),
+            (None, false) => writeln!(dot, "    {} -> {};", name(start), name(len)).unwrap(),
+        }
+
+        start = pc;
+    }
+
+    dot.push_str("}\n");
+    dot
+}
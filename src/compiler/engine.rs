@@ -0,0 +1,189 @@
+use crate::compiler::{
+    error::*,
+    common::*,
+    frontend::{ast::*, interpreter::Interpreter as AstInterpreter},
+    backend::{codegen::*, builder::*, opt::*, arch::{interpreter::Interpreter as SsaInterpreter, vm::Vm, threaded::Threaded}},
+};
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+use crate::compiler::backend::arch::x86_64::jit::Jit;
+use std::{io::{self, Read, Write}, str::FromStr};
+
+#[derive(Debug)]
+pub enum StepResult {
+    Error(Error<InterpreterError>),
+    Running,
+    Halted,
+}
+
+/// Counters an engine keeps while it runs, which so far is only how far the program got
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Stats {
+    /// Amount of URCL instructions executed so far
+    pub inst_count: usize,
+}
+
+/// Something that sits on the other end of the ports of a program.
+///
+/// Data always travels as `u128` so that devices work with every word size.
+pub trait Device {
+    fn read(&mut self, port: u128) -> Result<u128, InterpreterError>;
+    fn write(&mut self, port: u128, data: u128) -> Result<(), InterpreterError>;
+}
+
+/// The device used by engines which have nothing attached, every port is unsupported.
+pub struct NoDevice;
+
+impl Device for NoDevice {
+    fn read(&mut self, port: u128) -> Result<u128, InterpreterError> {
+        Err(InterpreterError::UnsupportedPort(port))
+    }
+
+    fn write(&mut self, port: u128, _data: u128) -> Result<(), InterpreterError> {
+        Err(InterpreterError::UnsupportedPort(port))
+    }
+}
+
+/// Maps `%TEXT` and `%NUMBER` onto a pair of byte streams.
+///
+/// Streams that fail give [`InterpreterError::PortFailed`], which engines running the SSA IR see
+/// as a port access that didn't go through.
+pub struct StdioDevice<W: Write, R: Read> {
+    pub stdout: W,
+    pub stdin: R,
+}
+
+impl<W: Write, R: Read> Device for StdioDevice<W, R> {
+    fn read(&mut self, port: u128) -> Result<u128, InterpreterError> {
+        const TEXT: u128 = Port::Text as u128;
+
+        match port {
+            // the end of the input reads as 0, like it does in the code the backends emit
+            TEXT => {
+                let mut buf = [0];
+                match self.stdin.read_exact(&mut buf) {
+                    Ok(()) => Ok(buf[0] as u128),
+                    Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(0),
+                    Err(_) => Err(InterpreterError::PortFailed(port)),
+                }
+            },
+            _ => Err(InterpreterError::UnsupportedPort(port)),
+        }
+    }
+
+    fn write(&mut self, port: u128, data: u128) -> Result<(), InterpreterError> {
+        const TEXT: u128 = Port::Text as u128;
+        const NUMBER: u128 = Port::Number as u128;
+
+        let written = match port {
+            TEXT => write!(self.stdout, "{}", u32::try_from(data).ok().and_then(char::from_u32).unwrap_or(char::REPLACEMENT_CHARACTER)),
+            NUMBER => write!(self.stdout, "{data}"),
+            _ => return Err(InterpreterError::UnsupportedPort(port)),
+        };
+
+        written.map_err(|_| InterpreterError::PortFailed(port))
+    }
+}
+
+/// Common interface of everything that can execute a URCL program.
+pub trait Engine {
+    /// Executes one step, the size of a step is up to the engine.
+    fn step(&mut self) -> StepResult;
+
+    /// Keeps on stepping until the program stops or `budget` more URCL instructions have been
+    /// executed.
+    ///
+    /// Engines running the SSA IR count the URCL instructions of a block all at once when they
+    /// leave it. Unoptimised code has a block per instruction so they stop right at the budget,
+    /// but once blocks got merged they may go over it by less than the size of one block.
+    fn run(&mut self, budget: usize) -> StepResult {
+        let end = self.stats().inst_count.saturating_add(budget);
+        while self.stats().inst_count < end {
+            match self.step() {
+                StepResult::Running => {},
+                other => return other,
+            }
+        }
+
+        StepResult::Running
+    }
+
+    /// Reads register `index`, where `r0` is always 0.
+    ///
+    /// Engines running the SSA IR give [`InterpreterError::OptimisedAway`] here and in the other
+    /// accessors below once the registers or RAM got promoted into values, which `mem2reg` does
+    /// from O1 on. Build the engine at O0 to keep the state of the program observable.
+    fn register(&self, index: usize) -> Result<u128, InterpreterError>;
+    /// Writes register `index`, the value is truncated to the word size of the program.
+    fn set_register(&mut self, index: usize, value: u128) -> Result<(), InterpreterError>;
+
+    /// Reads RAM at `address`.
+    fn memory(&self, address: u128) -> Result<u128, InterpreterError>;
+    /// Writes RAM at `address`, the value is truncated to the word size of the program.
+    fn set_memory(&mut self, address: u128, value: u128) -> Result<(), InterpreterError>;
+
+    fn attach_device(&mut self, device: Box<dyn Device>);
+
+    /// Counters of the program so far, see [`Stats`]
+    fn stats(&self) -> Stats;
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum EngineKind {
+    /// Walks the AST directly
+    Ast,
+    /// Interprets the SSA IR
+    Ssa,
+    /// Runs the SSA IR lowered into bytecode
+    #[default]
+    Vm,
+    /// Runs the SSA IR compiled into chains of closures
+    Threaded,
+    /// Runs the SSA IR compiled into native code, where that is supported
+    Jit,
+}
+
+impl FromStr for EngineKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ast" => Ok(Self::Ast),
+            "ssa" => Ok(Self::Ssa),
+            "vm" => Ok(Self::Vm),
+            "threaded" => Ok(Self::Threaded),
+            "jit" => Ok(Self::Jit),
+            _ => Err(format!("unknown engine `{s}`, expected one of: ast, ssa, vm, threaded, jit")),
+        }
+    }
+}
+
+/// Creates an engine of kind `kind` running `ast`, which only goes for 128-bit words if the
+/// program needs them. Engines running the SSA IR optimise it with `passes` first.
+///
+/// The JIT only exists for 64-bit words on x86-64 Linux, everywhere else the SSA interpreter
+/// takes its place.
+pub fn create_engine(kind: EngineKind, passes: &mut PassManager, ast: Ast) -> Box<dyn Engine> {
+    let wide = ast.bits > 64;
+    match kind {
+        EngineKind::Ast if wide => Box::new(AstInterpreter::<u128>::new(ast)),
+        EngineKind::Ast => Box::new(AstInterpreter::<u64>::new(ast)),
+        EngineKind::Ssa | EngineKind::Vm | EngineKind::Threaded | EngineKind::Jit => {
+            let mut builder = Builder::from_ssa(generate_ssa(ast));
+            passes.run(&mut builder);
+
+            #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+            if kind == EngineKind::Jit && !wide {
+                return Box::new(Jit::new(builder.get_ssa()));
+            }
+
+            match (kind, wide) {
+                (EngineKind::Ssa | EngineKind::Jit, true) => Box::new(SsaInterpreter::<u128>::new(builder.get_ssa())),
+                (EngineKind::Ssa | EngineKind::Jit, false) => Box::new(SsaInterpreter::<u64>::new(builder.get_ssa())),
+                (EngineKind::Threaded, true) => Box::new(Threaded::<u128>::new(builder.get_ssa())),
+                (EngineKind::Threaded, false) => Box::new(Threaded::<u64>::new(builder.get_ssa())),
+                (_, true) => Box::new(Vm::<u128>::new(builder.get_ssa())),
+                (_, false) => Box::new(Vm::<u64>::new(builder.get_ssa())),
+            }
+        },
+    }
+}
@@ -34,10 +34,66 @@ macro_rules! error_kind {
 error_kind!(InterpreterError =
     StackOverflow       "stack overflowed",
     StackUnderflow      "stack underflowed",
-    UnsupportedPort     "unsupported port {}" + u64,
-    MemoryAccessOob     "accessed out-of-bound memory location {}" + u64
+    UnsupportedPort     "unsupported port {}" + u128,
+    MemoryAccessOob     "accessed out-of-bound memory location {}" + u128,
+    RegisterOob         "accessed out-of-bound register {}" + u64,
+    DivisionByZero      "divided by zero",
+    OptimisedAway       "registers and memory were optimised away",
+    PortFailed          "port {} stopped working" + u128,
+    Unknown             "unknown error {}" + u128,
 );
 
+impl InterpreterError {
+    /// Codes of every error kind except `Unknown`
+    pub const CODES: std::ops::RangeInclusive<u128> = 0..=7;
+
+    /// Code of the error kind as passed to `ReportError`
+    pub const fn code(&self) -> u128 {
+        match self {
+            Self::StackOverflow         => 0,
+            Self::StackUnderflow        => 1,
+            Self::UnsupportedPort(_)    => 2,
+            Self::MemoryAccessOob(_)    => 3,
+            Self::RegisterOob(_)        => 4,
+            Self::DivisionByZero        => 5,
+            Self::OptimisedAway         => 6,
+            Self::PortFailed(_)         => 7,
+            Self::Unknown(code)         => *code,
+        }
+    }
+
+    /// Rebuilds an error from its code and the detail that came with it
+    pub fn from_code(code: u128, detail: u128) -> Self {
+        match code {
+            0 => Self::StackOverflow,
+            1 => Self::StackUnderflow,
+            2 => Self::UnsupportedPort(detail),
+            3 => Self::MemoryAccessOob(detail),
+            4 => Self::RegisterOob(detail as u64),
+            5 => Self::DivisionByZero,
+            6 => Self::OptimisedAway,
+            7 => Self::PortFailed(detail),
+            _ => Self::Unknown(code),
+        }
+    }
+
+    /// The message of error `code` with the detail left off its end, and whether it has one.
+    /// Meant for backends that print errors at runtime.
+    pub fn message_without_detail(code: u128) -> (String, bool) {
+        // the detail of `Unknown` is the code itself
+        let (a, b) = if Self::CODES.contains(&code) {
+            (Self::from_code(code, 0).message(), Self::from_code(code, 1).message())
+        } else {
+            (Self::Unknown(0).message(), Self::Unknown(1).message())
+        };
+
+        match a.strip_suffix('0') {
+            Some(prefix) if a != b => (prefix.to_string(), true),
+            _ => (a, false),
+        }
+    }
+}
+
 error_kind!(ParserError =
     SyntaxError             "syntax error",
     LabelNotDefined         "label is not defined anywhere",
@@ -50,6 +106,23 @@ error_kind!(ParserError =
     OperandCountNotMatch    "opcode doesn't support the amound of operand currently specified",
     NameNotDefined          "name is not defined previously",
     UnexpectedEof           "unexpected end of file",
+    UnsupportedBits         "word size has to be between 1 and 128 bits",
+);
+
+error_kind!(IrError =
+    SyntaxError             "syntax error",
+    Expecting               "expecting {}" + &'static str,
+    UnknownOperation        "unknown operation `{}`" + String,
+    UnknownFunction         "unknown function `{}`" + String,
+    BlockOutOfOrder         "blocks have to be numbered in order, expecting ${}" + usize,
+    BadBlockComment         "block comment has to be a name, optionally followed by spans like `[0: 1..2]`",
+    UnknownBlock            "block ${} does not exist" + usize,
+    UndefinedValue          "value %{} is never defined" + usize,
+);
+
+error_kind!(UrclError =
+    RamResized              "URCL can't resize the RAM",
+    UnpromotedVariable      "URCL only has memory for the RAM, #{} has to be promoted first" + usize,
 );
 
 pub struct LexerError;
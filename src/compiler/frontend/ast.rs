@@ -1,10 +1,13 @@
 use crate::compiler::{error::*, common::*, frontend::lexer::*};
 use logos::Span;
+use std::fmt::{self, Display};
 
 #[derive(Debug)]
 pub struct Ast {
     pub instructions: Vec<(Instruction, Span)>,
-    pub dw: Vec<u64>,
+    pub dw: Vec<u128>,
+    /// Every label along with the instruction it points at, sorted by that instruction
+    pub labels: Vec<(String, usize)>,
     pub minheap: usize,
     pub minstack: usize,
     pub minreg: usize,
@@ -16,12 +19,340 @@ impl Ast {
         Self {
             instructions: Vec::new(),
             dw: Vec::new(),
+            labels: Vec::new(),
             minheap: 16,
             minstack: 8,
             minreg: 8,
             bits: 8,
         }
     }
+
+    /// All ones in the lower `bits` bits of a word
+    pub const fn mask(&self) -> u128 {
+        u128::MAX >> (128 - self.bits)
+    }
+
+    /// Rewrites the program to only use the core instructions (`ADD`, `RSH`, `LOD`, `STR`, `BGE`
+    /// and `NOR`), so that it can run on machines implementing nothing else.
+    ///
+    /// Instructions without a core equivalent expand into several, which keep what they need
+    /// along the way in registers past the ones the program asks for, raising `minreg` to cover
+    /// them. Labels and branch targets move to where their instruction starts now. `IN` and `OUT`
+    /// are left alone as ports have no core form.
+    ///
+    /// Branches to a register keep going to the same number, which is only the same instruction
+    /// if nothing before it expanded. Dividing by zero doesn't stop the program any more, it
+    /// gives all ones with the dividend left over.
+    pub fn lower_to_core(&mut self) {
+        let len = self.instructions.len();
+        let mut temps = 0;
+        let expansions: Vec<_> = std::mem::take(&mut self.instructions).into_iter()
+            .map(|(inst, span)| {
+                let (core, used) = expand(inst, self.minreg + 1, self.bits);
+                temps = temps.max(used);
+                (core, span)
+            })
+            .collect();
+
+        // where every instruction starts now, and then where the program ends
+        let mut starts = Vec::with_capacity(len + 1);
+        let mut end = 0;
+        for (core, _) in expansions.iter() {
+            starts.push(end);
+            end += core.len();
+        }
+        starts.push(end);
+
+        for (pc, (core, span)) in expansions.into_iter().enumerate() {
+            for step in core {
+                let inst = match step {
+                    Core::Inst(inst) => inst,
+                    Core::Bge(to, a, b) => Instruction::BGE(match to {
+                        To::Source(Any::Immediate(t)) => imm(starts[(*t).min(len as u128) as usize] as u128),
+                        To::Source(other) => other,
+                        To::Local(k) => imm((starts[pc] + k) as u128),
+                        To::End => imm(end as u128),
+                    }, a, b),
+                };
+                self.instructions.push((inst, span.clone()));
+            }
+        }
+
+        for (_, pc) in self.labels.iter_mut() {
+            *pc = starts[(*pc).min(len)];
+        }
+        self.minreg += temps;
+    }
+}
+
+/// Where a branch of an expansion goes
+#[derive(Clone)]
+enum To {
+    /// The target of the instruction being expanded
+    Source(Any),
+    /// This many instructions into the expansion, which may be right after it
+    Local(usize),
+    /// Past the last instruction, halting
+    End,
+}
+
+/// An instruction of an expansion, with branches going somewhere only known once every
+/// instruction got expanded
+enum Core {
+    Inst(Instruction),
+    Bge(To, Any, Any),
+}
+
+/// Operands of the expansions, registers being given by their number
+trait Operand {
+    fn operand(&self) -> Any;
+}
+
+impl Operand for Any {
+    fn operand(&self) -> Any {
+        self.clone()
+    }
+}
+
+impl Operand for Register {
+    fn operand(&self) -> Any {
+        Any::Register(*self)
+    }
+}
+
+fn imm(v: u128) -> Any {
+    Any::Immediate(Box::new(v))
+}
+
+macro_rules! core {
+    ($($op: ident $($arg: expr),*);* $(;)?) => {
+        vec![$(core!(@$op $($arg),*)),*]
+    };
+    (@BGE $to: expr, $a: expr, $b: expr) => {
+        Core::Bge($to, $a.operand(), $b.operand())
+    };
+    (@STR $a: expr, $v: expr) => {
+        Core::Inst(Instruction::STR($a.operand(), $v.operand()))
+    };
+    (@$op: ident $d: expr $(, $arg: expr)*) => {
+        Core::Inst(Instruction::$op($d, $($arg.operand()),*))
+    };
+}
+
+/// The core instructions doing what `inst` does, along with how many registers from `temp` on
+/// they use
+fn expand(inst: Instruction, temp: Register, bits: usize) -> (Vec<Core>, usize) {
+    let (mask, sign) = (imm(u128::MAX >> (128 - bits)), imm(1 << (bits - 1)));
+    let (t, u, v, w) = (temp, temp + 1, temp + 2, temp + 3);
+    let r0 = Any::Register(0);
+    let (divides, shifts_left) = (matches!(inst, Instruction::DIV(..)), matches!(inst, Instruction::BSL(..)));
+
+    match inst {
+        // `r0` always reads as zero
+        Instruction::IMM(d, a) | Instruction::MOV(d, a) => (core![ADD d, a, r0], 0),
+        Instruction::SUB(d, a, Any::Immediate(b)) => (core![ADD d, a, imm(b.wrapping_neg() & (u128::MAX >> (128 - bits)))], 0),
+        Instruction::SUB(d, a, b) => (core![NOR t, b, b; ADD t, t, imm(1); ADD d, a, t], 1),
+        Instruction::JMP(to) => (core![BGE To::Source(to), r0, r0], 0),
+        Instruction::NOP() => (Vec::new(), 0),
+        Instruction::HLT() => (core![BGE To::End, r0, r0], 0),
+        Instruction::LSH(d, a) => (core![ADD d, a, a], 0),
+        Instruction::INC(d, a) => (core![ADD d, a, imm(1)], 0),
+        Instruction::DEC(d, a) => (core![ADD d, a, mask], 0),
+        Instruction::NEG(d, a) => (core![NOR d, a, a; ADD d, d, imm(1)], 0),
+        Instruction::NOT(d, a) => (core![NOR d, a, a], 0),
+        // a & b = ~(~a | ~b)
+        Instruction::AND(d, a, b) => (core![NOR t, a, a; NOR d, b, b; NOR d, t, d], 1),
+        Instruction::NAND(d, a, b) => (core![NOR t, a, a; NOR d, b, b; NOR d, t, d; NOR d, d, d], 1),
+        Instruction::OR(d, a, b) => (core![NOR d, a, b; NOR d, d, d], 0),
+        // a ^ b = ~(~(a | b) | (a & b))
+        Instruction::XOR(d, a, b) => (core![NOR t, a, a; NOR u, b, b; NOR u, t, u; NOR t, a, b; NOR d, t, u], 2),
+        Instruction::XNOR(d, a, b) => (core![NOR t, a, a; NOR u, b, b; NOR u, t, u; NOR t, a, b; NOR d, t, u; NOR d, d, d], 2),
+        Instruction::CPY(a, b) => (core![LOD t, b; STR a, t], 1),
+        Instruction::LLOD(d, a, b) => (core![ADD t, a, b; LOD d, t], 1),
+        Instruction::LSTR(a, b, c) => (core![ADD t, a, b; STR t, c], 1),
+        // shift and add, going through the bits of `b` from the lowest one up
+        Instruction::MLT(d, a, b) => (core![
+            ADD t, a, r0;
+            ADD u, b, r0;
+            ADD v, r0, r0;
+            BGE To::Local(11), r0, u;
+            // `u` is even if clearing its lowest bit leaves it the same
+            RSH w, u;
+            ADD w, w, w;
+            BGE To::Local(8), w, u;
+            ADD v, v, t;
+            ADD t, t, t;
+            RSH u, u;
+            BGE To::Local(3), r0, r0;
+            ADD d, v, r0;
+        ], 4),
+        // restoring division, shifting `a` into the remainder `u` from its highest bit down
+        // while the bits freed up at the bottom of `t` collect the quotient
+        Instruction::DIV(d, a, b) | Instruction::MOD(d, a, b) => {
+            let result = if divides { t } else { u };
+            (core![
+                ADD t, a, r0;
+                ADD u, r0, r0;
+                ADD v, r0, imm(bits as u128);
+                BGE To::Local(19), r0, v;
+                ADD v, v, mask;
+                ADD w, u, r0;
+                ADD u, u, u;
+                BGE To::Local(9), t, sign;
+                BGE To::Local(10), r0, r0;
+                ADD u, u, imm(1);
+                ADD t, t, t;
+                // a remainder which had its highest bit set is past anything `b` can be now
+                BGE To::Local(14), w, sign;
+                BGE To::Local(14), u, b;
+                BGE To::Local(3), r0, r0;
+                // u - b = ~(~u + b)
+                NOR u, u, u;
+                ADD u, u, b;
+                NOR u, u, u;
+                ADD t, t, imm(1);
+                BGE To::Local(3), r0, r0;
+                ADD d, result, r0;
+            ], 4)
+        },
+        Instruction::BSL(d, a, b) | Instruction::BSR(d, a, b) => {
+            let shift = if shifts_left { Instruction::ADD(t, t.operand(), t.operand()) } else { Instruction::RSH(t, t.operand()) };
+            let mut out = core![
+                ADD t, a, r0;
+                ADD u, b, r0;
+                BGE To::Local(7), u, imm(bits as u128);
+                BGE To::Local(8), r0, u;
+            ];
+            out.push(Core::Inst(shift));
+            out.extend(core![
+                ADD u, u, mask;
+                BGE To::Local(3), r0, r0;
+                ADD t, r0, r0;
+                ADD d, t, r0;
+            ]);
+            (out, 2)
+        },
+        Instruction::SETE(d, a, b) => set_if(d, &Instruction::BRE(r0, a, b), temp, bits),
+        Instruction::SETNE(d, a, b) => set_if(d, &Instruction::BNE(r0, a, b), temp, bits),
+        Instruction::SETG(d, a, b) => set_if(d, &Instruction::BRG(r0, a, b), temp, bits),
+        Instruction::SETL(d, a, b) => set_if(d, &Instruction::BRL(r0, a, b), temp, bits),
+        Instruction::SETGE(d, a, b) => set_if(d, &Instruction::BGE(r0, a, b), temp, bits),
+        Instruction::SETLE(d, a, b) => set_if(d, &Instruction::BLE(r0, a, b), temp, bits),
+        inst => match inst.target() {
+            Some(to) => branch(&inst, To::Source(to.clone()), 0, temp, bits),
+            None => (vec![Core::Inst(inst)], 0),
+        },
+    }
+}
+
+/// The core instructions going to `to` when the branch `inst` would, starting `at` instructions
+/// into the expansion
+fn branch(inst: &Instruction, to: To, at: usize, temp: Register, bits: usize) -> (Vec<Core>, usize) {
+    let sign = imm(1 << (bits - 1));
+    let (t, r0) = (temp, Any::Register(0));
+    let skip = |n| To::Local(at + n);
+
+    match inst {
+        Instruction::BGE(_, a, b) => (core![BGE to, a, b], 0),
+        Instruction::BRL(_, a, b) => (core![BGE skip(2), a, b; BGE to, r0, r0], 0),
+        Instruction::BRG(_, a, b) => (core![BGE skip(2), b, a; BGE to, r0, r0], 0),
+        Instruction::BLE(_, a, b) => (core![BGE to, b, a], 0),
+        Instruction::BRE(_, a, b) => (core![BGE skip(2), a, b; BGE skip(3), r0, r0; BGE to, b, a], 0),
+        Instruction::BNE(_, a, b) => (core![BGE skip(2), a, b; BGE to.clone(), r0, r0; BGE skip(4), b, a; BGE to, r0, r0], 0),
+        // `a` is even if clearing its lowest bit leaves it the same
+        Instruction::BOD(_, a) => (core![RSH t, a; ADD t, t, t; BGE skip(4), t, a; BGE to, r0, r0], 1),
+        Instruction::BEV(_, a) => (core![RSH t, a; ADD t, t, t; BGE to, t, a], 1),
+        Instruction::BRZ(_, a) => (core![BGE to, r0, a], 0),
+        Instruction::BNZ(_, a) => (core![BGE to, a, imm(1)], 0),
+        Instruction::BRN(_, a) => (core![BGE to, a, sign], 0),
+        Instruction::BRP(_, a) => (core![BGE skip(2), a, sign; BGE to, r0, r0], 0),
+        Instruction::JMP(_) => (core![BGE to, r0, r0], 0),
+        _ => unreachable!("`{}` doesn't branch", inst.name()),
+    }
+}
+
+/// The core instructions setting every bit of `d` if the branch `cond` would be taken, and
+/// clearing them otherwise
+fn set_if(d: Register, cond: &Instruction, temp: Register, bits: usize) -> (Vec<Core>, usize) {
+    let (t, r0) = (temp, Any::Register(0));
+
+    // branches take as many instructions wherever they go
+    let skip = branch(cond, To::End, 1, temp + 1, bits).0.len();
+    let (cond, used) = branch(cond, To::Local(skip + 2), 1, temp + 1, bits);
+
+    let mut out = core![ADD t, r0, imm(u128::MAX >> (128 - bits))];
+    out.extend(cond);
+    out.extend(core![ADD t, r0, r0; ADD d, t, r0]);
+    (out, used + 1)
+}
+
+/// Prints the program back as URCL, with branch targets going to the labels pointing at them
+impl Display for Ast {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "bits {}", self.bits)?;
+        writeln!(f, "minreg {}", self.minreg)?;
+        writeln!(f, "minheap {}", self.minheap)?;
+        writeln!(f, "minstack {}", self.minstack)?;
+        for w in self.dw.iter() {
+            writeln!(f, "dw {}", immediate(*w))?;
+        }
+
+        let operand = |any: &Any| match any {
+            Any::Register(r) => format!("r{r}"),
+            Any::Immediate(imm) => immediate(**imm),
+            Any::UnresolvedLabel(_) | Any::Name(_) => unreachable!(),
+        };
+        let target = |any: &Any| match any {
+            Any::Immediate(imm) => self.labels.iter()
+                .find(|(_, pc)| *pc as u128 == **imm)
+                .map_or_else(|| operand(any), |(name, _)| format!(".{name}")),
+            other => operand(other),
+        };
+
+        let mut labels = self.labels.iter().peekable();
+        for (pc, (inst, _)) in self.instructions.iter().enumerate() {
+            while let Some((name, _)) = labels.next_if(|(_, at)| *at == pc) {
+                writeln!(f, ".{name}")?;
+            }
+
+            let name = inst.name();
+            match inst {
+                Instruction::ADD(d, a, b) | Instruction::NOR(d, a, b) | Instruction::SUB(d, a, b)
+                    | Instruction::AND(d, a, b) | Instruction::OR(d, a, b) | Instruction::XOR(d, a, b)
+                    | Instruction::XNOR(d, a, b) | Instruction::NAND(d, a, b) | Instruction::MLT(d, a, b)
+                    | Instruction::DIV(d, a, b) | Instruction::MOD(d, a, b) | Instruction::BSL(d, a, b)
+                    | Instruction::BSR(d, a, b) | Instruction::SETE(d, a, b) | Instruction::SETNE(d, a, b)
+                    | Instruction::SETG(d, a, b) | Instruction::SETL(d, a, b) | Instruction::SETGE(d, a, b)
+                    | Instruction::SETLE(d, a, b) | Instruction::LLOD(d, a, b) => writeln!(f, "    {name} r{d} {} {}", operand(a), operand(b))?,
+                Instruction::RSH(d, a) | Instruction::LOD(d, a) | Instruction::IMM(d, a) | Instruction::MOV(d, a)
+                    | Instruction::LSH(d, a) | Instruction::INC(d, a) | Instruction::DEC(d, a) | Instruction::NEG(d, a)
+                    | Instruction::NOT(d, a) | Instruction::IN(d, a) => writeln!(f, "    {name} r{d} {}", operand(a))?,
+                Instruction::STR(a, b) | Instruction::CPY(a, b) | Instruction::OUT(a, b) => writeln!(f, "    {name} {} {}", operand(a), operand(b))?,
+                Instruction::LSTR(a, b, c) => writeln!(f, "    {name} {} {} {}", operand(a), operand(b), operand(c))?,
+                Instruction::BGE(t, a, b) | Instruction::BRL(t, a, b) | Instruction::BRG(t, a, b) | Instruction::BRE(t, a, b)
+                    | Instruction::BNE(t, a, b) | Instruction::BLE(t, a, b) => writeln!(f, "    {name} {} {} {}", target(t), operand(a), operand(b))?,
+                Instruction::BOD(t, a) | Instruction::BEV(t, a) | Instruction::BRZ(t, a) | Instruction::BNZ(t, a)
+                    | Instruction::BRN(t, a) | Instruction::BRP(t, a) => writeln!(f, "    {name} {} {}", target(t), operand(a))?,
+                Instruction::JMP(t) => writeln!(f, "    {name} {}", target(t))?,
+                Instruction::NOP() | Instruction::HLT() => writeln!(f, "    {name}")?,
+            }
+        }
+
+        for (name, _) in labels {
+            writeln!(f, ".{name}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Numbers past `i128::MAX` don't lex, so they are printed as their negative counterpart
+fn immediate(int: u128) -> String {
+    if int > i128::MAX as u128 {
+        (int as i128).to_string()
+    } else {
+        int.to_string()
+    }
 }
 
 pub type AToken = (Token, Span);
@@ -1,38 +1,80 @@
-use super::{common::*, ast::*, error::*};
+use crate::compiler::{common::*, error::*, engine::*, frontend::ast::*};
+use derivative::Derivative;
 use logos::Span;
-use std::io::{Read, Write};
 
-#[derive(Debug)]
-pub struct Interpreter {
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct Interpreter<W: Word> {
     ast: Ast,
+    #[derivative(Debug="ignore")]
+    device: Box<dyn Device>,
 
     pc: usize,
-    sp: usize,
 
-    ram: Vec<u64>,
-    reg: Vec<u64>,
+    mask: W,
+    ram: Vec<W>,
+    reg: Vec<W>,
 
+    pub inst_count: usize,
     pub debugging: bool
 }
 
-impl Interpreter {
+impl<W: Word> Interpreter<W> {
     pub fn new(ast: Ast) -> Self {
-        let ram_size = ast.dw.len() + ast.minheap + ast.minstack;
+        let mask = W::mask(ast.bits);
+        let mut ram: Vec<W> = ast.dw.iter().map(|w| W::from_u128(*w) & mask).collect();
+        ram.resize(ast.dw.len() + ast.minheap + ast.minstack, W::ZERO);
         let regs = ast.minreg;
         Self {
             ast,
+            device: Box::new(NoDevice),
 
             pc: 0,
-            sp: 0,
 
-            ram: vec![0; ram_size],
-            reg: vec![0; regs],
+            mask,
+            ram,
+            reg: vec![W::ZERO; regs],
 
+            inst_count: 0,
             debugging: false
         }
     }
 
-    pub fn step(&mut self, stdout: &mut impl Write, stdin: &mut impl Read) -> StepResult {
+    fn fetch(&mut self) -> Option<&(Instruction, Span)> {
+        self.pc += 1;
+        self.ast.instructions.get(self.pc-1)
+    }
+
+    fn port_out(&mut self, port: W, data: W, span: Span) -> StepResult {
+        const PROFILE: u128 = Port::Profile as u128;
+
+        match port.to_u128() {
+            PROFILE => {
+                self.debugging = data & W::ONE != W::ZERO;
+                println!("\x1b[1;32mInterpreter:\x1b[0m debugging is now {}", if self.debugging { "enabled" } else { "disabled" });
+            },
+            port => if let Err(kind) = self.device.write(port, data.to_u128()) {
+                return StepResult::Error(Error { kind, span });
+            },
+        }
+
+        StepResult::Running
+    }
+
+    fn port_in(&mut self, port: W, rd: usize, span: Span) -> StepResult {
+        match self.device.read(port.to_u128()) {
+            Ok(val) => if rd != 0 {
+                self.reg[rd-1] = W::from_u128(val) & self.mask;
+            },
+            Err(kind) => return StepResult::Error(Error { kind, span }),
+        }
+
+        StepResult::Running
+    }
+}
+
+impl<W: Word> Engine for Interpreter<W> {
+    fn step(&mut self) -> StepResult {
         macro_rules! step {
             ($op: expr => $sr: expr) => {{
                 $op;
@@ -42,7 +84,7 @@ impl Interpreter {
 
         macro_rules! trunc {
             ($v: expr) => {
-                $v & ((1 << self.ast.bits as u64) - 1)
+                $v & self.mask
             };
         }
 
@@ -71,23 +113,23 @@ impl Interpreter {
                 if reg != 0 {
                     self.reg[reg-1]
                 } else {
-                    0
+                    W::ZERO
                 }
             }};
             (get any $val: expr) => {{
                 match $val {
                     Any::Register(reg) => op!(get reg *reg),
-                    Any::Immediate(imm) => trunc!(**imm),
+                    Any::Immediate(imm) => trunc!(W::from_u128(**imm)),
                     _ => unreachable!(),
                 }
             }};
             (get mem $adr: expr, in $span: expr) => {{
                 let adr = $adr;
-                *some_or_error!(self.ram.get(adr as usize), InterpreterError::MemoryAccessOob(adr), in $span)
+                *some_or_error!(self.ram.get(adr.as_usize()), InterpreterError::MemoryAccessOob(adr.to_u128()), in $span)
             }};
             (set mem $adr: expr => $dt: expr, in $span: expr) => {{
                 let adr = $adr;
-                *some_or_error!(self.ram.get_mut(adr as usize), InterpreterError::MemoryAccessOob(adr), in $span) = $dt;
+                *some_or_error!(self.ram.get_mut(adr.as_usize()), InterpreterError::MemoryAccessOob(adr.to_u128()), in $span) = $dt;
             }};
         }
 
@@ -110,6 +152,7 @@ impl Interpreter {
         macro_rules! match_opcode {
             (|$span:ident| $($opc: ident $($iv: ident)* = $action: expr),* $(,)?) => {
                 if let Some((inst, span)) = self.fetch().cloned() {
+                    self.inst_count += 1;
                     match &inst {
                         $(
                             Instruction::$opc($($iv),*) => {
@@ -126,75 +169,97 @@ impl Interpreter {
         }
 
         match_opcode!(|s|
-            ADD d a b = step!(op!(set reg *d => trunc!(op!(get any a) + op!(get any b))) => StepResult::Running),
-            NOR d a b = step!(op!(set reg *d => !(op!(get any a) | op!(get any b))) => StepResult::Running),
-            BGE d a b = step!(_if!((op!(get any a) >= op!(get any b)) => branch!(op!(get any d) as usize)) => StepResult::Running),
+            ADD d a b = step!(op!(set reg *d => trunc!(op!(get any a).wrapping_add(op!(get any b)))) => StepResult::Running),
+            NOR d a b = step!(op!(set reg *d => trunc!(!(op!(get any a) | op!(get any b)))) => StepResult::Running),
+            BGE d a b = step!(_if!((op!(get any a) >= op!(get any b)) => branch!(op!(get any d).as_usize())) => StepResult::Running),
             STR d v = step!(op!(set mem op!(get any d) => op!(get any v), in s) => StepResult::Running),
             LOD d v = step!(op!(set reg *d => op!(get mem op!(get any v), in s)) => StepResult::Running),
-            RSH d v = step!(op!(set reg *d => op!(get any v) >> 1) => StepResult::Running),
+            RSH d v = step!(op!(set reg *d => op!(get any v).shift_right(W::ONE)) => StepResult::Running),
             IMM d v = step!(op!(set reg *d => op!(get any v)) => StepResult::Running),
             MOV d v = step!(op!(set reg *d => op!(get any v)) => StepResult::Running),
-            OUT p v = self.port_out(op!(get any p), op!(get any v), s, stdout),
-            IN d p = self.port_in(op!(get any p), *d, s, stdin),
+            SUB d a b = step!(op!(set reg *d => trunc!(op!(get any a).wrapping_sub(op!(get any b)))) => StepResult::Running),
+            JMP t = step!(branch!(op!(get any t).as_usize()) => StepResult::Running),
+            NOP = StepResult::Running,
+            LSH d v = step!(op!(set reg *d => trunc!(op!(get any v).shift_left(W::ONE))) => StepResult::Running),
+            INC d v = step!(op!(set reg *d => trunc!(op!(get any v).wrapping_add(W::ONE))) => StepResult::Running),
+            DEC d v = step!(op!(set reg *d => trunc!(op!(get any v).wrapping_sub(W::ONE))) => StepResult::Running),
+            NEG d v = step!(op!(set reg *d => trunc!(W::ZERO.wrapping_sub(op!(get any v)))) => StepResult::Running),
+            AND d a b = step!(op!(set reg *d => op!(get any a) & op!(get any b)) => StepResult::Running),
+            OR d a b = step!(op!(set reg *d => op!(get any a) | op!(get any b)) => StepResult::Running),
+            NOT d v = step!(op!(set reg *d => trunc!(!op!(get any v))) => StepResult::Running),
+            XOR d a b = step!(op!(set reg *d => op!(get any a) ^ op!(get any b)) => StepResult::Running),
+            XNOR d a b = step!(op!(set reg *d => trunc!(!(op!(get any a) ^ op!(get any b)))) => StepResult::Running),
+            NAND d a b = step!(op!(set reg *d => trunc!(!(op!(get any a) & op!(get any b)))) => StepResult::Running),
+            BRL t a b = step!(_if!((op!(get any a) < op!(get any b)) => branch!(op!(get any t).as_usize())) => StepResult::Running),
+            BRG t a b = step!(_if!((op!(get any a) > op!(get any b)) => branch!(op!(get any t).as_usize())) => StepResult::Running),
+            BRE t a b = step!(_if!((op!(get any a) == op!(get any b)) => branch!(op!(get any t).as_usize())) => StepResult::Running),
+            BNE t a b = step!(_if!((op!(get any a) != op!(get any b)) => branch!(op!(get any t).as_usize())) => StepResult::Running),
+            BLE t a b = step!(_if!((op!(get any a) <= op!(get any b)) => branch!(op!(get any t).as_usize())) => StepResult::Running),
+            BOD t a = step!(_if!((op!(get any a) & W::ONE != W::ZERO) => branch!(op!(get any t).as_usize())) => StepResult::Running),
+            BEV t a = step!(_if!((op!(get any a) & W::ONE == W::ZERO) => branch!(op!(get any t).as_usize())) => StepResult::Running),
+            BRZ t a = step!(_if!((op!(get any a) == W::ZERO) => branch!(op!(get any t).as_usize())) => StepResult::Running),
+            BNZ t a = step!(_if!((op!(get any a) != W::ZERO) => branch!(op!(get any t).as_usize())) => StepResult::Running),
+            // the sign is the highest of the `bits` bits
+            BRN t a = step!(_if!((op!(get any a) > self.mask.shift_right(W::ONE)) => branch!(op!(get any t).as_usize())) => StepResult::Running),
+            BRP t a = step!(_if!((op!(get any a) <= self.mask.shift_right(W::ONE)) => branch!(op!(get any t).as_usize())) => StepResult::Running),
+            CPY d v = step!(op!(set mem op!(get any d) => op!(get mem op!(get any v), in s.clone()), in s) => StepResult::Running),
+            HLT = step!(branch!(self.ast.instructions.len()) => StepResult::Halted),
+            MLT d a b = step!(op!(set reg *d => trunc!(op!(get any a).wrapping_mul(op!(get any b)))) => StepResult::Running),
+            DIV d a b = step!(op!(set reg *d => some_or_error!(op!(get any a).checked_div(op!(get any b)), InterpreterError::DivisionByZero, in s)) => StepResult::Running),
+            MOD d a b = step!(op!(set reg *d => some_or_error!(op!(get any a).checked_rem(op!(get any b)), InterpreterError::DivisionByZero, in s)) => StepResult::Running),
+            BSL d a b = step!(op!(set reg *d => trunc!(op!(get any a).shift_left(op!(get any b)))) => StepResult::Running),
+            BSR d a b = step!(op!(set reg *d => op!(get any a).shift_right(op!(get any b))) => StepResult::Running),
+            // conditions set every bit when they hold
+            SETE d a b = step!(op!(set reg *d => _if!((op!(get any a) == op!(get any b)) => self.mask; W::ZERO)) => StepResult::Running),
+            SETNE d a b = step!(op!(set reg *d => _if!((op!(get any a) != op!(get any b)) => self.mask; W::ZERO)) => StepResult::Running),
+            SETG d a b = step!(op!(set reg *d => _if!((op!(get any a) > op!(get any b)) => self.mask; W::ZERO)) => StepResult::Running),
+            SETL d a b = step!(op!(set reg *d => _if!((op!(get any a) < op!(get any b)) => self.mask; W::ZERO)) => StepResult::Running),
+            SETGE d a b = step!(op!(set reg *d => _if!((op!(get any a) >= op!(get any b)) => self.mask; W::ZERO)) => StepResult::Running),
+            SETLE d a b = step!(op!(set reg *d => _if!((op!(get any a) <= op!(get any b)) => self.mask; W::ZERO)) => StepResult::Running),
+            LLOD d a b = step!(op!(set reg *d => op!(get mem trunc!(op!(get any a).wrapping_add(op!(get any b))), in s)) => StepResult::Running),
+            LSTR a b v = step!(op!(set mem trunc!(op!(get any a).wrapping_add(op!(get any b))) => op!(get any v), in s) => StepResult::Running),
+            OUT p v = self.port_out(op!(get any p), op!(get any v), s),
+            IN d p = self.port_in(op!(get any p), *d, s),
         )
     }
 
-    fn fetch(&mut self) -> Option<&(Instruction, Span)> {
-        self.pc += 1;
-        self.ast.instructions.get(self.pc-1)
+    fn register(&self, index: usize) -> Result<u128, InterpreterError> {
+        match index {
+            0 => Ok(0),
+            _ => self.reg.get(index-1).map(|v| v.to_u128()).ok_or(InterpreterError::RegisterOob(index as u64)),
+        }
     }
 
-    fn port_out(&mut self, port: u64, data: u64, span: Span, stdout: &mut impl Write) -> StepResult {
-        match unsafe { std::mem::transmute(port) } {
-            Port::Text => write!(stdout, "{}", unsafe { char::from_u32_unchecked(data as u32) }).unwrap(),
-            Port::Number => write!(stdout, "{data}").unwrap(),
-
-            Port::Profile => {
-                self.debugging = data & 1 != 0;
-                println!("\x1b[1;32mInterpreter:\x1b[0m debugging is now {}", if self.debugging { "enabled" } else { "disabled" });
+    fn set_register(&mut self, index: usize, value: u128) -> Result<(), InterpreterError> {
+        match index {
+            0 => Ok(()),
+            _ => {
+                *self.reg.get_mut(index-1).ok_or(InterpreterError::RegisterOob(index as u64))? = W::from_u128(value) & self.mask;
+                Ok(())
             },
-            _ => return StepResult::Error(Error { kind: InterpreterError::UnsupportedPort(port), span }),
         }
-
-        StepResult::Running
     }
 
-    fn port_in(&mut self, port: u64, rd: usize, span: Span, stdin: &mut impl Read) -> StepResult {
-        macro_rules! set_reg {
-            ($val: expr) => {
-                if rd != 0 {
-                    self.reg[rd-1] = $val;
-                }
-            };
-        }
-
-        match unsafe { std::mem::transmute(port) } {
-            Port::Text => set_reg!({
-                let mut buf = [0];
-                stdin.read_exact(&mut buf).unwrap();
-                buf[0] as u64
-            }),
-            _ => return StepResult::Error(Error { kind: InterpreterError::UnsupportedPort(port), span }),
-        }
+    fn memory(&self, address: u128) -> Result<u128, InterpreterError> {
+        usize::try_from(address).ok()
+            .and_then(|a| self.ram.get(a))
+            .map(|v| v.to_u128())
+            .ok_or(InterpreterError::MemoryAccessOob(address))
+    }
 
-        StepResult::Running
+    fn set_memory(&mut self, address: u128, value: u128) -> Result<(), InterpreterError> {
+        let mask = self.mask;
+        *usize::try_from(address).ok()
+            .and_then(|a| self.ram.get_mut(a))
+            .ok_or(InterpreterError::MemoryAccessOob(address))? = W::from_u128(value) & mask;
+        Ok(())
     }
-}
 
-#[derive(Debug)]
-pub enum StepResult {
-    Error(Error<InterpreterError>),
-    Running,
-    Halted,
-}
+    fn attach_device(&mut self, device: Box<dyn Device>) {
+        self.device = device;
+    }
 
-#[derive(Debug)]
-#[repr(u64)]
-pub enum Port {
-    CpuBus, Text, Number, Supported = 5, Special, Profile,
-    X, Y, Color, Buffer, GSpecial = 15,
-    Ascii8, Char5, Char6, Ascii7, Utf8, TSpecial = 23,
-    Int, UInt, Bin, Hex, Float, Fixed, NSpecial = 31,
-    Addr, Bus, Page, SSpecial = 39,
-    Rng, Note, Instr, NLeg, Wait, NAddr, Data, MSpecial,
+    fn stats(&self) -> Stats {
+        Stats { inst_count: self.inst_count }
+    }
 }
@@ -6,8 +6,8 @@ pub use logos::{Logos, Lexer};
 #[logos(skip r"[\s^\n]")]
 #[logos(skip r"/\*([^*]|\*[^/])*\*/")]
 pub enum Token {
-    #[regex(r"(\+|\-)?(0[xX][A-Fa-f0-9]+|0[bB][0-1]+|0[oO][0-7]+|[0-9]+)", callback = |lex| parse_number(lex, 0).unwrap(), priority = 2)]
-    Number(i64),
+    #[regex(r"(\+|\-)?(0[xX][A-Fa-f0-9]+|0[bB][0-1]+|0[oO][0-7]+|[0-9]+)", callback = |lex| parse_number(lex, 0), priority = 2)]
+    Number(i128),
 
     #[regex(r"@[\S]+", callback = |lex| rm_prefix(lex, 1))]
     #[regex(r"bits"    , callback = |_| "bits".to_string(), ignore(case))]
@@ -25,10 +25,10 @@ pub enum Token {
     #[regex(r"[a-zA-Z_\u0100-\uFFFF][a-zA-Z_0-9\u0100-\uFFFF]*", callback = |lex| rm_prefix(lex, 0), priority = 0)]
     Name(String),
 
-    #[regex(r"(R|r|\$)(\+|\-)?(0[xX][A-Fa-f0-9]+|0[bB][0-1]+|0[oO][0-7]+|[0-9]+)", callback = |lex| parse_number(lex, 1).unwrap())]
+    #[regex(r"(R|r|\$)(\+|\-)?(0[xX][A-Fa-f0-9]+|0[bB][0-1]+|0[oO][0-7]+|[0-9]+)", callback = |lex| parse_number(lex, 1).map(|n| n as i64))]
     Register(i64),
 
-    #[regex(r"(M|m|\#)(\+|\-)?(0[xX][A-Fa-f0-9]+|0[bB][0-1]+|0[oO][0-7]+|[0-9]+)", callback = |lex| parse_number(lex, 1).unwrap())]
+    #[regex(r"(M|m|\#)(\+|\-)?(0[xX][A-Fa-f0-9]+|0[bB][0-1]+|0[oO][0-7]+|[0-9]+)", callback = |lex| parse_number(lex, 1).map(|n| n as i64))]
     Memory(i64),
 
     #[regex(r"'([^']|\\.|\\x[0-9a-fA-F]+|\\u[0-9a-fA-F]+)*'", callback = |lex| parse_char(lex).unwrap(), priority = 2)]
@@ -82,7 +82,10 @@ fn parse_char(lex: &Lexer<Token>) -> Option<char> {
     }
 }
 
-fn parse_number(lex: &Lexer<Token>, skip: usize) -> Option<i64> {
+// numbers are parsed as 128-bit so that they can fill up the widest words, the ones between
+// `i128::MAX` and `u128::MAX` wrap around like they would on the machine and anything wider than
+// 128 bits is a lexer error
+fn parse_number(lex: &Lexer<Token>, skip: usize) -> Option<i128> {
     let number = lex.slice();
     let mut i  = skip;
     let mut neg = false;
@@ -98,9 +101,9 @@ fn parse_number(lex: &Lexer<Token>, skip: usize) -> Option<i64> {
         _ => (10, 0)
     };
     i += skip;
-    let number = i64::from_str_radix(&number[i..], radix).ok();
+    let number = u128::from_str_radix(&number[i..], radix).ok().map(|v| v as i128);
     match (number, neg) {
-        (Some(v), true) => Some(-v),
+        (Some(v), true) => Some(v.wrapping_neg()),
         _ => number
     }
 }
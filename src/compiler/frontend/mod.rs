@@ -2,4 +2,4 @@ pub mod lexer;
 pub mod parser;
 pub mod ast;
 
-// pub mod interpreter;
+pub mod interpreter;
@@ -10,7 +10,7 @@ pub fn parse(parser: &mut Parser) -> Result<(), Vec<Error<ParserError>>> {
     let mut errors = Vec::new();
 
     let mut name  = HashMap::<String, Any>::new();
-    let mut label = (HashMap::<String, usize>::new(), 0, HashMap::<usize, u64>::new());
+    let mut label = (HashMap::<String, usize>::new(), 0, HashMap::<usize, u128>::new());
     let mut replace_labels = Vec::<(usize, Immediate, Span)>::new();
 
     macro_rules! give_id {
@@ -31,8 +31,8 @@ pub fn parse(parser: &mut Parser) -> Result<(), Vec<Error<ParserError>>> {
                 Token::Name(name)       => Some(Any::Name(name.clone())),
                 Token::Label(lb)        => Some(Any::UnresolvedLabel(give_id!(lb.clone()))),
                 Token::Register(rth)    => Some(Any::Register(rth as usize)),
-                Token::Number(num)      => Some(Any::Immediate(Box::new(num as u64))),
-                Token::Char(chr)        => Some(Any::Immediate(Box::new(chr as u64))),
+                Token::Number(num)      => Some(Any::Immediate(Box::new(num as u128))),
+                Token::Char(chr)        => Some(Any::Immediate(Box::new(chr as u128))),
                 _ => None,
             }
         };
@@ -139,14 +139,22 @@ pub fn parse(parser: &mut Parser) -> Result<(), Vec<Error<ParserError>>> {
 
                     name.insert(k, v);
                 },
-                "bits"      => parser.ast.bits      = get_imm!('main_loop: some_or_error!('main_loop: parser.next().cloned(), UnexpectedEof in span)) as usize,
+                "bits"      => {
+                    let tok = some_or_error!('main_loop: parser.next().cloned(), UnexpectedEof in span);
+                    let bits = get_imm!('main_loop: tok.clone());
+                    if !(1..=128).contains(&bits) {
+                        error!('main_loop: UnsupportedBits in tok.1);
+                    }
+
+                    parser.ast.bits = bits as usize;
+                },
                 "minheap"   => parser.ast.minheap   = get_imm!('main_loop: some_or_error!('main_loop: parser.next().cloned(), UnexpectedEof in span)) as usize,
                 "minstack"  => parser.ast.minstack  = get_imm!('main_loop: some_or_error!('main_loop: parser.next().cloned(), UnexpectedEof in span)) as usize,
                 "minreg"    => parser.ast.minreg    = get_imm!('main_loop: some_or_error!('main_loop: parser.next().cloned(), UnexpectedEof in span)) as usize,
                 _ => error!('main_loop: UnknownMacro in span),
             },
             Token::Dw => {
-                let word = get_imm!('main_loop: some_or_error!('main_loop: parser.next().cloned(), UnexpectedEof in span)) as u64;
+                let word = get_imm!('main_loop: some_or_error!('main_loop: parser.next().cloned(), UnexpectedEof in span)) as u128;
                 parser.ast.dw.push(word);
             },
             Token::Newline => {
@@ -160,7 +168,7 @@ pub fn parse(parser: &mut Parser) -> Result<(), Vec<Error<ParserError>>> {
                         let a = args.remove(0);
                         if let Any::UnresolvedLabel(id) = a.0 {
                             let imm = Box::new(69);
-                            let imm_clone = unsafe { std::ptr::read::<Box<u64>>(&imm as *const Immediate) };
+                            let imm_clone = unsafe { std::ptr::read::<Immediate>(&imm as *const Immediate) };
                             replace_labels.push((id, imm_clone, span.clone()));
                             Any::Immediate(imm)
                         } else if let Any::Name(id) = a.0 {
@@ -206,7 +214,7 @@ pub fn parse(parser: &mut Parser) -> Result<(), Vec<Error<ParserError>>> {
                             "" => {
                                 match args.get(0) {
                                     Some((Any::UnresolvedLabel(l), _)) => {
-                                        label.2.insert(*l, parser.ast.instructions.len() as u64);
+                                        label.2.insert(*l, parser.ast.instructions.len() as u128);
                                     },
                                     None => {},
                                     Some((_, s)) => error!('main_loop: SyntaxError in s.clone()),
@@ -229,6 +237,46 @@ pub fn parse(parser: &mut Parser) -> Result<(), Vec<Error<ParserError>>> {
                     IMM Register Any,
 
                     MOV Register Any,
+                    SUB Register Any Any,
+                    JMP Any,
+                    NOP,
+                    LSH Register Any,
+                    INC Register Any,
+                    DEC Register Any,
+                    NEG Register Any,
+                    AND Register Any Any,
+                    OR Register Any Any,
+                    NOT Register Any,
+                    XOR Register Any Any,
+                    XNOR Register Any Any,
+                    NAND Register Any Any,
+                    BRL Any Any Any,
+                    BRG Any Any Any,
+                    BRE Any Any Any,
+                    BNE Any Any Any,
+                    BLE Any Any Any,
+                    BOD Any Any,
+                    BEV Any Any,
+                    BRZ Any Any,
+                    BNZ Any Any,
+                    BRN Any Any,
+                    BRP Any Any,
+                    CPY Any Any,
+                    HLT,
+
+                    MLT Register Any Any,
+                    DIV Register Any Any,
+                    MOD Register Any Any,
+                    BSL Register Any Any,
+                    BSR Register Any Any,
+                    SETE Register Any Any,
+                    SETNE Register Any Any,
+                    SETG Register Any Any,
+                    SETL Register Any Any,
+                    SETGE Register Any Any,
+                    SETLE Register Any Any,
+                    LLOD Register Any Any,
+                    LSTR Any Any Any,
 
                     IN Register Any,
                     OUT Any Any,
@@ -243,6 +291,11 @@ pub fn parse(parser: &mut Parser) -> Result<(), Vec<Error<ParserError>>> {
         std::mem::forget(ptr);
     }
 
+    parser.ast.labels = label.0.into_iter()
+        .filter_map(|(name, id)| label.2.get(&id).map(|pc| (name, *pc as usize)))
+        .collect();
+    parser.ast.labels.sort_unstable_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+
     if errors.is_empty() {
         Ok(())
     } else {
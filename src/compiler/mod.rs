@@ -2,3 +2,5 @@ pub mod frontend;
 pub mod backend;
 pub mod error;
 pub mod common;
+pub mod engine;
+pub mod dot;
@@ -42,10 +42,107 @@ pub fn init_panic_hook() {
     console_error_panic_hook::set_once();
 }
 
+#[cfg(target_arch = "wasm32")]
+#[derive(Clone, Default)]
+struct SharedBuffer(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+#[cfg(target_arch = "wasm32")]
+impl std::io::Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn to_text(segments: Vec<FormatSegment>) -> String {
+    segments.into_iter().map(|s| s.text).collect()
+}
+
+/// Parses `src`, giving the errors as text if it doesn't
+#[cfg(target_arch = "wasm32")]
+fn parse_src(src: &str) -> Result<compiler::frontend::ast::Ast, String> {
+    use compiler::{frontend::{lexer::*, ast::*, parser::*}, error::*};
+
+    let mut lex = Token::lexer(src);
+    let mut parser = Parser::new(&mut lex).map_err(|errors| to_text(errors_to_formats(errors, src)))?;
+    parse(&mut parser).map_err(|errors| to_text(errors_to_formats(errors, src)))?;
+    Ok(parser.ast)
+}
+
+/// Runs `src` on the engine named `engine` at optimisation level `opt` and returns everything it
+/// printed, or the errors that stopped it.
 #[cfg(target_arch = "wasm32")]
 #[wasm_bindgen]
-pub fn test(src: &str) {
-    todo!()
+pub fn test(src: &str, engine: &str, opt: &str) -> String {
+    use compiler::{backend::opt::*, engine::*, error::*};
+
+    let kind = match engine.parse::<EngineKind>() {
+        Ok(kind) => kind,
+        Err(err) => return err,
+    };
+    let level = match opt.parse::<OptLevel>() {
+        Ok(level) => level,
+        Err(err) => return err,
+    };
+    let ast = match parse_src(src) {
+        Ok(ast) => ast,
+        Err(err) => return err,
+    };
+
+    let stdout = SharedBuffer::default();
+    let mut engine = create_engine(kind, &mut PassManager::new(level), ast);
+    engine.attach_device(Box::new(StdioDevice { stdout: stdout.clone(), stdin: std::io::empty() }));
+
+    let step = loop {
+        match engine.run(0x10000) {
+            StepResult::Running => {},
+            other => break other,
+        }
+    };
+
+    let mut out = String::from_utf8_lossy(&stdout.0.borrow()).into_owned();
+    if let StepResult::Error(err) = step {
+        out.push('\n');
+        out.push_str(&to_text(errors_to_formats(vec![err], src)));
+    }
+
+    out
+}
+
+/// Compiles `src` at optimisation level `opt` into a WebAssembly module for the page to
+/// instantiate, see [`compiler::backend::arch::wasm`] for what it imports.
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+pub fn compile(src: &str, opt: &str) -> Result<Vec<u8>, String> {
+    use compiler::backend::{opt::*, codegen::*, builder::*, arch::wasm::*};
+
+    let level = opt.parse::<OptLevel>()?;
+    let ast = parse_src(src)?;
+    if ast.bits > 64 {
+        return Err("WebAssembly modules only support words of up to 64 bits".to_string());
+    }
+
+    let mut builder = Builder::from_ssa(generate_ssa(ast));
+    PassManager::new(level).run(&mut builder);
+    Ok(to_wasm(&builder.get_ssa()))
+}
+
+/// Formats an error that a module from [`compile`] reported, the same way [`test`] does
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+pub fn describe_error(src: &str, kind: u64, pc: i64, detail: u64) -> String {
+    use compiler::error::*;
+
+    let span = parse_src(src).ok()
+        .and_then(|ast| ast.instructions.get(usize::try_from(pc).ok()?).map(|(_, span)| span.clone()))
+        .unwrap_or_default();
+    let err = Error { kind: InterpreterError::from_code(kind as u128, detail as u128), span };
+    to_text(errors_to_formats(vec![err], src))
 }
 
 static mut RAND_SEED: u64 = 0;
@@ -13,14 +13,77 @@
 
 use urcl_io::compiler::{
     frontend::{lexer::*, ast::*, parser::*},
-    backend::{codegen::*, arch::interpreter::*},
+    backend::{opt::*, codegen::*, builder::*, arch::{x86_64::elf::to_elf, c::to_c, wasm::to_wasm, llvm::to_llvm, urcl::{to_urcl, Origin}}},
+    engine::*,
     error::*,
+    dot::*,
 };
 use std::{time::*, io::*};
 use thousands::Separable;
 
 fn main() {
-    let src = std::fs::read_to_string("test.urcl").unwrap();
+    let mut file = "test.urcl".to_string();
+    let mut engine = EngineKind::default();
+    let mut level = OptLevel::default();
+    let mut dump_after = Vec::new();
+    let mut time_passes = false;
+    let mut output = None;
+
+    fn or_exit<T>(res: std::result::Result<T, String>) -> T {
+        res.unwrap_or_else(|err| {
+            eprintln!("\x1b[1;31mError:\x1b[0m {err}");
+            std::process::exit(1);
+        })
+    }
+
+    // prints the IR dumps asked for with `--dump-after`, and the pass timings with `--time-passes`
+    fn report_passes(passes: &PassManager, time_passes: bool) {
+        for (name, ir) in passes.dumps.iter() {
+            eprintln!("\x1b[1;32mIR after {name}:\x1b[0m\n{ir}");
+        }
+
+        if time_passes {
+            for (name, time) in passes.timings.iter() {
+                eprintln!("\x1b[1;32mPass {name}:\x1b[0m {}s", time.separate_with_commas());
+            }
+        }
+    }
+
+    let mut args = std::env::args().skip(1).peekable();
+
+    // `emit <ir|dot|source-dot|elf|c|wasm|llvm|urcl|core>` prints the program in another form instead of running it
+    let emit = if args.peek().is_some_and(|a| a == "emit") {
+        args.next();
+        let what = args.next().unwrap_or_default();
+        if !matches!(what.as_str(), "ir" | "dot" | "source-dot" | "elf" | "c" | "wasm" | "llvm" | "urcl" | "core") {
+            or_exit::<()>(Err(format!("unknown output `{what}`, expected one of: ir, dot, source-dot, elf, c, wasm, llvm, urcl, core")));
+        }
+
+        Some(what)
+    } else {
+        None
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--engine" | "-e" => engine = or_exit(args.next().unwrap_or_default().parse()),
+            "--opt-level" => level = or_exit(args.next().unwrap_or_default().parse()),
+            "-O0" | "-O1" | "-O2" | "-O3" => level = or_exit(arg[1..].parse()),
+            "--dump-after" => {
+                let name = args.next().unwrap_or_default();
+                if !matches!(name.as_str(), "all" | "codegen") && pass(&name).is_none() {
+                    or_exit::<()>(Err(format!("unknown pass `{name}`")));
+                }
+
+                dump_after.push(name);
+            },
+            "--time-passes" => time_passes = true,
+            "--output" | "-o" => output = args.next(),
+            _ => file = arg,
+        }
+    }
+
+    let src = std::fs::read_to_string(file).unwrap();
 
     let mut lex = Token::lexer(&src);
     let mut parser = match Parser::new(&mut lex) {
@@ -45,48 +108,116 @@ fn main() {
             std::process::exit(2);
         },
     }
-    eprintln!("{:#?}", parser.ast);
 
-    let ssa = generate_ssa(parser.ast);
+    let mut passes = PassManager::new(level);
+    for name in dump_after {
+        passes.dump_after(name);
+    }
 
-    eprintln!("{}", ssa.0);
+    if let Some(what) = emit {
+        // goes to `--output` if there is one, and stdout otherwise
+        let write = |bytes: &[u8]| match &output {
+            Some(path) => or_exit(std::fs::write(path, bytes).map_err(|err| format!("couldn't write `{path}`: {err}"))),
+            None => stdout().write_all(bytes).unwrap(),
+        };
 
-    let mut interpreter = Interpreter::new(ssa);
+        if what == "source-dot" {
+            write(source_to_dot(&parser.ast, &src).as_bytes());
+            std::process::exit(0);
+        }
 
-    let start_int = Instant::now();
+        if what == "core" {
+            parser.ast.lower_to_core();
+            write(parser.ast.to_string().as_bytes());
+            std::process::exit(0);
+        }
 
-    let mut stdout = BufWriter::with_capacity(16 * 0x20, stdout());
-    let mut stdin  = stdin();
-
-    loop {
-        let step = interpreter.step(&mut stdout, &mut stdin);
-        // if interpreter.debugging {
-            // println!("{interpreter:#?}");
-        // }
-        match step {
-            StepResult::Halted => {
-                let _ = stdout.flush();
-
-                let duration = start_int.elapsed().as_secs_f64();
-                eprintln!(
-                    "\x1b[1;32mInterpreter:\x1b[0m program halted (ran for {}s / {}Hz / {} cycles)",
-                    (duration).separate_with_commas(),
-                    (interpreter.inst_count as f64 / duration).separate_with_commas(),
-                    interpreter.inst_count.separate_with_commas(),
-                );
-                std::process::exit(0);
+        if what == "elf" && parser.ast.bits > 64 {
+            or_exit::<()>(Err("ELF executables only support words of up to 64 bits".to_string()));
+        }
+
+        if what == "c" && parser.ast.bits > 64 {
+            or_exit::<()>(Err("C code only supports words of up to 64 bits".to_string()));
+        }
+
+        if what == "wasm" && parser.ast.bits > 64 {
+            or_exit::<()>(Err("WebAssembly modules only support words of up to 64 bits".to_string()));
+        }
+
+        let (labels, minreg) = (parser.ast.labels.clone(), parser.ast.minreg);
+        let mut builder = Builder::from_ssa(generate_ssa(parser.ast));
+        passes.run(&mut builder);
+        report_passes(&passes, time_passes);
+
+        match what.as_str() {
+            "ir" => write(builder.body().to_string().as_bytes()),
+            "dot" => write(ssa_to_dot(builder.body()).as_bytes()),
+            "c" => write(to_c(&builder.get_ssa()).as_bytes()),
+            "wasm" => write(&to_wasm(&builder.get_ssa())),
+            "llvm" => write(to_llvm(&builder.get_ssa()).as_bytes()),
+            "urcl" => match to_urcl(builder.get_ssa(), &Origin { source: &src, labels: &labels, minreg }) {
+                Ok(urcl) => write(urcl.as_bytes()),
+                Err(err) => {
+                    for s in errors_to_formats(vec![err], &src) {
+                        eprint!("{}", s.to_ansi());
+                    }
+
+                    std::process::exit(1);
+                },
             },
-            StepResult::Error(err) => {
-                let _ = stdout.flush();
+            _ => {
+                write(&to_elf(&builder.get_ssa()));
 
-                let segments = errors_to_formats(vec![err], &src);
-                for s in segments {
-                    eprint!("{}", s.to_ansi());
+                #[cfg(unix)]
+                if let Some(path) = &output {
+                    use std::os::unix::fs::PermissionsExt;
+                    or_exit(std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).map_err(|err| format!("couldn't make `{path}` executable: {err}")));
                 }
+            },
+        }
 
-                std::process::exit(3);
-            }
+        std::process::exit(0);
+    }
+
+    let mut engine = create_engine(engine, &mut passes, parser.ast);
+    report_passes(&passes, time_passes);
+
+    engine.attach_device(Box::new(StdioDevice {
+        stdout: BufWriter::with_capacity(16 * 0x20, stdout()),
+        stdin: stdin(),
+    }));
+
+    let start_int = Instant::now();
+
+    let step = loop {
+        match engine.run(usize::MAX) {
             StepResult::Running => {},
+            other => break other,
         }
+    };
+    let inst_count = engine.stats().inst_count;
+    // flushes the output
+    drop(engine);
+
+    match step {
+        StepResult::Halted => {
+            let duration = start_int.elapsed().as_secs_f64();
+            eprintln!(
+                "\x1b[1;32mInterpreter:\x1b[0m program halted (ran for {}s / {}Hz / {} cycles)",
+                (duration).separate_with_commas(),
+                (inst_count as f64 / duration).separate_with_commas(),
+                inst_count.separate_with_commas(),
+            );
+            std::process::exit(0);
+        },
+        StepResult::Error(err) => {
+            let segments = errors_to_formats(vec![err], &src);
+            for s in segments {
+                eprint!("{}", s.to_ansi());
+            }
+
+            std::process::exit(3);
+        },
+        StepResult::Running => unreachable!(),
     }
 }
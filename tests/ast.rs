@@ -0,0 +1,22 @@
+mod common;
+
+use common::*;
+use urcl_io::compiler::{backend::opt::OptLevel, common::Instruction, engine::EngineKind};
+
+#[test]
+fn core_programs_do_what_the_source_does() {
+    for (name, src, input) in PROGRAMS {
+        let expected = run(EngineKind::Ast, OptLevel::O0, src, input);
+
+        let mut ast = parse_urcl(src);
+        ast.lower_to_core();
+        let core = ast.to_string();
+
+        let lowered = parse_urcl(&core);
+        assert!(lowered.instructions.iter().all(|(inst, _)| matches!(inst,
+            Instruction::ADD(..) | Instruction::RSH(..) | Instruction::LOD(..) | Instruction::STR(..) | Instruction::BGE(..)
+                | Instruction::NOR(..) | Instruction::IN(..) | Instruction::OUT(..)
+        )), "{name}:\n{core}");
+        assert_eq!(run(EngineKind::Ast, OptLevel::O0, &core, input), expected, "{name}:\n{core}");
+    }
+}
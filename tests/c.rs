@@ -0,0 +1,89 @@
+mod common;
+
+use common::*;
+use urcl_io::compiler::{
+    backend::{arch::{c::to_c, interpreter::Interpreter}, builder::Builder, codegen::generate_ssa, ir::parse_ir, opt::*},
+    engine::EngineKind,
+    error::InterpreterError,
+};
+use std::{io::Write, path::PathBuf, process::{Command, Stdio}};
+
+/// Builds the C code with `cc -std=c99` and runs it, giving `None` when there is no compiler
+fn run_c(name: &str, code: &str, input: &[u8]) -> Option<(String, Option<i32>)> {
+    let dir = std::env::temp_dir().join(format!("urcl-c-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let stem = name.replace(' ', "_");
+    let (source, binary): (PathBuf, PathBuf) = (dir.join(format!("{stem}.c")), dir.join(&stem));
+    std::fs::write(&source, code).unwrap();
+
+    let cc = Command::new("cc").arg("-std=c99").arg("-o").arg(&binary).arg(&source).output().ok()?;
+    assert!(cc.status.success(), "{name} doesn't compile:\n{}\n{code}", String::from_utf8_lossy(&cc.stderr));
+
+    let mut child = Command::new(&binary)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    let out = child.wait_with_output().unwrap();
+    std::fs::remove_file(&source).unwrap();
+    std::fs::remove_file(&binary).unwrap();
+
+    Some((String::from_utf8_lossy(&out.stdout).into_owned(), out.status.code()))
+}
+
+/// Exit code of the compiled program for what an engine ended with
+fn exit_code(outcome: &Outcome) -> Option<i32> {
+    Some(if outcome.error.is_some() { 3 } else { 0 })
+}
+
+#[test]
+fn c_matches_the_ast_interpreter() {
+    for (name, src, input) in PROGRAMS {
+        let expected = run(EngineKind::Ast, OptLevel::O0, src, input);
+
+        for level in [OptLevel::O0, OptLevel::O2] {
+            let mut builder = Builder::from_ssa(generate_ssa(parse_urcl(src)));
+            PassManager::new(level).run(&mut builder);
+
+            let Some((stdout, code)) = run_c(&format!("{name} {level:?}"), &to_c(&builder.get_ssa()), input) else {
+                eprintln!("skipped, there is no C compiler");
+                return;
+            };
+
+            assert_eq!(stdout, expected.stdout, "{name} at {level:?}");
+            assert_eq!(code, exit_code(&expected), "{name} at {level:?}");
+        }
+    }
+}
+
+#[test]
+fn c_reports_division_by_zero() {
+    // starts out as IR so that the division traps by itself, without the check `DIV` comes with
+    let src = "
+        bits 16
+        $0: // entry
+            %0 = 1
+            %1 = call PortRead(%0)
+            %2 = 2
+            %3 = 700
+            %4 = div %3 %1
+            call PortWrite(%2, %4)
+            ret
+    ";
+
+    for (input, stdout, error) in [(&b"\x07"[..], "100", None), (b"", "", Some(InterpreterError::DivisionByZero.code()))] {
+        let ssa = parse_ir(src).unwrap();
+        let expected = run_engine(Box::new(Interpreter::<u64>::new(parse_ir(src).unwrap())), input);
+        assert_eq!((expected.stdout.as_str(), expected.error), (stdout, error));
+
+        let Some((out, code)) = run_c("division", &to_c(&ssa), input) else {
+            eprintln!("skipped, there is no C compiler");
+            return;
+        };
+
+        assert_eq!(out, expected.stdout, "{input:?}");
+        assert_eq!(code, exit_code(&expected), "{input:?}");
+    }
+}
@@ -0,0 +1,17 @@
+mod common;
+
+use common::*;
+use urcl_io::compiler::backend::{ssa::*, codegen::generate_ssa};
+
+#[test]
+fn port_reads_are_truncated_in_the_ir() {
+    let (body, _, _) = generate_ssa(parse_urcl("bits 4\nin r1 1\n"));
+    let instructions = || body.blocks.iter().flat_map(|b| b.instructions.iter());
+
+    let read = instructions()
+        .find(|i| matches!(i.operation, Operation::Call(Function::PortRead, _)))
+        .and_then(|i| i.destination)
+        .unwrap();
+
+    assert!(instructions().any(|i| matches!(i.operation, Operation::BinOp(BinOp::And, v, _) if v == read)));
+}
@@ -0,0 +1,300 @@
+#![allow(dead_code)]
+
+use urcl_io::compiler::{
+    frontend::{ast::{Ast, Parser}, lexer::*, parser::parse},
+    backend::opt::*,
+    engine::*,
+};
+use std::{cell::RefCell, io::{Cursor, Write}, rc::Rc};
+
+pub fn parse_urcl(src: &str) -> Ast {
+    let mut lex = Token::lexer(src);
+    let mut parser = Parser::new(&mut lex).ok().expect("program doesn't lex");
+    assert!(parse(&mut parser).is_ok(), "program doesn't parse");
+    parser.ast
+}
+
+/// Output the device of an engine writes into, which stays readable after the engine is gone
+#[derive(Clone, Default)]
+pub struct Buffer(pub Rc<RefCell<Vec<u8>>>);
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// What a program printed, and the code of the error it stopped with if it didn't halt
+#[derive(Debug, PartialEq, Eq)]
+pub struct Outcome {
+    pub stdout: String,
+    pub error: Option<u128>,
+}
+
+/// Runs `src` to the end on engine `kind` at optimisation level `level`, with `input` as stdin
+pub fn run(kind: EngineKind, level: OptLevel, src: &str, input: &[u8]) -> Outcome {
+    run_engine(create_engine(kind, &mut PassManager::new(level), parse_urcl(src)), input)
+}
+
+/// Runs `engine` to the end with `input` as stdin
+pub fn run_engine(mut engine: Box<dyn Engine>, input: &[u8]) -> Outcome {
+    let stdout = Buffer::default();
+    engine.attach_device(Box::new(StdioDevice { stdout: stdout.clone(), stdin: Cursor::new(input.to_vec()) }));
+
+    let error = loop {
+        match engine.run(usize::MAX) {
+            StepResult::Running => {},
+            StepResult::Halted => break None,
+            StepResult::Error(err) => break Some(err.kind.code()),
+        }
+    };
+
+    drop(engine);
+    let stdout = String::from_utf8_lossy(&stdout.0.borrow()).into_owned();
+    Outcome { stdout, error }
+}
+
+/// Programs covering every instruction, along with the input they get
+pub const PROGRAMS: &[(&str, &str, &[u8])] = &[
+    ("echo", "
+        bits 8
+    .loop
+        in r1 1
+        bge .end 1 r1
+        out 1 r1
+        bge .loop 0 0
+    .end
+    ", b"hello\n"),
+    ("arithmetic", "
+        bits 16
+        imm r1 40000
+        add r2 r1 r1
+        out 2 r2
+        out 1 32
+        nor r3 r1 0
+        out 2 r3
+        out 1 32
+        rsh r4 r1
+        out 2 r4
+        out 1 32
+        mov r5 r4
+        add r5 r5 65535
+        out 2 r5
+    ", b""),
+    ("memory", "
+        bits 8
+        minheap 4
+        dw 7
+        imm r1 1
+    .fill
+        add r2 r1 r1
+        str r1 r2
+        add r1 r1 1
+        bge .done r1 4
+        bge .fill 0 0
+    .done
+        lod r3 0
+        out 2 r3
+        lod r3 3
+        out 2 r3
+    ", b""),
+    ("loop", "
+        bits 32
+        imm r1 0
+        imm r2 100
+    .loop
+        add r1 r1 r2
+        add r2 r2 -1
+        bge .loop r2 1
+        out 2 r1
+    ", b""),
+    ("odd bits", "
+        bits 13
+        imm r1 8191
+        add r1 r1 5
+        out 2 r1
+        out 1 32
+        nor r2 r1 r1
+        out 2 r2
+    ", b""),
+    ("basic", "
+        bits 16
+        minheap 4
+        imm r1 1000
+        sub r2 r1 58
+        out 2 r2
+        out 1 32
+        sub r2 r2 r1
+        out 2 r2
+        out 1 32
+        jmp .skip
+        out 2 9
+    .skip
+        nop
+        lsh r3 r1
+        inc r3 r3
+        out 2 r3
+        out 1 32
+        dec r4 r0
+        out 2 r4
+        out 1 32
+        neg r5 r3
+        out 2 r5
+        out 1 32
+        and r6 r3 r5
+        out 2 r6
+        out 1 32
+        and r5 r3 r5
+        out 2 r5
+        out 1 32
+        or r6 r3 0xff00
+        out 2 r6
+        out 1 32
+        not r6 r6
+        out 2 r6
+        out 1 32
+        xor r6 r6 r3
+        out 2 r6
+        out 1 32
+        xnor r6 r6 r1
+        out 2 r6
+        out 1 32
+        nand r6 r6 r3
+        out 2 r6
+        out 1 32
+        str 1 r6
+        cpy 2 1
+        lod r7 2
+        out 2 r7
+        hlt
+        out 2 9
+    ", b""),
+    ("branches", "
+        bits 8
+        dw 0
+        dw 1
+        dw 2
+        dw 127
+        dw 128
+        dw 200
+        dw 255
+        imm r1 0
+    .next
+        lod r2 r1
+        brl .a r2 2
+        out 1 97
+    .a
+        brg .b r2 127
+        out 1 98
+    .b
+        bre .c 200 r2
+        out 1 99
+    .c
+        bne .d r2 1
+        out 1 100
+    .d
+        ble .e r2 2
+        out 1 101
+    .e
+        bod .f r2
+        out 1 102
+    .f
+        bev .g r2
+        out 1 103
+    .g
+        brz .h r2
+        out 1 104
+    .h
+        bnz .i r2
+        out 1 105
+    .i
+        brn .j r2
+        out 1 106
+    .j
+        brp .k r2
+        out 1 107
+    .k
+        out 1 32
+        inc r1 r1
+        brl .next r1 7
+    ", b""),
+    ("complex", "
+        bits 16
+        minheap 8
+        imm r1 300
+        mlt r2 r1 250
+        out 2 r2
+        out 1 32
+        mlt r3 r1 r1
+        out 2 r3
+        out 1 32
+        div r4 r3 7
+        out 2 r4
+        out 1 32
+        mod r5 r3 7
+        out 2 r5
+        out 1 32
+        div r6 65535 r1
+        out 2 r6
+        out 1 32
+        mod r6 65535 r1
+        out 2 r6
+        out 1 32
+        div r7 65535 40000
+        out 2 r7
+        out 1 32
+        mod r7 65535 40000
+        out 2 r7
+        out 1 32
+        div r3 r3 r1
+        out 2 r3
+        out 1 32
+        imm r8 3
+        bsl r2 r1 r8
+        out 2 r2
+        out 1 32
+        bsl r2 r1 16
+        out 2 r2
+        out 1 32
+        bsr r2 r1 r8
+        out 2 r2
+        out 1 32
+        bsr r2 r1 20
+        out 2 r2
+        out 1 32
+        sete r2 r1 300
+        out 2 r2
+        setne r2 r1 300
+        out 2 r2
+        setg r2 r1 299
+        out 2 r2
+        setl r2 r1 299
+        out 2 r2
+        setge r2 r1 301
+        out 2 r2
+        setle r2 r1 300
+        out 2 r2
+        out 1 32
+        lstr 2 3 r1
+        llod r2 1 4
+        out 2 r2
+    ", b""),
+    ("out of bounds", "
+        bits 8
+        minheap 2
+        minstack 0
+        out 2 1
+        str 200 5
+        out 2 2
+    ", b""),
+    ("unsupported port", "
+        bits 8
+        out 1 65
+        out 9 1
+    ", b""),
+];
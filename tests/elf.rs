@@ -0,0 +1,84 @@
+#![cfg(all(target_arch = "x86_64", target_os = "linux"))]
+
+mod common;
+
+use common::*;
+use urcl_io::compiler::{
+    backend::{arch::{interpreter::Interpreter, x86_64::elf::to_elf}, builder::Builder, codegen::generate_ssa, ir::parse_ir, opt::*},
+    engine::EngineKind,
+    error::InterpreterError,
+};
+use std::{io::Write, os::unix::fs::PermissionsExt, process::{Command, Stdio}, sync::Mutex};
+
+/// Held while an executable is written and started, as a file another test has just forked with
+/// open can't be run
+static SPAWN: Mutex<()> = Mutex::new(());
+
+/// Writes the executable out and runs it
+fn run_elf(name: &str, elf: &[u8], input: &[u8]) -> (String, Option<i32>) {
+    let dir = std::env::temp_dir().join(format!("urcl-elf-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let binary = dir.join(name.replace(' ', "_"));
+    let spawning = SPAWN.lock().unwrap_or_else(|e| e.into_inner());
+    std::fs::write(&binary, elf).unwrap();
+    std::fs::set_permissions(&binary, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let mut child = Command::new(&binary)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    drop(spawning);
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    let out = child.wait_with_output().unwrap();
+    std::fs::remove_file(&binary).unwrap();
+
+    (String::from_utf8_lossy(&out.stdout).into_owned(), out.status.code())
+}
+
+/// Exit code of the executable for what an engine ended with
+fn exit_code(outcome: &Outcome) -> Option<i32> {
+    Some(if outcome.error.is_some() { 3 } else { 0 })
+}
+
+#[test]
+fn elf_matches_the_ast_interpreter() {
+    for (name, src, input) in PROGRAMS {
+        let expected = run(EngineKind::Ast, OptLevel::O0, src, input);
+
+        for level in [OptLevel::O0, OptLevel::O2] {
+            let mut builder = Builder::from_ssa(generate_ssa(parse_urcl(src)));
+            PassManager::new(level).run(&mut builder);
+
+            let (stdout, code) = run_elf(&format!("{name} {level:?}"), &to_elf(&builder.get_ssa()), input);
+            assert_eq!(stdout, expected.stdout, "{name} at {level:?}");
+            assert_eq!(code, exit_code(&expected), "{name} at {level:?}");
+        }
+    }
+}
+
+#[test]
+fn elf_reports_division_by_zero() {
+    // starts out as IR so that the division traps by itself, without the check `DIV` comes with
+    let src = "
+        bits 16
+        $0: // entry
+            %0 = 1
+            %1 = call PortRead(%0)
+            %2 = 2
+            %3 = 700
+            %4 = div %3 %1
+            call PortWrite(%2, %4)
+            ret
+    ";
+
+    for (input, stdout, error) in [(&b"\x07"[..], "100", None), (b"", "", Some(InterpreterError::DivisionByZero.code()))] {
+        let expected = run_engine(Box::new(Interpreter::<u64>::new(parse_ir(src).unwrap())), input);
+        assert_eq!((expected.stdout.as_str(), expected.error), (stdout, error));
+
+        let (got, code) = run_elf(&format!("division {}", input.len()), &to_elf(&parse_ir(src).unwrap()), input);
+        assert_eq!(got, expected.stdout);
+        assert_eq!(code, exit_code(&expected));
+    }
+}
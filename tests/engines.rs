@@ -0,0 +1,123 @@
+mod common;
+
+use common::*;
+use urcl_io::compiler::{backend::opt::{OptLevel, PassManager}, engine::*, error::InterpreterError};
+
+#[test]
+fn text_input_reads_zero_at_the_end() {
+    let src = "
+        in r1 1
+        in r2 1
+        out 2 r1
+        out 1 32
+        out 2 r2
+    ";
+
+    for kind in [EngineKind::Ast, EngineKind::Ssa, EngineKind::Vm, EngineKind::Threaded, EngineKind::Jit] {
+        assert_eq!(run(kind, OptLevel::default(), src, b"A").stdout, "65 0", "{kind:?}");
+    }
+}
+
+#[test]
+fn text_input_is_truncated_to_the_word() {
+    let src = "
+        bits 4
+        in r1 1
+        out 2 r1
+    ";
+
+    for kind in [EngineKind::Ast, EngineKind::Ssa, EngineKind::Vm, EngineKind::Threaded, EngineKind::Jit] {
+        for level in [OptLevel::O0, OptLevel::O2] {
+            assert_eq!(run(kind, level, src, b"A").stdout, "1", "{kind:?} at {level:?}");
+        }
+    }
+}
+
+#[test]
+fn state_is_observable_unless_optimised_away() {
+    let src = "
+        minreg 2
+        minheap 4
+        imm r1 5
+        str 3 r1
+    ";
+
+    for kind in [EngineKind::Ast, EngineKind::Ssa, EngineKind::Vm, EngineKind::Threaded, EngineKind::Jit] {
+        let mut engine = create_engine(kind, &mut PassManager::new(OptLevel::O0), parse_urcl(src));
+        assert!(matches!(engine.run(usize::MAX), StepResult::Halted), "{kind:?}");
+        assert_eq!(engine.register(1).ok(), Some(5), "{kind:?}");
+        assert_eq!(engine.memory(3).ok(), Some(5), "{kind:?}");
+        assert!(matches!(engine.register(9), Err(InterpreterError::RegisterOob(9))), "{kind:?}");
+
+        engine.set_register(2, 7).unwrap();
+        assert_eq!(engine.register(2).ok(), Some(7), "{kind:?}");
+    }
+
+    for kind in [EngineKind::Ssa, EngineKind::Vm, EngineKind::Threaded, EngineKind::Jit] {
+        let mut engine = create_engine(kind, &mut PassManager::new(OptLevel::O2), parse_urcl(src));
+        assert!(matches!(engine.run(usize::MAX), StepResult::Halted), "{kind:?}");
+        assert!(matches!(engine.register(1), Err(InterpreterError::OptimisedAway)), "{kind:?}");
+        assert!(matches!(engine.set_register(1, 0), Err(InterpreterError::OptimisedAway)), "{kind:?}");
+        assert!(matches!(engine.memory(3), Err(InterpreterError::OptimisedAway)), "{kind:?}");
+    }
+}
+
+/// Output that has been closed on the other end
+struct Closed;
+
+impl std::io::Write for Closed {
+    fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+        Err(std::io::ErrorKind::BrokenPipe.into())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn closed_output_stops_the_program() {
+    for kind in [EngineKind::Ast, EngineKind::Ssa, EngineKind::Vm, EngineKind::Threaded, EngineKind::Jit] {
+        for level in [OptLevel::O0, OptLevel::O2] {
+            let mut engine = create_engine(kind, &mut PassManager::new(level), parse_urcl("out 1 65"));
+            engine.attach_device(Box::new(StdioDevice { stdout: Closed, stdin: std::io::empty() }));
+            // the SSA IR only gets to know that the port access didn't go through
+            match engine.run(usize::MAX) {
+                StepResult::Error(err) if kind == EngineKind::Ast => assert!(matches!(err.kind, InterpreterError::PortFailed(1)), "{:?}", err.kind),
+                StepResult::Error(err) => assert!(matches!(err.kind, InterpreterError::UnsupportedPort(1)), "{kind:?} at {level:?}: {:?}", err.kind),
+                other => panic!("{kind:?} at {level:?} ended with {other:?}"),
+            }
+        }
+    }
+}
+
+#[test]
+fn budgets_are_kept() {
+    for (name, src, input) in PROGRAMS {
+        let expected = run(EngineKind::Ast, OptLevel::O0, src, input);
+
+        for kind in [EngineKind::Ast, EngineKind::Ssa, EngineKind::Vm, EngineKind::Threaded, EngineKind::Jit] {
+            for level in [OptLevel::O0, OptLevel::O2] {
+                let stdout = Buffer::default();
+                let mut engine = create_engine(kind, &mut PassManager::new(level), parse_urcl(src));
+                engine.attach_device(Box::new(StdioDevice { stdout: stdout.clone(), stdin: std::io::Cursor::new(input.to_vec()) }));
+
+                let error = loop {
+                    let before = engine.stats().inst_count;
+                    let result = engine.run(7);
+                    let ran = engine.stats().inst_count - before;
+                    match result {
+                        // merged blocks are counted all at once, which may go past the budget
+                        StepResult::Running if level == OptLevel::O0 => assert_eq!(ran, 7, "{name} on {kind:?}"),
+                        StepResult::Running => assert!((7..7 + src.lines().count()).contains(&ran), "{name} on {kind:?} ran {ran}"),
+                        StepResult::Halted => break None,
+                        StepResult::Error(err) => break Some(err.kind.code()),
+                    }
+                };
+
+                let stdout = String::from_utf8_lossy(&stdout.0.borrow()).into_owned();
+                assert_eq!(Outcome { stdout, error }, expected, "{name} on {kind:?} at {level:?}");
+            }
+        }
+    }
+}
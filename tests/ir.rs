@@ -0,0 +1,249 @@
+mod common;
+
+use common::*;
+use urcl_io::compiler::{
+    backend::{builder::Builder, codegen::generate_ssa, ir::parse_ir, opt::*},
+    error::IrError,
+};
+
+#[test]
+fn printed_ir_parses_back_the_same() {
+    for (name, src, _) in PROGRAMS {
+        for level in [OptLevel::O0, OptLevel::O2] {
+            let mut builder = Builder::from_ssa(generate_ssa(parse_urcl(src)));
+            PassManager::new(level).run(&mut builder);
+            let (body, values, variables) = builder.get_ssa();
+
+            let printed = body.to_string();
+            let Ok(parsed) = parse_ir(&printed) else { panic!("{name} at {level:?} doesn't parse back:\n{printed}") };
+            assert_eq!(parsed.0.to_string(), printed, "{name} at {level:?}");
+            assert_eq!(parsed.0.spans, body.spans, "{name} at {level:?}");
+            assert!(parsed.1 <= values && parsed.2 <= variables, "{name} at {level:?}");
+        }
+    }
+}
+
+#[test]
+fn references_have_to_exist() {
+    let errors = |src: &str| -> Vec<String> {
+        parse_ir(src).err().expect("IR parsed").iter().map(|e| match &e.kind {
+            IrError::UnknownBlock(b) => format!("${b}"),
+            IrError::UndefinedValue(v) => format!("%{v}"),
+            other => panic!("unexpected {other:?}"),
+        }).collect()
+    };
+
+    assert_eq!(errors("$0: %0 = 1\n jmp $1"), ["$1"]);
+    assert_eq!(errors("$0: %0 = 1\n br %0 $0 $3"), ["$3"]);
+    assert_eq!(errors("$0: jmp $1\n$1: %1 = phi $0: %0, $2: %1\n ret"), ["%0", "$2"]);
+    assert_eq!(errors("$0: %1 = add %0 %2\n ret"), ["%0", "%2"]);
+}
+
+#[test]
+fn const_fold_folds_constant_operations() {
+    let src = "
+        bits 8
+        $0: // entry
+            %0 = 200
+            %1 = 100
+            %2 = add %0 %1
+            call PortWrite(%0, %2)
+            ret
+    ";
+
+    let mut builder = Builder::from_ssa(parse_ir(src).unwrap());
+    const_fold(&mut builder);
+    dce(&mut builder);
+    let (body, ..) = builder.get_ssa();
+    let expected = "
+        bits 8
+        $0: // entry
+            %0 = 200
+            %2 = 300
+            call PortWrite(%0, %2)
+            ret
+    ";
+
+    let lines = |s: &str| s.lines().map(str::trim).filter(|l| !l.is_empty()).collect::<Vec<_>>().join("\n");
+    assert_eq!(lines(&body.to_string()), lines(expected));
+}
+
+#[test]
+fn const_fold_merges_equal_constants_from_every_block() {
+    let src = "
+        bits 8
+        $0: // entry
+            %0 = 0
+            %1 = call PortRead(%0)
+            br %1 $1 $2
+        $1: // then
+            %2 = 7
+            jmp $3
+        $2: // else
+            %3 = 7
+            jmp $3
+        $3: // join
+            %4 = phi $1: %2, $2: %3
+            %5 = 2
+            %6 = add %4 %5
+            call PortWrite(%5, %6)
+            ret
+    ";
+
+    let mut builder = Builder::from_ssa(parse_ir(src).unwrap());
+    const_fold(&mut builder);
+    dce(&mut builder);
+    let (body, ..) = builder.get_ssa();
+    let expected = "
+        bits 8
+        $0: // entry
+            %0 = 0
+            %5 = 2
+            %6 = 9
+            %1 = call PortRead(%0)
+            br %1 $1 $2
+        $1: // then
+            jmp $3
+        $2: // else
+            jmp $3
+        $3: // join
+            call PortWrite(%5, %6)
+            ret
+    ";
+
+    let lines = |s: &str| s.lines().map(str::trim).filter(|l| !l.is_empty()).collect::<Vec<_>>().join("\n");
+    assert_eq!(lines(&body.to_string()), lines(expected));
+}
+
+#[test]
+fn simplify_cfg_keeps_the_lines_of_merged_blocks() {
+    let src = "
+        bits 8
+        $0: // inst_0 [0: 0..4]
+            %0 = 1
+            jmp $1
+        $1: // inst_1 [0: 5..9]
+            jmp $2
+        $2: // inst_2 [0: 10..14]
+            %1 = call PortRead(%0)
+            br %1 $3 $3
+        $3: // inst_3 [0: 15..19]
+            br %1 $4 $5
+        $4: // inst_4 [0: 20..24]
+            jmp $5
+        $5: // inst_5 [0: 25..29]
+            call PortWrite(%0, %1)
+            ret
+    ";
+
+    let mut builder = Builder::from_ssa(parse_ir(src).unwrap());
+    let lines = |builder: &Builder| builder.body().blocks.iter().map(|b| b.spans.len()).sum::<usize>();
+    let before = lines(&builder);
+    simplify_cfg(&mut builder);
+    assert_eq!(lines(&builder), before);
+
+    let (body, ..) = builder.get_ssa();
+    let expected = "
+        bits 8
+        $0: // inst_0 [0: 0..4, 1: 5..9, 1: 10..14, 2: 15..19]
+            %0 = 1
+            %1 = call PortRead(%0)
+            br %1 $1 $2
+        $1: // inst_4 [0: 20..24]
+            jmp $2
+        $2: // inst_5 [0: 25..29]
+            call PortWrite(%0, %1)
+            ret
+    ";
+
+    let lines = |s: &str| s.lines().map(str::trim).filter(|l| !l.is_empty()).collect::<Vec<_>>().join("\n");
+    assert_eq!(lines(&body.to_string()), lines(expected));
+}
+
+#[test]
+fn dce_reports_unreachable_lines() {
+    let src = "
+        bits 8
+        $0: // inst_0 [0: 0..4]
+            %0 = 1
+            %1 = 2
+            %2 = add %0 %1
+            %3 = div %0 %1
+            %4 = 0
+            %5 = div %0 %4
+            jmp $2
+        $1: // inst_1 [0: 12..16, 1: 5..9]
+            call PortWrite(%0, %0)
+            jmp $2
+        $2: // inst_2 [0: 17..21]
+            %6 = call LastOk()
+            ret
+    ";
+
+    let mut builder = Builder::from_ssa(parse_ir(src).unwrap());
+    assert_eq!(dce(&mut builder), [5..9, 12..16]);
+
+    // dividing by zero traps, so it stays even though nothing uses it
+    let (body, ..) = builder.get_ssa();
+    let expected = "
+        bits 8
+        $0: // inst_0 [0: 0..4]
+            %0 = 1
+            %4 = 0
+            %5 = div %0 %4
+            jmp $1
+        $1: // inst_2 [0: 17..21]
+            ret
+    ";
+
+    let lines = |s: &str| s.lines().map(str::trim).filter(|l| !l.is_empty()).collect::<Vec<_>>().join("\n");
+    assert_eq!(lines(&body.to_string()), lines(expected));
+}
+
+#[test]
+fn gvn_forwards_stores_until_memory_changes() {
+    let src = "
+        bits 8
+        $0: // entry
+            %0 = 0
+            %1 = 16
+            alloc #0 %1
+            %2 = call PortRead(%0)
+            %3 = call PortRead(%0)
+            %4 = add %2 %3
+            store #0[%2] %4
+            %5 = load #0[%2]
+            %6 = add %3 %2
+            store #0[%3] %6
+            %7 = load #0[%2]
+            %8 = 16
+            call PortWrite(%5, %7)
+            call PortWrite(%8, %6)
+            ret
+    ";
+
+    let mut builder = Builder::from_ssa(parse_ir(src).unwrap());
+    gvn(&mut builder);
+    let (body, ..) = builder.get_ssa();
+
+    // the second store may have written over %2, so the load after it has to stay
+    let expected = "
+        bits 8
+        $0: // entry
+            %0 = 0
+            %1 = 16
+            alloc #0 %1
+            %2 = call PortRead(%0)
+            %3 = call PortRead(%0)
+            %4 = add %2 %3
+            store #0[%2] %4
+            store #0[%3] %4
+            %7 = load #0[%2]
+            call PortWrite(%4, %7)
+            call PortWrite(%1, %4)
+            ret
+    ";
+
+    let lines = |s: &str| s.lines().map(str::trim).filter(|l| !l.is_empty()).collect::<Vec<_>>().join("\n");
+    assert_eq!(lines(&body.to_string()), lines(expected));
+}
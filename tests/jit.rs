@@ -0,0 +1,132 @@
+#![cfg(all(target_arch = "x86_64", target_os = "linux"))]
+
+mod common;
+
+use common::*;
+use urcl_io::compiler::{
+    backend::{arch::{interpreter::Interpreter, x86_64::jit::Jit}, ir::parse_ir, opt::*},
+    engine::*,
+    error::InterpreterError,
+};
+
+/// Programs on top of `PROGRAMS` for the word sizes the JIT handles differently
+const WORDS: &[(&str, &str, &[u8])] = &[
+    ("64 bits", "
+        bits 64
+        imm r1 0xffffffffffffffff
+        add r2 r1 2
+        out 2 r2
+        out 1 32
+        rsh r3 r1
+        out 2 r3
+        out 1 32
+        nor r4 r3 0
+        out 2 r4
+        out 1 32
+        bge .big r1 0x8000000000000000
+        out 2 0
+    .big
+        in r5 1
+        out 2 r5
+    ", b"z"),
+    ("64 bits out of bounds", "
+        bits 64
+        minheap 2
+        out 2 1
+        lod r1 0xfffffffffffffff0
+        out 2 2
+    ", b""),
+    ("16 bits out of bounds", "
+        bits 16
+        minheap 2
+        minstack 0
+        imm r1 65535
+        str r1 3
+        out 2 3
+    ", b""),
+    ("8 bits unsupported port", "
+        bits 8
+        out 2 200
+        in r1 9
+    ", b""),
+];
+
+#[test]
+fn jit_matches_the_ast_interpreter() {
+    for (name, src, input) in PROGRAMS.iter().chain(WORDS) {
+        let expected = run(EngineKind::Ast, OptLevel::O0, src, input);
+
+        for level in [OptLevel::O0, OptLevel::O2] {
+            assert_eq!(run(EngineKind::Jit, level, src, input), expected, "{name} at {level:?}");
+        }
+    }
+}
+
+#[test]
+fn jit_stops_where_the_budget_runs_out() {
+    for (name, src, input) in PROGRAMS.iter().chain(WORDS) {
+        let expected = run(EngineKind::Ast, OptLevel::O0, src, input);
+
+        for level in [OptLevel::O0, OptLevel::O2] {
+            for budget in [1, 3, 7] {
+                let engines = [EngineKind::Ssa, EngineKind::Jit].map(|kind| {
+                    let stdout = Buffer::default();
+                    let mut engine = create_engine(kind, &mut PassManager::new(level), parse_urcl(src));
+                    engine.attach_device(Box::new(StdioDevice { stdout: stdout.clone(), stdin: std::io::Cursor::new(input.to_vec()) }));
+                    (engine, stdout)
+                });
+                let [(mut ssa, ssa_out), (mut jit, jit_out)] = engines;
+                let at = format!("{name} at {level:?} with budget {budget}");
+
+                // both run the same IR, so they stop on the same block with the same state
+                let error = loop {
+                    let (expected, got) = (ssa.run(budget), jit.run(budget));
+                    assert_eq!(jit.stats().inst_count, ssa.stats().inst_count, "{at}");
+                    assert_eq!(*jit_out.0.borrow(), *ssa_out.0.borrow(), "{at}");
+
+                    match (expected, got) {
+                        (StepResult::Running, StepResult::Running) => {
+                            for i in 0..8 {
+                                assert_eq!(jit.register(i + 1).ok(), ssa.register(i + 1).ok(), "r{} of {at}", i + 1);
+                                assert_eq!(jit.memory(i as u128).ok(), ssa.memory(i as u128).ok(), "M{i} of {at}");
+                            }
+                        },
+                        (StepResult::Halted, StepResult::Halted) => break None,
+                        (StepResult::Error(expected), StepResult::Error(got)) => {
+                            assert_eq!(got.kind.code(), expected.kind.code(), "{at}");
+                            break Some(got.kind.code());
+                        },
+                        (expected, got) => panic!("{at}: {got:?} instead of {expected:?}"),
+                    }
+                };
+
+                let stdout = String::from_utf8_lossy(&jit_out.0.borrow()).into_owned();
+                assert_eq!(Outcome { stdout, error }, expected, "{at}");
+            }
+        }
+    }
+}
+
+#[test]
+fn jit_reports_division_by_zero() {
+    // starts out as IR so that the division traps by itself, without the check `DIV` comes with
+    let src = "
+        bits 64
+        $0: // entry
+            %0 = 1
+            %1 = call PortRead(%0)
+            %2 = 2
+            %3 = 18446744073709551615
+            %4 = div %3 %1
+            call PortWrite(%2, %4)
+            ret
+    ";
+
+    for input in [&b"\x05"[..], b""] {
+        let expected = run_engine(Box::new(Interpreter::<u64>::new(parse_ir(src).unwrap())), input);
+        assert_eq!(run_engine(Box::new(Jit::new(parse_ir(src).unwrap())), input), expected);
+    }
+
+    let ended = run_engine(Box::new(Jit::new(parse_ir(src).unwrap())), b"");
+    assert_eq!(ended.error, Some(InterpreterError::DivisionByZero.code()));
+}
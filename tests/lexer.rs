@@ -0,0 +1,15 @@
+use urcl_io::compiler::frontend::lexer::*;
+
+#[test]
+fn numbers_wider_than_128_bits_are_lexer_errors() {
+    for src in ["0x100000000000000000000000000000000", "-340282366920938463463374607431768211456", "r340282366920938463463374607431768211456"] {
+        let tokens: Vec<_> = Token::lexer(src).collect();
+        assert!(matches!(tokens[..], [Err(())]), "{src}: {tokens:?}");
+    }
+}
+
+#[test]
+fn numbers_up_to_128_bits_wrap_around() {
+    let tokens: Vec<_> = Token::lexer("340282366920938463463374607431768211455 -0xff").collect();
+    assert!(matches!(tokens[..], [Ok(Token::Number(-1)), Ok(Token::Number(-255))]), "{tokens:?}");
+}
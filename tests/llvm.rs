@@ -0,0 +1,146 @@
+mod common;
+
+use common::*;
+use urcl_io::compiler::{
+    backend::{arch::{interpreter::Interpreter, llvm::to_llvm}, builder::Builder, codegen::generate_ssa, ir::parse_ir, opt::*},
+    engine::EngineKind,
+    error::InterpreterError,
+};
+use std::{io::Write, path::PathBuf, process::{Command, Stdio}};
+
+/// The runtime the module docs of `llvm` ask for, on stdin and stdout like the CLI
+const RUNTIME: &str = r#"
+#include <stdio.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+static int last_ok = 1;
+
+uint64_t urcl_port_read(uint64_t port) {
+    int c;
+    last_ok = port == 1;
+    if (!last_ok) return 0;
+    c = getchar();
+    return c == EOF ? 0 : (uint64_t) c;
+}
+
+static void put_char(uint64_t c) {
+    if (c >= 0x110000 || (c >= 0xd800 && c < 0xe000)) c = 0xfffd;
+    if (c < 0x80) {
+        putchar((int) c);
+    } else if (c < 0x800) {
+        putchar((int) (0xc0 | c >> 6));
+        putchar((int) (0x80 | (c & 0x3f)));
+    } else if (c < 0x10000) {
+        putchar((int) (0xe0 | c >> 12));
+        putchar((int) (0x80 | (c >> 6 & 0x3f)));
+        putchar((int) (0x80 | (c & 0x3f)));
+    } else {
+        putchar((int) (0xf0 | c >> 18));
+        putchar((int) (0x80 | (c >> 12 & 0x3f)));
+        putchar((int) (0x80 | (c >> 6 & 0x3f)));
+        putchar((int) (0x80 | (c & 0x3f)));
+    }
+}
+
+void urcl_port_write(uint64_t port, uint64_t data) {
+    last_ok = port == 1 || port == 2;
+    if (port == 1) put_char(data);
+    else if (port == 2) printf("%llu", (unsigned long long) data);
+}
+
+int urcl_last_ok(void) {
+    return last_ok;
+}
+
+void urcl_report_error(uint64_t kind, uint64_t pc, uint64_t detail) {
+    fflush(stdout);
+    fprintf(stderr, "error %llu at %llu: %llu\n", (unsigned long long) kind, (unsigned long long) pc, (unsigned long long) detail);
+    exit(3);
+}
+"#;
+
+/// Builds the module with `llc`, links it with [`RUNTIME`] and runs it, giving `None` when LLVM
+/// or a C compiler isn't there
+fn run_llvm(name: &str, ll: &str, input: &[u8]) -> Option<(String, Option<i32>)> {
+    let dir = std::env::temp_dir().join(format!("urcl-llvm-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let stem = name.replace(' ', "_");
+    let (module, object, runtime, binary): (PathBuf, PathBuf, PathBuf, PathBuf) =
+        (dir.join(format!("{stem}.ll")), dir.join(format!("{stem}.o")), dir.join(format!("{stem}.c")), dir.join(&stem));
+    std::fs::write(&module, ll).unwrap();
+    std::fs::write(&runtime, RUNTIME).unwrap();
+
+    let llc = Command::new("llc").arg("-filetype=obj").arg("-relocation-model=pic").arg("-o").arg(&object).arg(&module).output().ok()?;
+    assert!(llc.status.success(), "{name} doesn't compile:\n{}\n{ll}", String::from_utf8_lossy(&llc.stderr));
+    let cc = Command::new("cc").arg("-o").arg(&binary).arg(&runtime).arg(&object).output().ok()?;
+    assert!(cc.status.success(), "{name} doesn't link:\n{}", String::from_utf8_lossy(&cc.stderr));
+
+    let mut child = Command::new(&binary)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    let out = child.wait_with_output().unwrap();
+    for file in [module, object, runtime, binary] {
+        std::fs::remove_file(file).unwrap();
+    }
+
+    Some((String::from_utf8_lossy(&out.stdout).into_owned(), out.status.code()))
+}
+
+/// Exit code of the linked program for what an engine ended with
+fn exit_code(outcome: &Outcome) -> Option<i32> {
+    Some(if outcome.error.is_some() { 3 } else { 0 })
+}
+
+#[test]
+fn llvm_matches_the_ast_interpreter() {
+    for (name, src, input) in PROGRAMS {
+        let expected = run(EngineKind::Ast, OptLevel::O0, src, input);
+
+        for level in [OptLevel::O0, OptLevel::O2] {
+            let mut builder = Builder::from_ssa(generate_ssa(parse_urcl(src)));
+            PassManager::new(level).run(&mut builder);
+
+            let Some((stdout, code)) = run_llvm(&format!("{name} {level:?}"), &to_llvm(&builder.get_ssa()), input) else {
+                eprintln!("skipped, there is no llc or C compiler");
+                return;
+            };
+
+            assert_eq!(stdout, expected.stdout, "{name} at {level:?}");
+            assert_eq!(code, exit_code(&expected), "{name} at {level:?}");
+        }
+    }
+}
+
+#[test]
+fn llvm_reports_division_by_zero() {
+    // starts out as IR so that the division traps by itself, without the check `DIV` comes with
+    let src = "
+        bits 16
+        $0: // entry
+            %0 = 1
+            %1 = call PortRead(%0)
+            %2 = 2
+            %3 = 700
+            %4 = div %3 %1
+            call PortWrite(%2, %4)
+            ret
+    ";
+
+    for (input, stdout, error) in [(&b"\x07"[..], "100", None), (b"", "", Some(InterpreterError::DivisionByZero.code()))] {
+        let expected = run_engine(Box::new(Interpreter::<u64>::new(parse_ir(src).unwrap())), input);
+        assert_eq!((expected.stdout.as_str(), expected.error), (stdout, error));
+
+        let Some((out, code)) = run_llvm("division", &to_llvm(&parse_ir(src).unwrap()), input) else {
+            eprintln!("skipped, there is no llc or C compiler");
+            return;
+        };
+
+        assert_eq!(out, expected.stdout, "{input:?}");
+        assert_eq!(code, exit_code(&expected), "{input:?}");
+    }
+}
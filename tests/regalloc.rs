@@ -0,0 +1,70 @@
+use urcl_io::compiler::backend::{ir::parse_ir, regalloc::*};
+
+/// Does `moves` one after another on `regs`
+fn apply(moves: &[(usize, usize)], mut regs: Vec<u32>) -> Vec<u32> {
+    for (dst, src) in moves.iter() {
+        regs[*dst] = regs[*src];
+    }
+
+    regs
+}
+
+#[test]
+fn sequentialise_breaks_cycles_through_the_temporary() {
+    // the last register is the temporary
+    let regs = vec![10, 11, 12, 13, 0];
+    let cases = [
+        // a swap
+        (vec![(0, 1), (1, 0)], [11, 10, 12, 13], 3),
+        // a 3-cycle
+        (vec![(0, 1), (1, 2), (2, 0)], [11, 12, 10, 13], 4),
+        // a 3-cycle which is also copied out of, which has to happen before it gets overwritten
+        (vec![(0, 1), (3, 0), (1, 2), (2, 0)], [11, 12, 10, 10], 5),
+        // copies into themselves go away
+        (vec![(1, 1), (3, 2)], [10, 11, 12, 12], 1),
+    ];
+
+    for (copies, expected, count) in cases {
+        let moves = sequentialise(copies.clone(), 4);
+        assert_eq!(apply(&moves, regs.clone())[..4], expected, "{copies:?} as {moves:?}");
+        assert_eq!(moves.len(), count, "{copies:?} as {moves:?}");
+    }
+}
+
+#[test]
+fn allocate_spills_what_doesnt_fit_into_the_registers() {
+    // %0 to %3 are all live before the first add
+    let (body, values, _) = parse_ir("
+        bits 8
+        $0: // entry
+            %0 = 1
+            %1 = 2
+            %2 = 3
+            %3 = 4
+            %4 = add %0 %1
+            %5 = add %2 %3
+            %6 = add %4 %5
+            call PortWrite(%0, %6)
+            ret
+    ").unwrap();
+
+    // two registers are left once the middle one is reserved
+    let target = Target { registers: 3, reserved: vec![1] };
+    let alloc = allocate(&body, values, &target);
+
+    let locations: Vec<Location> = alloc.locations.iter().map(|l| l.expect("every value is used")).collect();
+    assert!(!locations.contains(&Location::Register(1)), "{locations:?}");
+
+    let live = &locations[..4];
+    for (i, a) in live.iter().enumerate() {
+        assert!(!live[i + 1..].contains(a), "{locations:?}");
+    }
+
+    let spilled = live.iter().filter(|l| matches!(l, Location::Stack(_))).count();
+    assert_eq!(spilled, 2, "{locations:?}");
+    assert_eq!(alloc.stack, 2, "{locations:?}");
+    assert!(locations.iter().all(|l| match l {
+        Location::Register(r) => target.allocatable().any(|a| a == *r),
+        Location::Stack(s) => *s < alloc.stack,
+    }), "{locations:?}");
+}
@@ -0,0 +1,20 @@
+use urcl_io::compiler::backend::{builder::Builder, ssa::*};
+
+#[test]
+fn verify_reports_branches_to_missing_blocks() {
+    let mut builder = Builder::default();
+    let entry = builder.append_block("entry", None);
+    let exit = builder.append_block("exit", None);
+    let (mut five, mut seven) = (exit, exit);
+    (*five, *seven) = (5, 7);
+
+    let one = builder.allocate_value();
+    builder.append_instruction(entry, Instruction { operation: Operation::Integer(1), destination: Some(one) });
+    builder.set_terminator(entry, Terminator::Branch(one, exit, five));
+    builder.set_terminator(exit, Terminator::Jump(seven));
+
+    let (body, ..) = builder.get_ssa();
+    let errors = verify(&body).unwrap_err();
+    let messages: Vec<_> = errors.iter().map(|e| e.message.as_str()).collect();
+    assert_eq!(messages, ["branches to $5, which does not exist", "branches to $7, which does not exist"]);
+}
@@ -0,0 +1,156 @@
+mod common;
+
+use common::*;
+use urcl_io::compiler::{
+    backend::{arch::urcl::{to_urcl, Origin}, builder::Builder, codegen::generate_ssa, ir::parse_ir, opt::*},
+    common::Instruction,
+    engine::*,
+    error::{Error, UrclError},
+};
+
+#[test]
+fn hoisted_constants_are_copied_without_a_comment() {
+    let src = "bits 32\nimm r1 0\nimm r2 100\n.loop\nadd r1 r1 r2\nadd r2 r2 -1\nbge .loop r2 1\nout 2 r1\n";
+    let ast = parse_urcl(src);
+    let (labels, minreg) = (ast.labels.clone(), ast.minreg);
+
+    let mut builder = Builder::from_ssa(generate_ssa(ast));
+    PassManager::new(OptLevel::O2).run(&mut builder);
+    let urcl = to_urcl(builder.get_ssa(), &Origin { source: src, labels: &labels, minreg }).unwrap();
+
+    let lines: Vec<_> = urcl.lines().map(str::trim_end).collect();
+    assert!(lines.contains(&"    imm r1 0"), "{urcl}");
+    assert!(lines.contains(&"    imm r2 100"), "{urcl}");
+    assert!(lines.contains(&"    add r1 r1 r2            // 5: add r1 r1 r2"), "{urcl}");
+}
+
+/// Puts `src` through the passes of `level` and back into URCL
+fn emit(src: &str, level: OptLevel) -> String {
+    let ast = parse_urcl(src);
+    let (labels, minreg) = (ast.labels.clone(), ast.minreg);
+
+    let mut builder = Builder::from_ssa(generate_ssa(ast));
+    PassManager::new(level).run(&mut builder);
+    to_urcl(builder.get_ssa(), &Origin { source: src, labels: &labels, minreg }).unwrap()
+}
+
+/// Runs `src` on the AST engine, giving whether it ended with an error and how many cycles it took
+fn cycles(src: &str, input: &[u8]) -> (String, bool, usize) {
+    let stdout = Buffer::default();
+    let mut engine = create_engine(EngineKind::Ast, &mut PassManager::new(OptLevel::O0), parse_urcl(src));
+    engine.attach_device(Box::new(StdioDevice { stdout: stdout.clone(), stdin: std::io::Cursor::new(input.to_vec()) }));
+
+    let failed = loop {
+        match engine.run(usize::MAX) {
+            StepResult::Running => {},
+            StepResult::Halted => break false,
+            StepResult::Error(_) => break true,
+        }
+    };
+
+    let count = engine.stats().inst_count;
+    drop(engine);
+    let stdout = String::from_utf8_lossy(&stdout.0.borrow()).into_owned();
+    (stdout, failed, count)
+}
+
+#[test]
+fn output_takes_no_more_cycles_than_the_source() {
+    // the bounds checks of the load and store share one comparison after GVN
+    let table = ("table", "
+        bits 8
+        minheap 8
+        dw 3
+        dw 1
+        dw 4
+        dw 1
+        imm r1 0
+        imm r2 0
+    .loop
+        lod r3 r1
+        add r2 r2 r3
+        str r1 r2
+        add r1 r1 1
+        bge .done r1 4
+        bge .loop 0 0
+    .done
+        lod r3 3
+        out 2 r3
+    ", &b""[..]);
+
+    for (name, src, input) in PROGRAMS.iter().copied().chain([table]) {
+        let (stdout, failed, expected) = cycles(src, input);
+        // the output has to loop for instructions like `MLT`, so only the rest are held to it
+        let bounded = parse_urcl(src).instructions.iter().all(|(inst, _)| matches!(inst,
+            Instruction::ADD(..) | Instruction::RSH(..) | Instruction::LOD(..) | Instruction::STR(..) | Instruction::BGE(..)
+                | Instruction::NOR(..) | Instruction::IMM(..) | Instruction::MOV(..) | Instruction::IN(..) | Instruction::OUT(..)
+        ));
+
+        for level in [OptLevel::O0, OptLevel::O2, OptLevel::O3] {
+            let urcl = emit(src, level);
+            let (out, fails, took) = cycles(&urcl, input);
+            assert_eq!((out.as_str(), fails), (stdout.as_str(), failed), "{name} at {level:?}:\n{urcl}");
+            assert!(!bounded || took <= expected, "{name} at {level:?} takes {took} cycles instead of {expected}:\n{urcl}");
+        }
+    }
+}
+
+#[test]
+fn errors_write_to_the_special_port_and_halt() {
+    let src = "
+        bits 8
+        $0: // entry
+            %0 = 1
+            %1 = call PortRead(%0)
+            %2 = 5
+            %3 = 9
+            br %1 $1 $2
+        $1: // fails
+            call ReportError(%2, %3, %1)
+            trap
+        $2: // done
+            call PortWrite(%0, %0)
+            ret
+    ";
+
+    let urcl = to_urcl(parse_ir(src).unwrap(), &Origin { source: "", labels: &[], minreg: 8 }).unwrap();
+    let lines: Vec<_> = urcl.lines().map(str::trim).collect();
+    let at = lines.iter().position(|l| *l == "out 6 5").expect(&urcl);
+    assert_eq!(lines[at + 1], "out 6 r1", "{urcl}");
+    assert!(lines[at + 2].starts_with("bge .halt") && lines[at + 2].ends_with(" r0 r0"), "{urcl}");
+
+    for (input, stdout, failed) in [(&b"\x00"[..], "\u{1}", false), (b"\x07", "", true)] {
+        let (out, fails, _) = cycles(&urcl, input);
+        assert_eq!((out.as_str(), fails), (stdout, failed), "{input:?}");
+    }
+}
+
+#[test]
+fn memory_urcl_has_no_way_to_do_is_an_error() {
+    let origin = Origin { source: "", labels: &[], minreg: 8 };
+    let resized = "
+        bits 8
+        $0: // entry
+            %0 = 1
+            %1 = call PortRead(%0)
+            alloc #0 %1
+            ret
+    ";
+    assert!(matches!(to_urcl(parse_ir(resized).unwrap(), &origin), Err(Error { kind: UrclError::RamResized, .. })));
+
+    // indexed with a value that isn't known up front, so it can't be promoted
+    let other = "
+        bits 8
+        $0: // entry
+            %0 = 1
+            %1 = 4
+            alloc #0 %1
+            alloc #1 %1
+            %2 = call PortRead(%0)
+            store #1[%2] %0
+            %3 = load #1[%0]
+            call PortWrite(%0, %3)
+            ret
+    ";
+    assert!(matches!(to_urcl(parse_ir(other).unwrap(), &origin), Err(Error { kind: UrclError::UnpromotedVariable(1), .. })));
+}
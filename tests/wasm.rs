@@ -0,0 +1,60 @@
+mod common;
+
+use common::*;
+use urcl_io::compiler::backend::{arch::wasm::to_wasm, builder::Builder, codegen::generate_ssa, opt::*};
+use wasmparser::{ExternalKind, FuncType, Parser, Payload, TypeRef, ValType, Validator};
+
+#[test]
+fn modules_are_valid_and_laid_out_as_documented() {
+    for (name, src, _) in PROGRAMS {
+        for level in [OptLevel::O0, OptLevel::O2] {
+            let mut builder = Builder::from_ssa(generate_ssa(parse_urcl(src)));
+            PassManager::new(level).run(&mut builder);
+            let module = to_wasm(&builder.get_ssa());
+
+            assert_eq!(&module[..8], b"\0asm\x01\0\0\0", "{name} at {level:?}");
+            if let Err(err) = Validator::new().validate_all(&module) {
+                panic!("{name} at {level:?} doesn't validate: {err}");
+            }
+
+            let mut sections = Vec::new();
+            let mut types = Vec::new();
+            let mut imports = Vec::new();
+            let mut exports = Vec::new();
+            for payload in Parser::new(0).parse_all(&module) {
+                let payload = payload.unwrap();
+                if let Some((id, _)) = payload.as_section() {
+                    sections.push(id);
+                }
+
+                match payload {
+                    Payload::TypeSection(r) => types = r.into_iter_err_on_gc_types().collect::<Result<Vec<_>, _>>().unwrap(),
+                    Payload::ImportSection(r) => for import in r.into_imports() {
+                        let import = import.unwrap();
+                        let TypeRef::Func(ty) = import.ty else { panic!("{name}: {} isn't a function", import.name) };
+                        imports.push((import.module.to_string(), import.name.to_string(), types[ty as usize].clone()));
+                    },
+                    Payload::ExportSection(r) => for export in r {
+                        let export = export.unwrap();
+                        exports.push((export.name.to_string(), export.kind));
+                    },
+                    _ => {},
+                }
+            }
+
+            // type, import, function, memory, global, export, code
+            assert_eq!(sections, [1, 2, 3, 5, 6, 7, 10], "{name} at {level:?}");
+
+            let func = |params: &[ValType], results: &[ValType]| FuncType::new(params.iter().copied(), results.iter().copied());
+            let env = |field: &str, ty: FuncType| ("env".to_string(), field.to_string(), ty);
+            assert_eq!(imports, [
+                env("port_read", func(&[ValType::I64], &[ValType::I64])),
+                env("port_write", func(&[ValType::I64, ValType::I64], &[])),
+                env("last_ok", func(&[], &[ValType::I32])),
+                env("report_error", func(&[ValType::I64, ValType::I64, ValType::I64], &[])),
+            ], "{name} at {level:?}");
+
+            assert_eq!(exports, [("memory".to_string(), ExternalKind::Memory), ("main".to_string(), ExternalKind::Func)], "{name} at {level:?}");
+        }
+    }
+}